@@ -1,15 +1,67 @@
-use crate::constants;
-use clap::{App, Arg, ArgMatches};
-use core::panic;
+use crate::{
+    config::{self, ConfigFile, Settings},
+    constants,
+};
+use anyhow::{anyhow, bail};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use od_get::download::{
+    auth::{self, Credentials},
+    crawl::{CrawlLimits, ListingFormat},
+    export::ExportFormat,
+    scope::RedirectPolicy,
+    stats::Selection,
+    storage::{S3Config, S3Sink, StdoutSink, StorageSink, TarSink, ZipSink},
+};
+use od_get::{OdGet, OdGetBuilder};
 use regex::Regex;
 use reqwest::Url;
+use std::{
+    env, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+/// The kind of archive the files get written into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// The file extension of the archive (e.g. for the archives of a batch)
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tar" => Ok(Self::Tar),
+            "zip" => Ok(Self::Zip),
+            other => bail!(
+                "Invalid archive format \"{}\" (must be `tar` or `zip`)",
+                other
+            ),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct CliOptions {
     pub url: Url,
     pub destination: String,
+    pub archive: Option<ArchiveFormat>,
+    pub s3_endpoint: Option<String>,
     pub no_download: bool,
-    pub verbosity: u64,
     pub limit_count: Option<u64>,
     pub skip_count: Option<u64>,
     pub recursion_limit: Option<u64>,
@@ -18,9 +70,133 @@ pub struct CliOptions {
     pub file_matcher: Option<Regex>,
     pub path_matcher: Option<Regex>,
     pub state_store_path: Option<String>,
+    pub retry_failed: bool,
+    pub credentials: Option<Credentials>,
+    pub no_parent: bool,
+    pub allowed_hosts: Vec<String>,
+    pub redirect_policy: RedirectPolicy,
+    pub crawl_limits: CrawlLimits,
+    pub listing_format: ListingFormat,
+    pub progress: bool,
+}
+
+impl CliOptions {
+    /// Configures the crawl & download of the root URL (without the shared client, limiter & reporter)
+    pub fn builder(&self) -> OdGetBuilder {
+        let mut builder = OdGet::builder()
+            .url(self.url.as_str())
+            .destination(&self.destination)
+            .selection(Selection {
+                file_filter: self.file_filter.clone(),
+                file_matcher: self.file_matcher.clone(),
+                path_filter: self.path_filter.clone(),
+                path_matcher: self.path_matcher.clone(),
+            })
+            .crawl_limits(self.crawl_limits)
+            .listing_format(self.listing_format)
+            .no_parent(self.no_parent)
+            .allowed_hosts(self.allowed_hosts.clone())
+            .redirect_policy(self.redirect_policy);
+
+        if let Some(limit) = self.limit_count {
+            builder = builder.limit(limit);
+        }
+        if let Some(skip) = self.skip_count {
+            builder = builder.skip(skip);
+        }
+        if let Some(recursion_limit) = self.recursion_limit {
+            builder = builder.recursion_limit(recursion_limit);
+        }
+        if let Some(credentials) = self.credentials.clone() {
+            builder = builder.credentials(credentials);
+        }
+
+        builder
+    }
+
+    /**
+    Makes the storage for the destination, unless it's a local directory.
+
+    `-` writes to stdout (a single file, or a tar stream), and `s3://bucket/prefix`
+    uploads to an object store (configured by the usual `AWS_*` environment variables).
+    */
+    pub fn storage(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<Option<Arc<dyn StorageSink>>, anyhow::Error> {
+        if let Some(location) = self.destination.strip_prefix("s3://") {
+            if self.archive.is_some() {
+                bail!("Archives cannot be uploaded to an object store");
+            }
+
+            return Ok(Some(Arc::new(S3Sink::new(
+                client.clone(),
+                self.s3_config(location)?,
+            ))));
+        }
+
+        let storage: Arc<dyn StorageSink> = match (self.archive, self.destination.as_str()) {
+            (None, "-") => Arc::new(StdoutSink::new()),
+            (None, _) => return Ok(None),
+            (Some(ArchiveFormat::Tar), "-") => Arc::new(TarSink::tar(io::stdout())),
+            (Some(ArchiveFormat::Zip), "-") => {
+                bail!("Zip archives cannot be written to stdout (use `--archive tar` instead)")
+            }
+            (Some(format), path) => {
+                // Make sure the directory of the archive exists
+                if let Some(parent) = Path::new(path).parent() {
+                    fs::create_dir_all(parent)?;
+                }
+
+                match format {
+                    ArchiveFormat::Tar => Arc::new(TarSink::tar_file(Path::new(path))?),
+                    ArchiveFormat::Zip => Arc::new(ZipSink::zip_file(Path::new(path))?),
+                }
+            }
+        };
+
+        Ok(Some(storage))
+    }
+
+    /// Configures the object store of an `s3://` destination (e.g. `bucket/mirror`)
+    fn s3_config(&self, location: &str) -> Result<S3Config, anyhow::Error> {
+        let (bucket, prefix) = match location.split_once('/') {
+            Some((bucket, prefix)) => (bucket, prefix.trim_matches('/')),
+            None => (location, ""),
+        };
+        if bucket.is_empty() {
+            bail!("No bucket specified in destination {}", self.destination);
+        }
+
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+        let region = var("AWS_REGION")
+            .or_else(|| var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|| "us-east-1".to_owned());
+        let endpoint = self
+            .s3_endpoint
+            .clone()
+            .or_else(|| var("AWS_ENDPOINT_URL"))
+            .unwrap_or_else(|| format!("https://s3.{}.amazonaws.com", region));
+
+        Ok(S3Config {
+            endpoint: Url::parse(&endpoint)
+                .map_err(|err| anyhow!("Invalid S3 endpoint {}: {}", endpoint, err))?,
+            region,
+            bucket: bucket.to_owned(),
+            prefix: match prefix {
+                "" => String::new(),
+                prefix => format!("{}/", prefix),
+            },
+            access_key: var("AWS_ACCESS_KEY_ID")
+                .ok_or_else(|| anyhow!("AWS_ACCESS_KEY_ID is required for s3:// destinations"))?,
+            secret_key: var("AWS_SECRET_ACCESS_KEY").ok_or_else(|| {
+                anyhow!("AWS_SECRET_ACCESS_KEY is required for s3:// destinations")
+            })?,
+        })
+    }
 }
 
-pub fn configure_parser(default_path: &str) -> App {
+pub fn configure_parser(default_path: &str) -> App<'_, '_> {
     let app = App::new(constants::NAME)
         .version(constants::VERSION)
         .author(constants::AUTHOR)
@@ -28,23 +204,39 @@ pub fn configure_parser(default_path: &str) -> App {
         .after_help(constants::LICENSE)
         .args(&[
             Arg::with_name("URL")
-                .help("The root URL you want to crawl & download")
-                .required(true)
+                .help("The root URL you want to crawl & download (HTTP(S) or FTP(S), may be set by a profile)")
                 .index(1),
             Arg::with_name("destination")
-                .help("The path to which to write the downloaded files to")
+                .help("The path to which to write the downloaded files to (`-` for stdout, `s3://bucket/prefix` for an object store)")
                 .default_value(default_path)
                 .short("d")
                 .long("destination")
                 .value_name("path"),
+            Arg::with_name("archive")
+                .takes_value(true)
+                .possible_values(&["tar", "zip"])
+                .help("Write the files into an archive at the destination (`-` for a tar stream on stdout)")
+                .long("archive")
+                .value_name("format"),
+            Arg::with_name("s3_endpoint")
+                .takes_value(true)
+                .help("The endpoint of the object store for s3:// destinations [default: $AWS_ENDPOINT_URL or AWS]")
+                .long("s3-endpoint")
+                .value_name("url"),
             Arg::with_name("disable download")
                 .help("Crawls without downloading (you mut also use -S)")
                 .short("n")
                 .long("no-download"),
             Arg::with_name("verbosity")
                 .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("Log more details (-v for debug, -vv for trace messages)"),
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
                 .multiple(true)
-                .help("The verbosity level of the application"),
+                .help("Log less (-q for warnings & errors, -qq for errors only, -qqq for nothing)"),
             Arg::with_name("limit")
                 .help("Limit to n finding(s) to be downloaded")
                 .short("l")
@@ -93,40 +285,506 @@ pub fn configure_parser(default_path: &str) -> App {
                 .short("S")
                 .long("store-state")
                 .value_name("path"),
-        ]);
+            Arg::with_name("retry_failed")
+                .help("Retry the files which failed in earlier runs (according to the state store)")
+                .long("retry-failed"),
+            Arg::with_name("user")
+                .takes_value(true)
+                .help("The username to authenticate with (only sent to the root host)")
+                .short("u")
+                .long("user")
+                .value_name("user"),
+            Arg::with_name("password")
+                .takes_value(true)
+                .help("The password to authenticate with (only sent to the root host)")
+                .long("password")
+                .value_name("password"),
+            Arg::with_name("netrc")
+                .takes_value(true)
+                .help("Look up credentials in this netrc file [default: ~/.netrc]")
+                .long("netrc-file")
+                .value_name("path"),
+            Arg::with_name("input_file")
+                .takes_value(true)
+                .help("Read root URLs from a file (one per line, `-` for stdin)")
+                .short("i")
+                .long("input-file")
+                .value_name("path"),
+            Arg::with_name("rate_limit")
+                .help("Maximum number of requests per second, shared by all roots (0 is unlimited)")
+                .long("rate-limit")
+                .value_name("integer")
+                .default_value("0"),
+            Arg::with_name("no_parent")
+                .help("Never ascend above the root directory (the default)")
+                .long("no-parent")
+                .overrides_with("allow_parent"),
+            Arg::with_name("allow_parent")
+                .help("Also follow links above the root directory (on the root host)")
+                .long("allow-parent")
+                .overrides_with("no_parent"),
+            Arg::with_name("allow_host")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Also follow links to this host (may be specified multiple times)")
+                .long("allow-host")
+                .value_name("host"),
+            Arg::with_name("redirects")
+                .takes_value(true)
+                .possible_values(&["follow", "refuse"])
+                .help("Whether to follow redirects to URLs outside of the scope")
+                .long("redirects")
+                .value_name("policy")
+                .default_value("refuse"),
+            Arg::with_name("listing")
+                .takes_value(true)
+                .possible_values(&["html", "webdav", "s3"])
+                .help("How to list the directories of HTTP(S) servers (HTML pages, WebDAV PROPFIND or S3 bucket listings)")
+                .long("listing")
+                .value_name("format")
+                .default_value("html"),
+            Arg::with_name("max_directories")
+                .help("Stop crawling after n directories (0 is unlimited)")
+                .long("max-directories")
+                .value_name("integer")
+                .default_value("100000"),
+            Arg::with_name("max_url_length")
+                .help("Don't crawl directories with longer URLs (0 is unlimited)")
+                .long("max-url-length")
+                .value_name("integer")
+                .default_value("2048"),
+            Arg::with_name("no_progress")
+                .help("Don't display the progress (bars on a terminal, periodic lines otherwise)")
+                .long("no-progress"),
+            Arg::with_name("log_file")
+                .takes_value(true)
+                .help("Also append the log messages to this file")
+                .long("log-file")
+                .value_name("path"),
+            Arg::with_name("log_format")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .help("Write the log as plain text or as one JSON event per line")
+                .long("log-format")
+                .value_name("format")
+                .default_value("text"),
+            Arg::with_name("report")
+                .takes_value(true)
+                .help("Write a summary of the run (counts, failures, bytes & hosts) to this JSON file")
+                .long("report")
+                .value_name("path"),
+            Arg::with_name("config")
+                .takes_value(true)
+                .help("Read settings from this config file [default: ~/.config/od-get/config.toml]")
+                .short("c")
+                .long("config")
+                .value_name("path")
+                .global(true),
+            Arg::with_name("profile")
+                .takes_value(true)
+                .help("Use the settings of a named profile from the config file")
+                .long("profile")
+                .value_name("name")
+                .global(true),
+        ])
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the configuration")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Print the effective configuration (config file, profile & flags)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Export the crawl of a state store (e.g. as input for wget or aria2c)")
+                .args(&[
+                    Arg::with_name("STATE_STORE")
+                        .help("The state store holding the crawl (written with -S)")
+                        .required(true)
+                        .index(1),
+                    Arg::with_name("format")
+                        .takes_value(true)
+                        .possible_values(&ExportFormat::NAMES)
+                        .help("The format to export to")
+                        .short("f")
+                        .long("format")
+                        .value_name("format")
+                        .default_value("urls"),
+                    Arg::with_name("output")
+                        .takes_value(true)
+                        .help("Write to this file instead of stdout")
+                        .short("o")
+                        .long("output")
+                        .value_name("path"),
+                    Arg::with_name("destination")
+                        .takes_value(true)
+                        .help("The download directory (for the `dir=` options of aria2c)")
+                        .short("d")
+                        .long("destination")
+                        .value_name("path")
+                        .default_value("."),
+                ]),
+        )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Migrate state stores written by older versions to the current format")
+                .arg(
+                    Arg::with_name("STATE_STORE")
+                        .help("The state stores to migrate (a backup of each is kept)")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Summarize the crawl of a state store (and estimate what the filters select)")
+                .args(&[
+                    Arg::with_name("STATE_STORE")
+                        .help("The state store holding the crawl (written with -S)")
+                        .required(true)
+                        .index(1),
+                    Arg::with_name("top")
+                        .takes_value(true)
+                        .help("The number of largest files to list")
+                        .long("top")
+                        .value_name("integer")
+                        .default_value("10"),
+                    Arg::with_name("json")
+                        .help("Print the statistics as JSON")
+                        .long("json"),
+                    Arg::with_name("file_filter")
+                        .takes_value(true)
+                        .help("Estimate without files matching this regex (default: from the profile)")
+                        .short("f")
+                        .long("file-filter")
+                        .value_name("regex"),
+                    Arg::with_name("path_filter")
+                        .takes_value(true)
+                        .help("Estimate without directories matching this regex")
+                        .short("p")
+                        .long("path-filter")
+                        .value_name("regex"),
+                    Arg::with_name("file_matcher")
+                        .takes_value(true)
+                        .help("Estimate with only the files matching this regex")
+                        .short("F")
+                        .long("file-matcher")
+                        .value_name("regex"),
+                    Arg::with_name("path_matcher")
+                        .takes_value(true)
+                        .help("Estimate with only the directories matching this regex")
+                        .short("P")
+                        .long("path-matcher")
+                        .value_name("regex"),
+                ]),
+        );
 
     app
 }
 
-pub fn get_options(matches: ArgMatches) -> Result<CliOptions, anyhow::Error> {
-    let make_regex = |name: &str| {
-        matches.value_of(name).and_then(|v| match Regex::new(v) {
-            Ok(regex) => Some(regex),
-            Err(err) => panic!("{}", &format!("{:?}", err)),
-        })
+/// Returns the effective settings (command line > profile > config file > defaults)
+pub fn get_settings(matches: &ArgMatches) -> Result<Settings, anyhow::Error> {
+    // Global arguments may also be specified after a sub-command
+    let global = |name: &str| {
+        matches
+            .subcommand()
+            .1
+            .and_then(|sub| sub.subcommand().1.or(Some(sub)))
+            .and_then(|sub| sub.value_of(name))
+            .or_else(|| matches.value_of(name))
+    };
+
+    // Use the specified config file, or the default one (if it exists)
+    let config_file = match global("config") {
+        Some(path) => ConfigFile::load(Path::new(path))?,
+        None => match config::default_config_path() {
+            Some(path) => ConfigFile::load(&path)?,
+            None => ConfigFile::default(),
+        },
+    };
+
+    Ok(explicit_settings(matches)?
+        .or(config_file.settings(global("profile"))?)
+        .or(default_settings(matches)?))
+}
+
+/// Extracts the settings which were explicitly specified on the command line
+fn explicit_settings(matches: &ArgMatches) -> Result<Settings, anyhow::Error> {
+    let value = |name: &str| {
+        if matches.occurrences_of(name) > 0 {
+            matches.value_of(name).map(|v| v.to_owned())
+        } else {
+            None
+        }
+    };
+    let number = |name: &str| value(name).map(|v| parse_number(&v)).transpose();
+
+    Ok(Settings {
+        url: value("URL"),
+        destination: value("destination"),
+        archive: value("archive"),
+        s3_endpoint: value("s3_endpoint"),
+        no_download: Some(true).filter(|_| matches.is_present("disable download")),
+        verbosity: Some(matches.occurrences_of("verbosity")).filter(|&v| v > 0),
+        quiet: Some(matches.occurrences_of("quiet")).filter(|&q| q > 0),
+        limit: number("limit")?,
+        skip: number("skip")?,
+        recursive_depth: number("max_depth")?,
+        file_filter: value("file_filter"),
+        path_filter: value("path_filter"),
+        file_matcher: value("file_matcher"),
+        path_matcher: value("path_matcher"),
+        store_state: value("state_store"),
+        retry_failed: Some(true).filter(|_| matches.is_present("retry_failed")),
+        user: value("user"),
+        password: value("password"),
+        netrc_file: value("netrc"),
+        input_file: value("input_file"),
+        rate_limit: number("rate_limit")?,
+        no_parent: if matches.is_present("allow_parent") {
+            Some(false)
+        } else if matches.is_present("no_parent") {
+            Some(true)
+        } else {
+            None
+        },
+        allow_hosts: matches
+            .values_of("allow_host")
+            .map(|hosts| hosts.map(|host| host.to_owned()).collect()),
+        redirects: value("redirects"),
+        listing: value("listing"),
+        max_directories: number("max_directories")?,
+        max_url_length: number("max_url_length")?,
+        no_progress: Some(true).filter(|_| matches.is_present("no_progress")),
+        log_file: value("log_file"),
+        log_format: value("log_format"),
+        report: value("report"),
+    })
+}
+
+/// Extracts the default values of the command line parser
+fn default_settings(matches: &ArgMatches) -> Result<Settings, anyhow::Error> {
+    let number = |name: &str| matches.value_of(name).map(parse_number).transpose();
+
+    Ok(Settings {
+        destination: matches.value_of("destination").map(|v| v.to_owned()),
+        no_download: Some(false),
+        verbosity: Some(0),
+        quiet: Some(0),
+        limit: number("limit")?,
+        skip: number("skip")?,
+        recursive_depth: number("max_depth")?,
+        rate_limit: number("rate_limit")?,
+        no_parent: Some(true),
+        redirects: matches.value_of("redirects").map(|v| v.to_owned()),
+        listing: matches.value_of("listing").map(|v| v.to_owned()),
+        max_directories: number("max_directories")?,
+        max_url_length: number("max_url_length")?,
+        log_format: matches.value_of("log_format").map(|v| v.to_owned()),
+        ..Settings::default()
+    })
+}
+
+/**
+Turns the effective settings into the options of a run (one per root URL).
+
+In batch mode (when reading the URLs from a file), every root gets its own
+destination sub-folder, and the state store path is used as a directory.
+*/
+pub fn get_options(settings: &Settings) -> Result<Vec<CliOptions>, anyhow::Error> {
+    // Collect the root URLs from the arguments and the input file
+    let mut urls: Vec<String> = settings.url.iter().cloned().collect();
+    if let Some(path) = &settings.input_file {
+        urls.extend(read_url_list(path)?);
+    }
+
+    if urls.is_empty() {
+        bail!("No URL specified (neither as an argument, by a profile, nor in an input file)");
+    }
+
+    let batch = settings.input_file.is_some() || urls.len() > 1;
+
+    urls.iter()
+        .map(|url| get_root_options(settings, url, batch))
+        .collect()
+}
+
+/// Makes the filters of the `stats` sub-command (falling back to the ones of the settings)
+pub fn get_selection(
+    matches: &ArgMatches,
+    settings: &Settings,
+) -> Result<Selection, anyhow::Error> {
+    let regex = |name: &str, fallback: &Option<String>| {
+        matches
+            .value_of(name)
+            .or(fallback.as_deref())
+            .map(|value| {
+                Regex::new(value).map_err(|err| anyhow!("Invalid {} regex: {}", name, err))
+            })
+            .transpose()
+    };
+
+    Ok(Selection {
+        file_filter: regex("file_filter", &settings.file_filter)?,
+        file_matcher: regex("file_matcher", &settings.file_matcher)?,
+        path_filter: regex("path_filter", &settings.path_filter)?,
+        path_matcher: regex("path_matcher", &settings.path_matcher)?,
+    })
+}
+
+/// Makes the options for a single root URL
+fn get_root_options(
+    settings: &Settings,
+    url: &str,
+    batch: bool,
+) -> Result<CliOptions, anyhow::Error> {
+    let make_regex = |name: &str, value: &Option<String>| {
+        value
+            .as_ref()
+            .map(|v| Regex::new(v).map_err(|err| anyhow!("Invalid {} regex: {}", name, err)))
+            .transpose()
     };
 
+    let mut url = Url::parse(url).map_err(|err| anyhow!("Invalid URL {}: {}", url, err))?;
+
+    // Strip the credentials from the URL (or use the ones specified otherwise)
+    let netrc_path = settings
+        .netrc_file
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(auth::default_netrc_path);
+    let credentials = Credentials::resolve(
+        &mut url,
+        settings.user.as_deref(),
+        settings.password.as_deref(),
+        netrc_path.as_deref(),
+    )?;
+
+    let mut destination = settings.destination.clone().unwrap_or_default();
+    let mut state_store_path = settings.store_state.clone();
+    let archive = settings
+        .archive
+        .as_deref()
+        .map(str::parse::<ArchiveFormat>)
+        .transpose()?;
+
+    // Keep the roots of a batch apart from each other
+    if batch {
+        let slug = root_slug(&url);
+
+        destination = match archive {
+            _ if destination == "-" => bail!("Cannot write the roots of a batch to stdout"),
+            _ if destination.starts_with("s3://") => {
+                format!("{}/{}", destination.trim_end_matches('/'), slug)
+            }
+            Some(format) => Path::new(&destination)
+                .join(format!("{}.{}", slug, format.extension()))
+                .to_string_lossy()
+                .into_owned(),
+            None => Path::new(&destination)
+                .join(&slug)
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        state_store_path = state_store_path.map(|dir| {
+            Path::new(&dir)
+                .join(format!("{}.json", slug))
+                .to_string_lossy()
+                .into_owned()
+        });
+    }
+
     Ok(CliOptions {
-        url: Url::parse(matches.value_of("URL").unwrap())?,
-        destination: matches.value_of("destination").unwrap().to_owned(),
-        no_download: matches.is_present("disable download"),
-        verbosity: matches.occurrences_of("verbosity"),
-        limit_count: make_option(matches.value_of("limit").unwrap().parse::<u64>()),
-        skip_count: make_option(matches.value_of("skip").unwrap().parse::<u64>()),
-        recursion_limit: make_option(matches.value_of("max_depth").unwrap().parse::<u64>()),
-        file_filter: make_regex("file_filter"),
-        path_filter: make_regex("path_filter"),
-        file_matcher: make_regex("file_matcher"),
-        path_matcher: make_regex("path_matcher"),
-        state_store_path: matches.value_of("state_store").map(|path| path.to_owned()),
+        url,
+        destination,
+        archive,
+        s3_endpoint: settings.s3_endpoint.clone(),
+        no_download: settings.no_download.unwrap_or_default(),
+        limit_count: make_option(settings.limit.unwrap_or_default()),
+        skip_count: make_option(settings.skip.unwrap_or_default()),
+        recursion_limit: make_option(settings.recursive_depth.unwrap_or_default()),
+        file_filter: make_regex("file_filter", &settings.file_filter)?,
+        path_filter: make_regex("path_filter", &settings.path_filter)?,
+        file_matcher: make_regex("file_matcher", &settings.file_matcher)?,
+        path_matcher: make_regex("path_matcher", &settings.path_matcher)?,
+        state_store_path,
+        retry_failed: settings.retry_failed.unwrap_or_default(),
+        credentials,
+        no_parent: settings.no_parent.unwrap_or(true),
+        allowed_hosts: settings.allow_hosts.clone().unwrap_or_default(),
+        redirect_policy: match &settings.redirects {
+            Some(policy) => policy.parse()?,
+            None => RedirectPolicy::Refuse,
+        },
+        crawl_limits: CrawlLimits {
+            max_directories: make_option(settings.max_directories.unwrap_or_default()),
+            max_url_length: make_option(settings.max_url_length.unwrap_or_default())
+                .map(|length| length as usize),
+        },
+        listing_format: match &settings.listing {
+            Some(format) => format.parse()?,
+            None => ListingFormat::Html,
+        },
+        progress: !settings.no_progress.unwrap_or_default(),
+    })
+}
+
+/// Reads a list of URLs (one per line, ignoring blank lines and `#` comments)
+fn read_url_list(path: &str) -> Result<Vec<String>, anyhow::Error> {
+    let text = if path == "-" {
+        let mut text = String::new();
+        io::stdin().read_to_string(&mut text)?;
+        text
+    } else {
+        fs::read_to_string(path)
+            .map_err(|err| anyhow!("Cannot read input file {}: {}", path, err))?
+    };
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_owned)
+        .collect())
+}
+
+/// Makes a file-name-safe identifier for a root URL (e.g. `example.com_pub_linux`)
+fn root_slug(url: &Url) -> String {
+    let mut slug = url.host_str().unwrap_or("unknown_host").to_owned();
+
+    if let Some(port) = url.port() {
+        slug.push_str(&format!("_{}", port));
+    }
+
+    for segment in url.path_segments().into_iter().flatten() {
+        if !segment.is_empty() {
+            slug.push('_');
+            slug.push_str(segment);
+        }
+    }
+
+    slug
+}
+
+/// Parses a number (which has to be a positive integer)
+pub fn parse_number(number: &str) -> Result<u64, anyhow::Error> {
+    number.parse::<u64>().map_err(|err| {
+        anyhow!(
+            "Invalid number {:?} (must be a positive integer): {}",
+            number,
+            err
+        )
     })
 }
 
-/// Converts a number (which has to be greater than zero) to an option, or None (in case of zero)
-fn make_option(number: Result<u64, std::num::ParseIntError>) -> Option<u64> {
-    match number
-        .unwrap_or_else(|err| panic!("Invalid number (must be a positive integer): {:?}", err))
-    {
+/// Converts a number to an option, or None (in case of zero)
+fn make_option(number: u64) -> Option<u64> {
+    match number {
         0 => None,
         n => Some(n),
     }
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/**
+A set of (optional) settings, named after the long CLI flags.
+
+Used for the top-level settings of a config file, for its profiles,
+and for the flags specified on the command line.
+*/
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub archive: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub s3_endpoint: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_download: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbosity: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quiet: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recursive_depth: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_matcher: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path_matcher: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub store_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_failed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub netrc_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_parent: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirects: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub listing: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_directories: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_url_length: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_progress: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub report: Option<String>,
+}
+
+impl Settings {
+    /// Fills every unset value of `self` with the one from `fallback`
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            url: self.url.or(fallback.url),
+            destination: self.destination.or(fallback.destination),
+            archive: self.archive.or(fallback.archive),
+            s3_endpoint: self.s3_endpoint.or(fallback.s3_endpoint),
+            no_download: self.no_download.or(fallback.no_download),
+            verbosity: self.verbosity.or(fallback.verbosity),
+            quiet: self.quiet.or(fallback.quiet),
+            limit: self.limit.or(fallback.limit),
+            skip: self.skip.or(fallback.skip),
+            recursive_depth: self.recursive_depth.or(fallback.recursive_depth),
+            file_filter: self.file_filter.or(fallback.file_filter),
+            path_filter: self.path_filter.or(fallback.path_filter),
+            file_matcher: self.file_matcher.or(fallback.file_matcher),
+            path_matcher: self.path_matcher.or(fallback.path_matcher),
+            store_state: self.store_state.or(fallback.store_state),
+            retry_failed: self.retry_failed.or(fallback.retry_failed),
+            user: self.user.or(fallback.user),
+            password: self.password.or(fallback.password),
+            netrc_file: self.netrc_file.or(fallback.netrc_file),
+            input_file: self.input_file.or(fallback.input_file),
+            rate_limit: self.rate_limit.or(fallback.rate_limit),
+            no_parent: self.no_parent.or(fallback.no_parent),
+            allow_hosts: self.allow_hosts.or(fallback.allow_hosts),
+            redirects: self.redirects.or(fallback.redirects),
+            listing: self.listing.or(fallback.listing),
+            max_directories: self.max_directories.or(fallback.max_directories),
+            max_url_length: self.max_url_length.or(fallback.max_url_length),
+            no_progress: self.no_progress.or(fallback.no_progress),
+            log_file: self.log_file.or(fallback.log_file),
+            log_format: self.log_format.or(fallback.log_format),
+            report: self.report.or(fallback.report),
+        }
+    }
+
+    /// Returns a copy which is safe to print (i.e. without the password)
+    pub fn redacted(&self) -> Settings {
+        Settings {
+            password: self.password.as_ref().map(|_| "********".to_owned()),
+            ..self.clone()
+        }
+    }
+}
+
+/**
+The contents of a config file.
+
+The top-level settings apply to every run, the named profiles only when selected:
+
+```toml
+destination = "/srv/mirror"
+
+[profiles.linux-isos]
+url = "https://example.com/pub/linux/"
+file-matcher = "\\.iso$"
+```
+*/
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub defaults: Settings,
+    pub profiles: BTreeMap<String, Settings>,
+}
+
+impl ConfigFile {
+    /// Reads a config file (TOML, or YAML if the extension is `.yaml` or `.yml`)
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| anyhow!("Cannot read config file {}: {}", path.display(), err))?;
+
+        let is_yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml") | Some("yml")
+        );
+
+        let parsed = if is_yaml {
+            serde_yaml::from_str(&text).map_err(anyhow::Error::from)
+        } else {
+            toml::from_str(&text).map_err(anyhow::Error::from)
+        };
+
+        parsed.map_err(|err| anyhow!("Invalid config file {}: {}", path.display(), err))
+    }
+
+    /**
+    Returns the settings of a run using the given profile (if any).
+
+    The values of the profile take precedence over the top-level settings.
+    */
+    pub fn settings(&self, profile: Option<&str>) -> Result<Settings> {
+        match profile {
+            None => Ok(self.defaults.clone()),
+            Some(name) => match self.profiles.get(name) {
+                Some(settings) => Ok(settings.clone().or(self.defaults.clone())),
+                None => bail!("No profile named \"{}\" in the config file", name),
+            },
+        }
+    }
+}
+
+/**
+Returns the path of the default config file (if one exists).
+
+Looks for `od-get/config.{toml,yaml,yml}` in `$XDG_CONFIG_HOME` (or `~/.config`).
+*/
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+    ["config.toml", "config.yaml", "config.yml"]
+        .iter()
+        .map(|name| config_dir.join(crate::constants::NAME).join(name))
+        .find(|path| path.exists())
+}
@@ -0,0 +1,589 @@
+use super::{
+    charset, compression,
+    error::{OdGetError, Result},
+    session::Session,
+};
+use chrono::DateTime;
+use quick_xml::events::Event as XmlEvent;
+use reqwest::{Method, Url};
+
+/// The query parameters which select a page of a listing (instead of a directory)
+const PAGE_PARAMETERS: [&str; 3] = ["marker", "continuation-token", "start-after"];
+
+/// An object (a file) in a bucket
+#[derive(Debug, Clone, Default)]
+pub struct Object {
+    /// The full key (e.g. `photos/2020/a.jpg`)
+    pub key: String,
+
+    pub size: Option<u64>,
+
+    /// The entity tag (without the quotes)
+    pub etag: String,
+
+    /// The modification time (e.g. `2024-01-31 12:00`)
+    pub modified: String,
+}
+
+/// The (merged) pages of a `ListBucketResult`
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    /// The prefix all keys of the listing share (e.g. `photos/`)
+    pub prefix: String,
+
+    /// The delimiter grouping the keys into common prefixes (empty if they are not grouped)
+    pub delimiter: String,
+
+    pub objects: Vec<Object>,
+
+    /// The common prefixes (the subdirectories, e.g. `photos/2020/`)
+    pub prefixes: Vec<String>,
+
+    /// The query parameter & value requesting the next page (if the listing is truncated)
+    next_page: Option<(&'static str, String)>,
+}
+
+/// Checks if a response is the XML listing of a bucket (S3, GCS, MinIO, ...)
+pub fn is_listing(body: &str) -> bool {
+    matches!(body.find("<ListBucketResult"), Some(index) if index < 1024)
+}
+
+/**
+Returns the URL listing the keys of a bucket grouped into directories (`delimiter=/`).
+
+The URL may already select a prefix (e.g. `http://host/bucket/?prefix=photos/`).
+Version 2 of the API is requested unless the URL chooses one.
+*/
+pub fn listing_url(url: &Url) -> Url {
+    let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    let has = |key: &str| pairs.iter().any(|(name, _)| name == key);
+
+    let mut url = url.clone();
+    {
+        let mut query = url.query_pairs_mut();
+        if !has("list-type") {
+            query.append_pair("list-type", "2");
+        }
+        if !has("delimiter") {
+            query.append_pair("delimiter", "/");
+        }
+    }
+
+    url
+}
+
+/**
+Lists (all pages of) a bucket.
+
+Returns the URL after redirects (from which the URLs of the entries are derived) along the listing.
+*/
+pub async fn list(url: &Url, session: &Session) -> Result<(Url, Listing)> {
+    let (base_url, body) = fetch(url, session).await?;
+    let listing = parse_listing(&body)?;
+    let listing = list_remaining(&base_url, listing, session).await?;
+
+    Ok((base_url, listing))
+}
+
+/**
+Completes a listing from its first page (e.g. a bucket found while crawling HTTP listings).
+
+Listings without a delimiter contain all keys of the bucket at once, so they are
+requested again grouped into directories.
+*/
+pub async fn list_from(base_url: &Url, body: &str, session: &Session) -> Result<(Url, Listing)> {
+    let listing = parse_listing(body)?;
+    if listing.delimiter.is_empty() {
+        return list(&listing_url(base_url), session).await;
+    }
+
+    let listing = list_remaining(base_url, listing, session).await?;
+    Ok((base_url.clone(), listing))
+}
+
+/// Fetches the remaining pages of a truncated listing
+async fn list_remaining(
+    base_url: &Url,
+    mut listing: Listing,
+    session: &Session,
+) -> Result<Listing> {
+    while let Some((parameter, value)) = listing.next_page.take() {
+        let mut url = base_url.clone();
+        set_query(&mut url, &PAGE_PARAMETERS, &[(parameter, &value)]);
+
+        let (_, body) = fetch(&url, session).await?;
+        let page = parse_listing(&body)?;
+
+        // Servers repeating the same page would never finish
+        if page.next_page.as_ref().map(|(_, next)| next) == Some(&value) {
+            return Err(OdGetError::Parse(format!(
+                "The bucket listing {} repeats the page {}",
+                base_url, value
+            )));
+        }
+
+        listing.objects.extend(page.objects);
+        listing.prefixes.extend(page.prefixes);
+        listing.next_page = page.next_page;
+    }
+
+    Ok(listing)
+}
+
+/// Fetches a page of a listing
+async fn fetch(url: &Url, session: &Session) -> Result<(Url, String)> {
+    let res = session
+        .request(
+            Method::GET,
+            url.as_str(),
+            compression::listing_headers(),
+            &[],
+        )
+        .await?
+        .error_for_status()?;
+    let base_url = res.url().clone();
+    let content_type = charset::content_type(res.headers());
+
+    let body = compression::read_listing(res, session).await?;
+    let (body, _) = charset::decode(&body, content_type.as_deref());
+
+    if !is_listing(&body) {
+        return Err(OdGetError::Parse(format!(
+            "{} is no bucket listing",
+            base_url
+        )));
+    }
+
+    Ok((base_url, body))
+}
+
+/**
+Returns the URL of an object of a bucket (e.g. `http://host/bucket/photos/a.jpg`).
+
+The bucket is the listing URL without its query (path-style or virtual-hosted).
+*/
+pub fn object_url(base_url: &Url, key: &str) -> Result<Url> {
+    let mut url = base_url.clone();
+    url.set_query(None);
+    url.set_fragment(None);
+    url.path_segments_mut()
+        .map_err(|_| OdGetError::Parse(format!("Invalid bucket URL {}", base_url)))?
+        .pop_if_empty()
+        .extend(key.split('/'));
+
+    Ok(url)
+}
+
+/// Returns the URL listing a common prefix (like the listing URL, but without its page)
+pub fn prefix_url(base_url: &Url, prefix: &str) -> Url {
+    let mut url = base_url.clone();
+    let mut removed = PAGE_PARAMETERS.to_vec();
+    removed.push("prefix");
+    set_query(&mut url, &removed, &[("prefix", prefix)]);
+
+    url
+}
+
+/// Replaces query parameters (keeping the other ones in order)
+fn set_query(url: &mut Url, removed: &[&str], added: &[(&str, &str)]) {
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .into_owned()
+        .filter(|(name, _)| !removed.contains(&name.as_str()))
+        .collect();
+
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(pairs)
+        .extend_pairs(added);
+}
+
+/**
+Parses a page of a `ListBucketResult` (version 1 or 2 of the API).
+
+Elements are matched by their local names (GCS and MinIO use other namespaces than S3).
+*/
+fn parse_listing(xml: &str) -> Result<Listing> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut listing = Listing::default();
+    let mut truncated = false;
+    let mut next_marker = None;
+    let mut next_token = None;
+
+    let mut object = Object::default();
+    let mut path: Vec<Vec<u8>> = vec![];
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|err| OdGetError::Parse(format!("Invalid bucket listing: {}", err)))?;
+
+        match event {
+            XmlEvent::Start(element) => {
+                let name = element.local_name().as_ref().to_owned();
+                if name == b"Contents" {
+                    object = Object::default();
+                }
+                path.push(name);
+            }
+            XmlEvent::Text(text) => {
+                let text = text
+                    .unescape()
+                    .map_err(|err| OdGetError::Parse(format!("Invalid bucket listing: {}", err)))?;
+                let text = text.trim();
+
+                let parent = path
+                    .len()
+                    .checked_sub(2)
+                    .map(|index| path[index].as_slice());
+
+                match (parent, path.last().map(Vec::as_slice)) {
+                    (Some(b"ListBucketResult"), Some(b"Prefix")) => {
+                        listing.prefix = text.to_owned()
+                    }
+                    (Some(b"ListBucketResult"), Some(b"Delimiter")) => {
+                        listing.delimiter = text.to_owned()
+                    }
+                    (Some(b"ListBucketResult"), Some(b"IsTruncated")) => {
+                        truncated = text.eq_ignore_ascii_case("true")
+                    }
+                    (Some(b"ListBucketResult"), Some(b"NextMarker")) => {
+                        next_marker = Some(text.to_owned())
+                    }
+                    (Some(b"ListBucketResult"), Some(b"NextContinuationToken")) => {
+                        next_token = Some(text.to_owned())
+                    }
+                    (Some(b"Contents"), Some(b"Key")) => object.key = text.to_owned(),
+                    (Some(b"Contents"), Some(b"Size")) => object.size = text.parse().ok(),
+                    (Some(b"Contents"), Some(b"ETag")) => {
+                        object.etag = text.trim_matches('"').to_owned()
+                    }
+                    (Some(b"Contents"), Some(b"LastModified")) => {
+                        object.modified = format_date(text)
+                    }
+                    (Some(b"CommonPrefixes"), Some(b"Prefix")) => {
+                        listing.prefixes.push(text.to_owned())
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::End(element) => {
+                path.pop();
+
+                if element.local_name().as_ref() == b"Contents" && !object.key.is_empty() {
+                    listing.objects.push(std::mem::take(&mut object));
+                }
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+
+    // Version 1 only states the next marker with a delimiter, else it's the last entry
+    if truncated {
+        listing.next_page = match (next_token, next_marker) {
+            (Some(token), _) => Some(("continuation-token", token)),
+            (None, Some(marker)) => Some(("marker", marker)),
+            (None, None) => listing
+                .objects
+                .last()
+                .map(|object| object.key.as_str())
+                .max(listing.prefixes.last().map(String::as_str))
+                .map(|marker| ("marker", marker.to_owned())),
+        };
+    }
+
+    Ok(listing)
+}
+
+/// Formats an ISO 8601 date (e.g. `2024-01-31T12:00:00.000Z`) like the dates of HTML listings
+fn format_date(date: &str) -> String {
+    match DateTime::parse_from_rfc3339(date) {
+        Ok(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => date.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::test_server::{Reply, Request, TestServer};
+
+    const V2_PAGE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">
+  <Name>bucket</Name>
+  <Prefix>photos/</Prefix>
+  <KeyCount>3</KeyCount>
+  <MaxKeys>3</MaxKeys>
+  <Delimiter>/</Delimiter>
+  <IsTruncated>true</IsTruncated>
+  <NextContinuationToken>1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=</NextContinuationToken>
+  <Contents>
+    <Key>photos/a &amp; b.jpg</Key>
+    <LastModified>2024-01-31T12:00:00.000Z</LastModified>
+    <ETag>&quot;d41d8cd98f00b204e9800998ecf8427e&quot;</ETag>
+    <Size>1024</Size>
+    <StorageClass>STANDARD</StorageClass>
+  </Contents>
+  <CommonPrefixes>
+    <Prefix>photos/2020/</Prefix>
+  </CommonPrefixes>
+  <CommonPrefixes>
+    <Prefix>photos/2021/</Prefix>
+  </CommonPrefixes>
+</ListBucketResult>"#;
+
+    #[test]
+    fn parses_a_page() {
+        let listing = parse_listing(V2_PAGE).unwrap();
+
+        assert_eq!(listing.prefix, "photos/");
+        assert_eq!(listing.delimiter, "/");
+        assert_eq!(listing.prefixes, vec!["photos/2020/", "photos/2021/"]);
+
+        assert_eq!(listing.objects.len(), 1);
+        let object = &listing.objects[0];
+        assert_eq!(object.key, "photos/a & b.jpg");
+        assert_eq!(object.size, Some(1024));
+        assert_eq!(object.etag, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(object.modified, "2024-01-31 12:00");
+    }
+
+    #[test]
+    fn continues_with_the_token() {
+        let listing = parse_listing(V2_PAGE).unwrap();
+
+        assert_eq!(
+            listing.next_page,
+            Some((
+                "continuation-token",
+                "1ueGcxLPRx1Tr/XYExHnhbYLgveDs2J/wm36Hy4vbOwM=".to_owned()
+            ))
+        );
+    }
+
+    #[test]
+    fn ends_with_the_last_page() {
+        let xml = V2_PAGE.replace(
+            "<IsTruncated>true</IsTruncated>",
+            "<IsTruncated>false</IsTruncated>",
+        );
+
+        assert_eq!(parse_listing(&xml).unwrap().next_page, None);
+    }
+
+    #[test]
+    fn continues_version_1_with_the_next_marker() {
+        let xml = r#"<ListBucketResult>
+            <Prefix></Prefix>
+            <Delimiter>/</Delimiter>
+            <IsTruncated>true</IsTruncated>
+            <NextMarker>b/</NextMarker>
+            <Contents><Key>a.txt</Key><Size>1</Size></Contents>
+            <CommonPrefixes><Prefix>b/</Prefix></CommonPrefixes>
+        </ListBucketResult>"#;
+
+        assert_eq!(
+            parse_listing(xml).unwrap().next_page,
+            Some(("marker", "b/".to_owned()))
+        );
+    }
+
+    #[test]
+    fn continues_version_1_after_the_last_entry() {
+        // Without a delimiter, version 1 states no next marker
+        let xml = r#"<ListBucketResult>
+            <IsTruncated>true</IsTruncated>
+            <Contents><Key>a/1.txt</Key></Contents>
+            <Contents><Key>c.txt</Key></Contents>
+            <CommonPrefixes><Prefix>b/</Prefix></CommonPrefixes>
+        </ListBucketResult>"#;
+
+        assert_eq!(
+            parse_listing(xml).unwrap().next_page,
+            Some(("marker", "c.txt".to_owned()))
+        );
+    }
+
+    #[test]
+    fn matches_elements_by_their_local_names() {
+        let xml = r#"<s3:ListBucketResult xmlns:s3="http://doc.s3.amazonaws.com/2006-03-01">
+            <s3:Prefix>x/</s3:Prefix>
+            <s3:Contents><s3:Key>x/y.bin</s3:Key><s3:Size>7</s3:Size></s3:Contents>
+        </s3:ListBucketResult>"#;
+        let listing = parse_listing(xml).unwrap();
+
+        assert_eq!(listing.prefix, "x/");
+        assert_eq!(listing.objects[0].key, "x/y.bin");
+        assert_eq!(listing.objects[0].size, Some(7));
+    }
+
+    #[test]
+    fn refuses_broken_xml() {
+        assert!(parse_listing("<ListBucketResult><Contents></ListBucketResult>").is_err());
+    }
+
+    #[test]
+    fn builds_page_urls() {
+        let url = listing_url(&Url::parse("http://host/bucket/?prefix=photos/").unwrap());
+        assert_eq!(
+            url.as_str(),
+            "http://host/bucket/?prefix=photos/&list-type=2&delimiter=%2F"
+        );
+
+        let mut page = url.clone();
+        set_query(
+            &mut page,
+            &PAGE_PARAMETERS,
+            &[("continuation-token", "a+b=")],
+        );
+        assert_eq!(
+            page.as_str(),
+            "http://host/bucket/?prefix=photos%2F&list-type=2&delimiter=%2F&continuation-token=a%2Bb%3D"
+        );
+
+        assert_eq!(
+            prefix_url(&page, "photos/2020/").as_str(),
+            "http://host/bucket/?list-type=2&delimiter=%2F&prefix=photos%2F2020%2F"
+        );
+    }
+
+    /// A page of a listing of the keys (ending with the elements stating the next page)
+    fn page(keys: &[&str], next_page: &str) -> Reply {
+        let contents: String = keys
+            .iter()
+            .map(|key| format!("<Contents><Key>{}</Key><Size>1</Size></Contents>", key))
+            .collect();
+        let xml = format!(
+            "<ListBucketResult><Prefix></Prefix><Delimiter>/</Delimiter>{}{}</ListBucketResult>",
+            next_page, contents
+        );
+
+        Reply::new(200, xml).header("content-type", "application/xml")
+    }
+
+    /// The value of a query parameter of a request
+    fn query(request: &Request, name: &str) -> Option<String> {
+        Url::parse(&format!("http://host{}", request.target))
+            .unwrap()
+            .query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+
+    fn keys(listing: &Listing) -> Vec<&str> {
+        listing
+            .objects
+            .iter()
+            .map(|object| object.key.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lists_version_2_pages() {
+        let server = TestServer::start(|request| {
+            match query(request, "continuation-token").as_deref() {
+                None => page(
+                    &["a.txt"],
+                    "<IsTruncated>true</IsTruncated><NextContinuationToken>t+1=</NextContinuationToken>",
+                ),
+                Some("t+1=") => page(&["b.txt"], "<IsTruncated>false</IsTruncated>"),
+                Some(_) => Reply::new(400, "Unknown token"),
+            }
+        })
+        .await;
+        let url = listing_url(&server.url("/bucket/"));
+
+        let (base_url, listing) = list(&url, &server.session("/bucket/")).await.unwrap();
+        assert_eq!(base_url, url);
+        assert_eq!(keys(&listing), ["a.txt", "b.txt"]);
+
+        let targets: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.target)
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                "/bucket/?list-type=2&delimiter=%2F",
+                "/bucket/?list-type=2&delimiter=%2F&continuation-token=t%2B1%3D",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_version_1_pages() {
+        let server = TestServer::start(|request| match query(request, "marker").as_deref() {
+            None => page(
+                &["a.txt"],
+                "<IsTruncated>true</IsTruncated><NextMarker>a.txt</NextMarker>",
+            ),
+            Some("a.txt") => page(&["b.txt"], "<IsTruncated>true</IsTruncated>"),
+            Some("b.txt") => page(&["c.txt"], "<IsTruncated>false</IsTruncated>"),
+            Some(_) => Reply::new(400, "Unknown marker"),
+        })
+        .await;
+        let url = server.url("/bucket/?list-type=1&delimiter=/");
+
+        let (_, listing) = list(&url, &server.session("/bucket/")).await.unwrap();
+        assert_eq!(keys(&listing), ["a.txt", "b.txt", "c.txt"]);
+
+        let markers: Vec<Option<String>> = server
+            .requests()
+            .iter()
+            .map(|request| query(request, "marker"))
+            .collect();
+        assert_eq!(
+            markers,
+            [None, Some("a.txt".to_owned()), Some("b.txt".to_owned())]
+        );
+    }
+
+    #[tokio::test]
+    async fn refuses_repeated_pages() {
+        let server = TestServer::start(|_| {
+            page(
+                &["a.txt"],
+                "<IsTruncated>true</IsTruncated><NextContinuationToken>same</NextContinuationToken>",
+            )
+        })
+        .await;
+        let url = listing_url(&server.url("/bucket/"));
+
+        let result = list(&url, &server.session("/bucket/")).await;
+        assert!(matches!(result, Err(OdGetError::Parse(message)) if message.contains("repeats")));
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn lists_again_grouped_into_directories() {
+        let server = TestServer::start(|request| match query(request, "delimiter") {
+            Some(_) => page(&["a.txt"], "<IsTruncated>false</IsTruncated>"),
+            None => Reply::new(400, "Expected a delimiter"),
+        })
+        .await;
+        let base_url = server.url("/bucket/");
+        let first_page =
+            "<ListBucketResult><Contents><Key>a/b/c.txt</Key></Contents></ListBucketResult>";
+
+        let (url, listing) = list_from(&base_url, first_page, &server.session("/bucket/"))
+            .await
+            .unwrap();
+        assert_eq!(url, listing_url(&base_url));
+        assert_eq!(keys(&listing), ["a.txt"]);
+    }
+
+    #[tokio::test]
+    async fn refuses_other_documents() {
+        let server = TestServer::start(|_| Reply::new(200, "<html>Not a bucket</html>")).await;
+        let url = listing_url(&server.url("/bucket/"));
+
+        let result = list(&url, &server.session("/bucket/")).await;
+        assert!(
+            matches!(result, Err(OdGetError::Parse(message)) if message.contains("no bucket listing"))
+        );
+    }
+}
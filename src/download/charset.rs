@@ -0,0 +1,168 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use regex::bytes::Regex;
+use reqwest::header::{HeaderMap, CONTENT_TYPE};
+
+/// How many bytes are searched for a `<meta>` tag declaring the charset (like browsers do)
+const META_PRESCAN_LENGTH: usize = 1024;
+
+lazy_static! {
+    /// `<meta charset="...">` or `<meta http-equiv="Content-Type" content="text/html; charset=...">`
+    static ref RX_META_CHARSET: Regex =
+        Regex::new(r#"(?i-u)<meta\s[^>]*charset\s*=\s*["']?([a-z0-9_:.+-]+)"#).unwrap();
+}
+
+/**
+Decodes a listing from its bytes.
+
+The charset is taken from a byte order mark, the `Content-Type` header or a `<meta>` tag
+(in this order). Without any, the listing is UTF-8 if it's valid, else Windows-1252
+(a superset of Latin-1). Invalid bytes are replaced instead of failing.
+*/
+pub fn decode(body: &[u8], content_type: Option<&str>) -> (String, &'static Encoding) {
+    let encoding = Encoding::for_bom(body)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| content_type.and_then(header_charset))
+        .or_else(|| meta_charset(body))
+        .unwrap_or_else(|| {
+            if std::str::from_utf8(body).is_ok() {
+                UTF_8
+            } else {
+                WINDOWS_1252
+            }
+        });
+
+    let (text, encoding, _) = encoding.decode(body);
+    (text.into_owned(), encoding)
+}
+
+/**
+Percent-decodes a segment of a URL (e.g. a file name).
+
+Browsers encode links as UTF-8, but legacy servers encode the names in the charset
+of their listing (e.g. `%E9` for `é` in Latin-1), so that's the fallback.
+*/
+pub fn decode_segment(segment: &str, encoding: &'static Encoding) -> String {
+    let bytes: Vec<u8> = percent_decode_str(segment).collect();
+
+    match String::from_utf8(bytes) {
+        Ok(name) => name,
+        Err(err) => encoding
+            .decode_without_bom_handling(err.as_bytes())
+            .0
+            .into_owned(),
+    }
+}
+
+/// Returns the `Content-Type` header of a response (to pass it to `decode`)
+pub fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+}
+
+/// Returns the charset of a `Content-Type` header (e.g. `text/html; charset=ISO-8859-1`)
+fn header_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+}
+
+/// Returns the charset declared by a `<meta>` tag near the start of a page
+fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    let head = &body[..body.len().min(META_PRESCAN_LENGTH)];
+
+    RX_META_CHARSET
+        .captures(head)
+        .and_then(|captures| Encoding::for_label(&captures[1]))
+        // Pages can't declare UTF-16 in themselves (they would be ASCII-compatible)
+        .map(Encoding::output_encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `café` in Windows-1252
+    const CAFE_1252: &[u8] = b"caf\xe9";
+
+    #[test]
+    fn prefers_the_byte_order_mark() {
+        let body = b"\xef\xbb\xbf<meta charset=\"iso-8859-1\">caf\xc3\xa9";
+        let (text, encoding) = decode(body, Some("text/html; charset=ISO-8859-1"));
+
+        assert_eq!(encoding, UTF_8);
+        assert_eq!(text, "<meta charset=\"iso-8859-1\">café");
+    }
+
+    #[test]
+    fn prefers_the_content_type_over_meta_tags() {
+        let body = [b"<meta charset=\"utf-8\">".as_ref(), CAFE_1252].concat();
+        let (text, encoding) = decode(&body, Some("text/html; charset=\"windows-1252\""));
+
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(text.ends_with("café"));
+    }
+
+    #[test]
+    fn reads_meta_tags() {
+        let body = [
+            b"<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=ISO-8859-1\">"
+                .as_ref(),
+            CAFE_1252,
+        ]
+        .concat();
+
+        // Content types without charset don't decide
+        let (text, encoding) = decode(&body, Some("text/html"));
+        assert_eq!(encoding, WINDOWS_1252);
+        assert!(text.ends_with("café"));
+
+        // UTF-16 can't be declared in the page itself
+        let (_, encoding) = decode(b"<meta charset=utf-16>caf\xc3\xa9", None);
+        assert_eq!(encoding, UTF_8);
+    }
+
+    #[test]
+    fn only_prescans_the_start_for_meta_tags() {
+        let body = [
+            " ".repeat(META_PRESCAN_LENGTH).as_bytes(),
+            b"<meta charset=\"windows-1252\">caf\xc3\xa9",
+        ]
+        .concat();
+
+        assert_eq!(decode(&body, None).1, UTF_8);
+    }
+
+    #[test]
+    fn defaults_to_utf_8() {
+        let (text, encoding) = decode("café".as_bytes(), None);
+
+        assert_eq!(encoding, UTF_8);
+        assert_eq!(text, "café");
+    }
+
+    #[test]
+    fn falls_back_to_windows_1252_on_invalid_utf_8() {
+        let (text, encoding) = decode(CAFE_1252, None);
+
+        assert_eq!(encoding, WINDOWS_1252);
+        assert_eq!(text, "café");
+    }
+
+    #[test]
+    fn decodes_segments() {
+        assert_eq!(decode_segment("%C3%A9t%C3%A9.txt", WINDOWS_1252), "été.txt");
+        assert_eq!(decode_segment("%E9t%E9.txt", WINDOWS_1252), "été.txt");
+        assert_eq!(
+            decode_segment("%E9t%E9.txt", UTF_8),
+            "\u{fffd}t\u{fffd}.txt"
+        );
+        assert_eq!(decode_segment("a%20b.txt", WINDOWS_1252), "a b.txt");
+    }
+}
@@ -0,0 +1,329 @@
+use super::{
+    error::{OdGetError, Result},
+    session::Session,
+};
+use brotli::{Decompressor, DecompressorWriter};
+use flate2::{
+    read::{DeflateDecoder, GzDecoder, ZlibDecoder},
+    write,
+};
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING},
+    Response,
+};
+use std::io::{Read, Write};
+
+/// The encodings offered for listings (large listings compress very well)
+const LISTING_ENCODINGS: &str = "gzip, deflate, br";
+
+/// The encodings offered for downloads (of files which aren't compressed already)
+const DOWNLOAD_ENCODINGS: &str = "gzip, br";
+
+/// The maximum size of a listing (also after decompressing it, e.g. against compression bombs)
+const MAX_LISTING_SIZE: u64 = 128 * 1024 * 1024;
+
+/// The buffer size of the brotli decoders
+const BROTLI_BUFFER: usize = 4096;
+
+/// The extensions of files which are compressed already (and must be stored as they are)
+const COMPRESSED_EXTENSIONS: [&str; 16] = [
+    "gz", "tgz", "svgz", "bz2", "tbz", "tbz2", "xz", "txz", "zst", "br", "lz", "lzma", "z", "zip",
+    "7z", "rar",
+];
+
+/// The headers of a listing request (negotiating a compressed transfer)
+pub fn listing_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(LISTING_ENCODINGS));
+    headers
+}
+
+/**
+The headers of a download request.
+
+A compressed transfer is only negotiated if it doesn't change the stored bytes: servers
+often mark compressed files (e.g. `.tar.gz`) with `Content-Encoding: gzip`, so these are
+requested without any encoding and stored as they arrive.
+*/
+pub fn download_headers(name: &str) -> HeaderMap {
+    let encoding = if is_compressed_file(name) {
+        "identity"
+    } else {
+        DOWNLOAD_ENCODINGS
+    };
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(encoding));
+    headers
+}
+
+/// Checks if a file is compressed already (by its extension)
+pub fn is_compressed_file(name: &str) -> bool {
+    match name.rsplit_once('.') {
+        Some((_, extension)) => COMPRESSED_EXTENSIONS
+            .iter()
+            .any(|compressed| compressed.eq_ignore_ascii_case(extension)),
+        None => false,
+    }
+}
+
+/**
+Reads the body of a listing, decompressing it as stated by its `Content-Encoding`.
+
+The transferred (compressed) bytes are counted by the reporter. Listings larger than
+`MAX_LISTING_SIZE` (before or after decompressing them) are refused.
+*/
+pub async fn read_listing(mut res: Response, session: &Session) -> Result<Vec<u8>> {
+    let url = res.url().clone();
+    let encodings = content_encodings(res.headers());
+
+    let mut body = vec![];
+    while let Some(chunk) = res.chunk().await? {
+        session.reporter.transferred(&url, chunk.len() as u64);
+        if (body.len() + chunk.len()) as u64 > MAX_LISTING_SIZE {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    // Encodings are listed in the order they were applied
+    for encoding in encodings.iter().rev() {
+        body = decompress(&body, encoding)?;
+    }
+
+    Ok(body)
+}
+
+/// Decompresses a whole body (up to `MAX_LISTING_SIZE` bytes)
+fn decompress(body: &[u8], encoding: &str) -> Result<Vec<u8>> {
+    match encoding {
+        "gzip" | "x-gzip" => read_limited(GzDecoder::new(body)),
+        // Some servers send raw deflate streams instead of zlib ones
+        "deflate" => match read_limited(ZlibDecoder::new(body)) {
+            Err(OdGetError::Io(_)) => read_limited(DeflateDecoder::new(body)),
+            result => result,
+        },
+        "br" => read_limited(Decompressor::new(body, BROTLI_BUFFER)),
+        other => Err(unsupported(other)),
+    }
+}
+
+/// Reads a decoder to the end, failing once the output exceeds `MAX_LISTING_SIZE`
+fn read_limited(decoder: impl Read) -> Result<Vec<u8>> {
+    let mut output = vec![];
+    decoder
+        .take(MAX_LISTING_SIZE + 1)
+        .read_to_end(&mut output)?;
+
+    if output.len() as u64 > MAX_LISTING_SIZE {
+        return Err(too_large());
+    }
+
+    Ok(output)
+}
+
+/// Decodes the body of a download as it arrives (in chunks)
+pub enum Decoder {
+    Identity,
+    Gzip(Box<write::GzDecoder<Vec<u8>>>),
+    Brotli(Box<DecompressorWriter<Vec<u8>>>),
+}
+
+impl Decoder {
+    /**
+    Chooses the decoder for a download requested with `download_headers`.
+
+    Bodies are only decoded if a compressed transfer was negotiated, else they are stored
+    as they arrive (whatever the server states).
+    */
+    pub fn for_download(headers: &HeaderMap, name: &str) -> Result<Self> {
+        if is_compressed_file(name) {
+            return Ok(Self::Identity);
+        }
+
+        let encodings = content_encodings(headers);
+        match encodings
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .as_slice()
+        {
+            [] => Ok(Self::Identity),
+            ["gzip"] | ["x-gzip"] => Ok(Self::Gzip(Box::new(write::GzDecoder::new(vec![])))),
+            ["br"] => Ok(Self::Brotli(Box::new(DecompressorWriter::new(
+                vec![],
+                BROTLI_BUFFER,
+            )))),
+            _ => Err(unsupported(&encodings.join(", "))),
+        }
+    }
+
+    /// Whether the body is stored as it arrives
+    pub fn is_identity(&self) -> bool {
+        matches!(self, Self::Identity)
+    }
+
+    /// Decodes a chunk (returning the bytes decoded so far)
+    pub fn decode(&mut self, chunk: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Identity => Ok(chunk.to_vec()),
+            Self::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+            Self::Brotli(decoder) => {
+                decoder.write_all(chunk)?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+
+    /// Finishes decoding (returning the remaining bytes)
+    pub fn finish(&mut self) -> Result<Vec<u8>> {
+        match std::mem::replace(self, Self::Identity) {
+            Self::Identity => Ok(vec![]),
+            Self::Gzip(decoder) => Ok(decoder.finish()?),
+            Self::Brotli(mut decoder) => {
+                // Fails if the stream ended early
+                decoder.close()?;
+                Ok(std::mem::take(decoder.get_mut()))
+            }
+        }
+    }
+}
+
+/// Returns the encodings stated by the `Content-Encoding` header (e.g. `gzip`, lower-cased)
+fn content_encodings(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|encoding| encoding.trim().to_ascii_lowercase())
+        .filter(|encoding| !encoding.is_empty() && encoding != "identity")
+        .collect()
+}
+
+fn too_large() -> OdGetError {
+    OdGetError::Parse(format!(
+        "The listing is larger than {} bytes",
+        MAX_LISTING_SIZE
+    ))
+}
+
+fn unsupported(encoding: &str) -> OdGetError {
+    OdGetError::Parse(format!("Unsupported content encoding \"{}\"", encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{
+        write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+        Compression,
+    };
+
+    const BODY: &[u8] = b"<html><a href=\"file.txt\">file.txt</a></html>";
+
+    fn encoded(encoding: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        headers
+    }
+
+    fn accepted(headers: &HeaderMap) -> &str {
+        headers[ACCEPT_ENCODING].to_str().unwrap()
+    }
+
+    fn gzip() -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(BODY).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zlib() -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(BODY).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn raw_deflate() -> Vec<u8> {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(BODY).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn brotli() -> Vec<u8> {
+        let mut encoder = brotli::CompressorWriter::new(vec![], BROTLI_BUFFER, 5, 22);
+        encoder.write_all(BODY).unwrap();
+        encoder.into_inner()
+    }
+
+    /// Feeds a body to a decoder in small chunks (like a download arrives)
+    fn decode_chunked(decoder: &mut Decoder, body: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        for chunk in body.chunks(7) {
+            output.extend(decoder.decode(chunk).unwrap());
+        }
+        output.extend(decoder.finish().unwrap());
+        output
+    }
+
+    #[test]
+    fn detects_compressed_files() {
+        assert!(is_compressed_file("backup.tar.gz"));
+        assert!(is_compressed_file("ARCHIVE.ZIP"));
+        assert!(is_compressed_file("image.iso.xz"));
+        assert!(!is_compressed_file("notes.txt"));
+        assert!(!is_compressed_file("README"));
+        assert!(!is_compressed_file("gz"));
+    }
+
+    #[test]
+    fn requests_compressed_files_without_encoding() {
+        for name in &["a.tar.gz", "b.zip", "c.xz"] {
+            assert_eq!(accepted(&download_headers(name)), "identity");
+        }
+
+        for name in &["a.txt", "b.iso", "README"] {
+            assert_eq!(accepted(&download_headers(name)), DOWNLOAD_ENCODINGS);
+        }
+    }
+
+    #[test]
+    fn stores_compressed_files_undecoded() {
+        // The server marks the file as gzip encoded, yet it's stored as it is
+        let mut decoder = Decoder::for_download(&encoded("gzip"), "a.tar.gz").unwrap();
+        assert!(decoder.is_identity());
+        assert_eq!(decode_chunked(&mut decoder, &gzip()), gzip());
+
+        let mut decoder = Decoder::for_download(&HeaderMap::new(), "a.txt").unwrap();
+        assert!(decoder.is_identity());
+        assert_eq!(decode_chunked(&mut decoder, BODY), BODY);
+    }
+
+    #[test]
+    fn decodes_compressed_downloads() {
+        let mut decoder = Decoder::for_download(&encoded("gzip"), "a.txt").unwrap();
+        assert!(!decoder.is_identity());
+        assert_eq!(decode_chunked(&mut decoder, &gzip()), BODY);
+
+        let mut decoder = Decoder::for_download(&encoded("br"), "a.txt").unwrap();
+        assert_eq!(decode_chunked(&mut decoder, &brotli()), BODY);
+
+        assert!(Decoder::for_download(&encoded("compress"), "a.txt").is_err());
+    }
+
+    #[test]
+    fn decompresses_listings() {
+        assert_eq!(decompress(&gzip(), "gzip").unwrap(), BODY);
+        assert_eq!(decompress(&brotli(), "br").unwrap(), BODY);
+        assert!(decompress(BODY, "compress").is_err());
+    }
+
+    #[test]
+    fn decompresses_zlib_and_raw_deflate() {
+        assert_eq!(decompress(&zlib(), "deflate").unwrap(), BODY);
+        assert_eq!(decompress(&raw_deflate(), "deflate").unwrap(), BODY);
+    }
+}
@@ -1,18 +1,37 @@
-use std::str::FromStr;
-
-use super::types::{DirLinkMetaData, FileLinkMetaData, Node};
-use anyhow::{anyhow, bail, Result};
-use html_escape::decode_html_entities_to_vec;
+use super::{
+    bucket, charset, compression,
+    error::{OdGetError, Result},
+    events::{DirSkipReason, Event},
+    ftp,
+    scope::{self, Queue, Scope},
+    session::Session,
+    types::{DirLinkMetaData, DiscoveredEntry, FileLinkMetaData, Node},
+    webdav,
+};
+use encoding_rs::Encoding;
+use futures::stream::{self, Stream};
+use html_escape::decode_html_entities;
 use lazy_static::lazy_static;
+use log::debug;
+use percent_encoding::percent_decode_str;
 use rayon::prelude::*;
 use regex::Regex;
-use reqwest::{self, Url};
+use reqwest::{self, Method, Url};
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet, VecDeque},
+    future::Future,
+    hash::{Hash, Hasher},
+    pin::Pin,
+    str::FromStr,
+    time::Instant,
+};
+
+const CANNOT_PARSE_DIRECTORY: &str = "Couldn't parse the directory name";
 
-// Make-shift errors
-const CANNOT_PARSE_DIRECTORY: &'static str = "Couldn't parse the directory name";
-const EMPTY_RESPONSE: &'static str = "Got a empty response";
+const EMPTY_SIZE_STRING: &str = "  - ";
 
-const EMPTY_SIZE_STRING: &'static str = "  - ";
+/// The maximum number of pages merged into the listing of a directory
+const MAX_LISTING_PAGES: usize = 1000;
 
 pub const POS_HREF: usize = 1;
 pub const POS_NAME: usize = 2;
@@ -33,18 +52,35 @@ lazy_static! {
 
     /// This is an example for using doc comment attributes
     static ref RX_TITLE: Regex = Regex::new("<h1>Index of (.+?)</h1>").unwrap();
+
+    /// Links (or `<link>` tags) marked as the next page, e.g. `<a rel="next" href="?page=2">`
+    static ref RX_REL_NEXT: Regex =
+        Regex::new(r#"(?i)<(?:a|link)\s[^>]*\brel\s*=\s*["']?next\b[^>]*>"#).unwrap();
+
+    /// Links labelled as the next page, e.g. `<a href="?page=2">Next »</a>`
+    static ref RX_NEXT_LABEL: Regex =
+        Regex::new(r#"(?is)<a\s[^>]*>\s*(?:next(?:\s+page)?\s*[»›>]*|[»›]+)\s*</a>"#).unwrap();
+
+    /// The opening tags of links
+    static ref RX_LINK: Regex = Regex::new(r#"(?i)<a\s[^>]*>"#).unwrap();
+
+    /// The (quoted or unquoted) href attribute of a tag
+    static ref RX_HREF: Regex =
+        Regex::new(r#"(?i)\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).unwrap();
 }
 
+/// A closure turning a line of HTML into a node (if it contains one)
+pub type RowProcessor<'a> = Box<dyn Fn(&str) -> Option<Node> + Send + Sync + 'a>;
+
 /**
 Returns the first match in a string with a given Regex pattern
 */
 fn get_first<'a>(text: &'a str, regex: &Regex) -> Result<&'a str> {
-    Ok(regex
+    regex
         .captures(text)
-        .ok_or(anyhow!(CANNOT_PARSE_DIRECTORY))?
-        .get(1)
-        .ok_or(anyhow!(CANNOT_PARSE_DIRECTORY))?
-        .as_str())
+        .and_then(|captures| captures.get(1))
+        .map(|capture| capture.as_str())
+        .ok_or_else(|| OdGetError::Parse(CANNOT_PARSE_DIRECTORY.to_owned()))
 }
 
 /**
@@ -70,21 +106,160 @@ pub fn cheap_extract_from_html(html: &str, base_url: &Url) -> Result<(String, Ve
     Ok((dir_name.to_owned(), nodes))
 }
 
+/**
+Turns the entries of an FTP listing into nodes.
+
+Returns a tuple containing the path of the directory (e.g. `/pub/linux/`, like the title
+of an HTML listing) and the nodes.
+*/
+pub fn extract_from_ftp_listing(
+    entries: Vec<ftp::Entry>,
+    base_url: &Url,
+) -> Result<(String, Vec<Node>)> {
+    let dir_name = percent_decode_str(base_url.path())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    let mut nodes = vec![];
+    for entry in entries {
+        let mut href = base_url.clone();
+        href.path_segments_mut()
+            .map_err(|_| OdGetError::Parse(format!("Invalid FTP URL {}", base_url)))?
+            .pop_if_empty()
+            .push(&entry.name);
+
+        nodes.push(entry_node(href, entry.dir, entry.size, entry.modified));
+    }
+
+    Ok((dir_name, nodes))
+}
+
+/**
+Turns the members of a WebDAV collection into nodes.
+
+Returns a tuple containing the path of the collection (e.g. `/pub/linux/`) and the nodes.
+*/
+pub fn extract_from_webdav_listing(
+    entries: Vec<webdav::Entry>,
+    base_url: &Url,
+) -> Result<(String, Vec<Node>)> {
+    let dir_name = percent_decode_str(base_url.path())
+        .decode_utf8_lossy()
+        .into_owned();
+
+    let nodes = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let href = base_url.join(&entry.href).ok()?;
+            Some(entry_node(href, entry.dir, entry.size, entry.modified))
+        })
+        .collect();
+
+    Ok((dir_name, nodes))
+}
+
+/**
+Turns a bucket listing into nodes (common prefixes become directories, keys files).
+
+Returns a tuple containing the path of the prefix (e.g. `/bucket/photos/`) and the nodes.
+*/
+pub fn extract_from_bucket_listing(
+    listing: bucket::Listing,
+    base_url: &Url,
+) -> Result<(String, Vec<Node>)> {
+    let bucket_url = bucket::object_url(base_url, "")?;
+    let dir_name = format!(
+        "{}{}",
+        percent_decode_str(bucket_url.path()).decode_utf8_lossy(),
+        listing.prefix
+    );
+
+    let mut nodes = vec![];
+    for prefix in listing.prefixes {
+        // The URL of the directory is the listing of its prefix
+        let href = bucket::prefix_url(base_url, &prefix);
+
+        let name = prefix
+            .trim_end_matches(listing.delimiter.as_str())
+            .rsplit(listing.delimiter.as_str())
+            .next()
+            .unwrap_or_default();
+
+        nodes.push(Node::PendingDir(DirLinkMetaData {
+            url: href.to_string(),
+            name: format!("{}/", name),
+            last_modified: String::new(),
+            description: String::new(),
+        }));
+    }
+
+    for object in listing.objects {
+        // Skip the placeholders of empty "folders" (e.g. `photos/`)
+        if object.key == listing.prefix || object.key.ends_with('/') {
+            continue;
+        }
+
+        let href = bucket::object_url(base_url, &object.key)?;
+        let mut node = entry_node(href, false, object.size, object.modified);
+        if let Node::File(file) = &mut node {
+            file.etag = object.etag;
+        }
+        nodes.push(node);
+    }
+
+    Ok((dir_name, nodes))
+}
+
+/**
+Makes the node of a listing entry with known metadata (e.g. from FTP or WebDAV).
+
+The name is taken from the URL, and directories get a trailing `/` (like the links of
+HTML listings).
+*/
+fn entry_node(mut href: Url, dir: bool, size: Option<u64>, last_modified: String) -> Node {
+    if dir && !href.path().ends_with('/') {
+        href.set_path(&format!("{}/", href.path()));
+    }
+
+    let name = entry_name(href.as_str(), "");
+
+    if dir {
+        Node::PendingDir(DirLinkMetaData {
+            url: href.to_string(),
+            name: format!("{}/", name),
+            last_modified,
+            description: String::new(),
+        })
+    } else {
+        Node::File(FileLinkMetaData {
+            url: href.to_string(),
+            name,
+            last_modified,
+            size: size.map(|size| size.to_string()).unwrap_or_default(),
+            description: String::new(),
+            etag: String::new(),
+        })
+    }
+}
+
 /**
 Turns an ElementRef (of a HTML table-row into a node (Either PendingDir or File)
 */
-pub fn cheap_process_row<'a>(
-    base_url: &'a Url,
-) -> Box<dyn Fn(&str) -> Option<Node> + Send + Sync + 'a> {
+pub fn cheap_process_row(base_url: &Url) -> RowProcessor<'_> {
     Box::new(move |line| {
         let captures = RX_MAIN.captures(line)?;
 
         // Calculate the absolute href using the base_url
-        let mut href = base_url
+        let href = base_url
             .join(captures.get(POS_HREF)?.as_str())
             .to_owned()
             .ok()?;
 
+        // Links to the listing itself (e.g. to sort it, `?C=N;O=D`) are no subdirectories
+        if is_same_dir(base_url, &href) {
+            return None;
+        }
+
         // The other values get extracted using the regex
         let name = captures.get(POS_NAME)?.as_str().to_owned();
         let last_modified = captures.get(POS_DATE)?.as_str().to_owned();
@@ -95,7 +270,6 @@ pub fn cheap_process_row<'a>(
         if captures.get(POS_SIZE)?.as_str() == EMPTY_SIZE_STRING {
             // TODO re-introduce count
             // println!("Got directory ({:4}): {}", nodes.len(), &name);
-            println!("Got directory: {}", &name);
 
             Some(Node::PendingDir(DirLinkMetaData {
                 url: href.to_string(),
@@ -104,12 +278,8 @@ pub fn cheap_process_row<'a>(
                 description,
             }))
         } else {
-            clean_url(&mut href);
-
             // TODO re-introduce count
             // println!("Got file ({:4}): {}", nodes.len(), &name);
-            println!("Got file: {}", &name);
-            println!("{}\n", &href);
 
             Some(Node::File(FileLinkMetaData {
                 url: href.to_string(),
@@ -117,6 +287,7 @@ pub fn cheap_process_row<'a>(
                 last_modified,
                 size,
                 description,
+                etag: String::new(),
             }))
         }
     })
@@ -124,91 +295,770 @@ pub fn cheap_process_row<'a>(
     // unimplemented!()
 }
 
+/// How the listings of HTTP(S) directories are requested (FTP servers are always listed over FTP)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListingFormat {
+    /// HTML index pages (e.g. Apache's `mod_autoindex`)
+    #[default]
+    Html,
+
+    /// WebDAV `PROPFIND` requests (e.g. Nextcloud shares or Apache's `mod_dav`)
+    WebDav,
+
+    /// S3-style `ListBucketResult` listings (e.g. public S3, GCS or MinIO buckets)
+    S3,
+}
+
+impl FromStr for ListingFormat {
+    type Err = OdGetError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "html" => Ok(Self::Html),
+            "webdav" => Ok(Self::WebDav),
+            "s3" => Ok(Self::S3),
+            other => Err(OdGetError::Parse(format!(
+                "Invalid listing format \"{}\" (must be `html`, `webdav` or `s3`)",
+                other
+            ))),
+        }
+    }
+}
+
 /**
-Expand all PengingDir nodes
+Limits protecting the crawler against pathological servers (e.g. endless symlink loops)
 */
-pub async fn expand_node(nodes: &mut Vec<Node>, client: &reqwest::Client) -> Result<()> {
-    for node in nodes {
-        // Only crawl if needed
-        if let Node::PendingDir(dir) = node {
-            println!("Now crawling: {}", dir.name);
-            let req = client.get(&dir.url).send();
-
-            // Get the HTML from the server
-            let html = match req.await {
-                Ok(res) => sanitize_html(&res.text().await.expect(EMPTY_RESPONSE))?,
-                Err(err) => bail!(err),
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CrawlLimits {
+    /// The maximum number of directories to crawl
+    pub max_directories: Option<u64>,
+
+    /// Directories with longer URLs are not crawled
+    pub max_url_length: Option<usize>,
+}
+
+/// The state of a recursive crawl
+struct CrawlState {
+    limits: CrawlLimits,
+
+    /// The canonical URLs (after redirects) of the directories crawled so far
+    visited: HashSet<String>,
+
+    /// The number of directories crawled so far
+    directories: u64,
+
+    /// Whether the directory limit was reached
+    limit_reached: bool,
+}
+
+impl CrawlState {
+    fn new(limits: CrawlLimits) -> Self {
+        Self {
+            limits,
+            visited: HashSet::new(),
+            directories: 0,
+            limit_reached: false,
+        }
+    }
+}
+
+/// A directory listing as fetched from the server (before parsing)
+enum Page {
+    /// The (sanitized) HTML of an HTTP listing & the charset it was encoded in
+    Html(String, &'static Encoding),
+
+    /// The entries of an FTP listing
+    Ftp(Vec<ftp::Entry>),
+
+    /// The members of a WebDAV collection
+    WebDav(Vec<webdav::Entry>),
+
+    /// The keys & common prefixes of a bucket
+    Bucket(bucket::Listing),
+}
+
+/**
+Fetches the listing of a directory (over HTTP, WebDAV, FTP or from a bucket).
+
+Returns the URL after redirects (to which the links are relative) along the listing.
+*/
+async fn fetch_listing(url: &Url, session: &Session) -> Result<(Url, Page)> {
+    if ftp::is_ftp(url) {
+        // The links of the listing are relative to the directory
+        let mut base_url = url.clone();
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+
+        let entries = ftp::list(&base_url, session).await?;
+        return Ok((base_url, Page::Ftp(entries)));
+    }
+
+    if session.listing_format == ListingFormat::WebDav {
+        let (base_url, entries) = webdav::list(url, session).await?;
+        return Ok((base_url, Page::WebDav(entries)));
+    }
+
+    if session.listing_format == ListingFormat::S3 {
+        let (base_url, listing) = bucket::list(&bucket::listing_url(url), session).await?;
+        return Ok((base_url, Page::Bucket(listing)));
+    }
+
+    let (base_url, text, encoding) = fetch_text(url, session).await?;
+
+    // Public buckets answer with an XML listing instead of an index page
+    if bucket::is_listing(&text) {
+        let (base_url, listing) = bucket::list_from(&base_url, &text, session).await?;
+        return Ok((base_url, Page::Bucket(listing)));
+    }
+
+    let html = fetch_pages(&base_url, sanitize_html(&text), session).await?;
+    Ok((base_url, Page::Html(html, encoding)))
+}
+
+/**
+Fetches a page (compressed if the server supports it) & decodes it in its charset.
+
+Returns the URL after redirects along the text and its charset.
+*/
+async fn fetch_text(url: &Url, session: &Session) -> Result<(Url, String, &'static Encoding)> {
+    let res = session
+        .request(
+            Method::GET,
+            url.as_str(),
+            compression::listing_headers(),
+            &[],
+        )
+        .await?;
+    let base_url = res.url().clone();
+    let content_type = charset::content_type(res.headers());
+
+    let body = compression::read_listing(res, session).await?;
+
+    let (text, encoding) = charset::decode(&body, content_type.as_deref());
+    if encoding != encoding_rs::UTF_8 {
+        debug!(event = "charset", url:% = base_url, charset = encoding.name(); "Decoding {} as {}", base_url, encoding.name());
+    }
+
+    Ok((base_url, text, encoding))
+}
+
+/**
+Fetches the following pages of a paginated HTML listing (e.g. of h5ai or PHP indexes).
+
+The pages are appended to the first one, so they get parsed as a single listing.
+*/
+async fn fetch_pages(first_url: &Url, mut html: String, session: &Session) -> Result<String> {
+    let mut pages = HashSet::new();
+    pages.insert(scope::normalize(first_url).to_string());
+
+    let mut next = next_page_url(&html, first_url);
+    while let Some(url) = next.take() {
+        // Stop at pages linking back (or servers with endless pages)
+        if pages.len() >= MAX_LISTING_PAGES || !pages.insert(scope::normalize(&url).to_string()) {
+            break;
+        }
+
+        debug!(event = "page", url:% = url; "Fetching listing page {}", url);
+        let (page_url, text, _) = fetch_text(&url, session).await?;
+
+        // The links of the pages are resolved against the first one
+        if !is_same_dir(first_url, &page_url) {
+            break;
+        }
+
+        let page = sanitize_html(&text);
+        next = next_page_url(&page, &page_url);
+
+        html.push('\n');
+        html.push_str(&page);
+    }
+
+    Ok(html)
+}
+
+/**
+Finds the link to the next page of a listing.
+
+Links marked with `rel="next"` are preferred, then links labelled as the next page
+(e.g. `Next »`), then links to the following page number (e.g. `?page=3` on page 2).
+Only links to other pages of the same directory are considered (not the sort orders).
+*/
+fn next_page_url(html: &str, page_url: &Url) -> Option<Url> {
+    let links = |regex: &Regex| -> Vec<Url> {
+        regex
+            .find_iter(html)
+            .filter_map(|tag| RX_HREF.captures(tag.as_str()))
+            .filter_map(|captures| captures.iter().skip(1).flatten().next())
+            .filter_map(|href| page_url.join(href.as_str()).ok())
+            .filter(|url| is_other_page(page_url, url))
+            .collect()
+    };
+
+    let next_number = page_number(page_url).unwrap_or(1) + 1;
+
+    links(&RX_REL_NEXT)
+        .into_iter()
+        .chain(links(&RX_NEXT_LABEL))
+        .next()
+        .or_else(|| {
+            links(&RX_LINK)
+                .into_iter()
+                .find(|url| page_number(url) == Some(next_number))
+        })
+}
+
+/// Checks if a URL points to the same directory (possibly with another query)
+fn is_same_dir(base_url: &Url, url: &Url) -> bool {
+    url.scheme() == base_url.scheme()
+        && url.host_str() == base_url.host_str()
+        && url.port_or_known_default() == base_url.port_or_known_default()
+        && url.path() == base_url.path()
+}
+
+/// Checks if a URL is another page of a listing (the same directory, another query)
+fn is_other_page(page_url: &Url, url: &Url) -> bool {
+    is_same_dir(page_url, url)
+        && url.query().is_some()
+        && url.query() != page_url.query()
+        && !is_sort_link(url)
+}
+
+/// Checks if a URL sorts a listing (e.g. Apache's `?C=N;O=D`)
+fn is_sort_link(url: &Url) -> bool {
+    url.query().is_some_and(|query| {
+        query
+            .split(['&', ';'])
+            .any(|pair| pair.starts_with("C=") || pair.starts_with("O="))
+    })
+}
+
+/// Returns the number of a page (the `page` parameter, e.g. `?page=2`)
+fn page_number(url: &Url) -> Option<u64> {
+    url.query_pairs()
+        .find(|(name, _)| name == "page")
+        .and_then(|(_, number)| number.parse().ok())
+}
+
+/// Parses a listing into the name of the directory and its entries
+fn parse_listing(page: Page, base_url: &Url) -> Result<(String, Vec<Node>)> {
+    match page {
+        Page::Html(html, encoding) => {
+            let (name, mut nodes) = cheap_extract_from_html(&html, base_url)?;
+            decode_names(&mut nodes, encoding);
+            Ok((name, nodes))
+        }
+        Page::Ftp(entries) => extract_from_ftp_listing(entries, base_url),
+        Page::WebDav(entries) => extract_from_webdav_listing(entries, base_url),
+        Page::Bucket(listing) => extract_from_bucket_listing(listing, base_url),
+    }
+}
+
+/**
+Keeps the entries of a listing which are within the scope (and not enqueued yet).
+
+Their URLs are brought into their canonical form.
+*/
+fn enqueue_nodes(nodes: Vec<Node>, scope: &Scope) -> Vec<Node> {
+    nodes
+        .into_iter()
+        .filter_map(|mut node| {
+            let is_file = matches!(node, Node::File(_));
+            let url = match &mut node {
+                Node::File(file) => &mut file.url,
+                Node::PendingDir(dir) | Node::CrawledDir(dir, _) => &mut dir.url,
             };
 
-            // Perse the response
-            match cheap_extract_from_html(&html, &Url::from_str(&dir.url)?) {
-                Err(err) => bail!(err),
-                Ok(dir_data) => {
-                    // Replace the PendingDir node with a CrawledDir one
-                    *node = Node::CrawledDir(
-                        DirLinkMetaData {
-                            url: dir.url.clone(), // TODO remove copy
-                            name: dir_data.0,
-                            description: dir.description.clone(), // TODO remove copy
-                            last_modified: dir.last_modified.clone(), // TODO remove copy
-                        },
-                        dir_data.1,
-                    )
+            // Only follow (new) links within the scope
+            let mut href = match scope.enqueue(&Url::parse(url).ok()?, Queue::Crawl) {
+                Some(href) => href,
+                None => {
+                    debug!(event = "skip", reason = "scope", url = url.as_str(); "(Scope) Skip link {}", url);
+                    return None;
                 }
             };
+
+            if is_file {
+                clean_url(&mut href);
+            }
+
+            *url = href.to_string();
+            Some(node)
+        })
+        .collect()
+}
+
+/**
+Names the entries after their URLs where these are encoded in the charset of the listing.
+
+Legacy servers link e.g. `caf%E9.txt` in Latin-1 listings. The link texts may be
+truncated (e.g. `very-long-na..>`), so the downloads rely on these names.
+*/
+fn decode_names(nodes: &mut [Node], encoding: &'static Encoding) {
+    for node in nodes {
+        let (url, name, suffix) = match node {
+            Node::File(file) => (&file.url, &mut file.name, ""),
+            Node::PendingDir(dir) | Node::CrawledDir(dir, _) => (&dir.url, &mut dir.name, "/"),
+        };
+
+        let segment = match Url::parse(url)
+            .ok()
+            .and_then(|url| Some(url.path_segments()?.rfind(|s| !s.is_empty())?.to_owned()))
+        {
+            Some(segment) => segment,
+            None => continue,
+        };
+
+        if percent_decode_str(&segment).decode_utf8().is_err() {
+            *name = format!("{}{}", charset::decode_segment(&segment, encoding), suffix);
         }
     }
+}
+
+/// The outcome of crawling a single directory
+enum Listing {
+    /// The name & entries of the directory (and the hash of its listing)
+    Crawled {
+        name: String,
+        children: Vec<Node>,
+        hash: u64,
+    },
+
+    /// A guard skipped the directory
+    Skipped,
+
+    /// The directory limit is reached (nothing more gets crawled)
+    LimitReached,
+}
+
+/**
+Expand all PengingDir nodes (recursively)
+
+The nodes are expected to be the listing of the root directory (with its hash,
+see `get_root_dir`).
+
+Directories are skipped if they were crawled before (under their canonical URL),
+if they loop back to a parent directory (see `is_loop`), or if their URL is too long. Crawling stops when the directory limit is reached.
+*/
+pub async fn expand_node(
+    nodes: &mut Vec<Node>,
+    root_hash: u64,
+    session: &Session,
+    limits: &CrawlLimits,
+) -> Result<()> {
+    let mut state = CrawlState::new(*limits);
+    let mut ancestors = vec![root_hash];
+
+    let started = Instant::now();
+    expand_level(nodes, session, &mut state, &mut ancestors).await?;
+
+    session.events.emit(Event::CrawlFinished {
+        directories: state.directories,
+        duration_ms: started.elapsed().as_millis() as u64,
+    });
 
     Ok(())
 }
 
+/// Expands the PendingDir nodes of one directory level (and their children)
+fn expand_level<'a>(
+    nodes: &'a mut Vec<Node>,
+    session: &'a Session,
+    state: &'a mut CrawlState,
+    ancestors: &'a mut Vec<u64>,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+    Box::pin(async move {
+        for node in nodes {
+            // Only crawl if needed
+            if let Node::PendingDir(dir) = node {
+                let (name, mut children, hash) =
+                    match crawl_dir(dir, session, state, ancestors).await? {
+                        Listing::Crawled {
+                            name,
+                            children,
+                            hash,
+                        } => (name, children, hash),
+                        Listing::Skipped => continue,
+                        Listing::LimitReached => return Ok(()),
+                    };
+
+                // Crawl the sub-directories
+                ancestors.push(hash);
+                expand_level(&mut children, session, state, ancestors).await?;
+                ancestors.pop();
+
+                // Replace the PendingDir node with a CrawledDir one
+                *node = Node::CrawledDir(
+                    DirLinkMetaData {
+                        url: dir.url.clone(), // TODO remove copy
+                        name,
+                        description: dir.description.clone(), // TODO remove copy
+                        last_modified: dir.last_modified.clone(), // TODO remove copy
+                    },
+                    children,
+                )
+            }
+        }
+
+        Ok(())
+    })
+}
+
+/// A directory waiting to be crawled by a streaming crawl
+struct PendingListing {
+    dir: DirLinkMetaData,
+    path: String,
+
+    /// The listing hashes of the parent directories
+    ancestors: Vec<u64>,
+}
+
+/// The state of a streaming crawl
+struct StreamState {
+    session: Session,
+    crawl: CrawlState,
+
+    /// The root URL (until its listing is fetched)
+    root: Option<Url>,
+
+    /// The entries of the listings crawled so far (not yet yielded)
+    ready: VecDeque<DiscoveredEntry>,
+
+    /// The directories still to crawl (the last one is next)
+    pending: Vec<PendingListing>,
+
+    started: Instant,
+}
+
+impl StreamState {
+    /// Queues the entries of a listing (and its sub-directories for crawling)
+    fn queue(&mut self, parent: &str, children: Vec<Node>, ancestors: &[u64]) {
+        let mut directories = vec![];
+
+        for child in children {
+            match child {
+                Node::File(file) => {
+                    let path = format!("{}{}", parent, entry_name(&file.url, &file.name));
+                    self.ready.push_back(DiscoveredEntry::File { path, file });
+                }
+                Node::PendingDir(dir) => {
+                    let path = format!("{}{}/", parent, entry_name(&dir.url, &dir.name));
+                    self.ready.push_back(DiscoveredEntry::Dir {
+                        path: path.clone(),
+                        dir: dir.clone(),
+                    });
+                    directories.push(PendingListing {
+                        dir,
+                        path,
+                        ancestors: ancestors.to_vec(),
+                    });
+                }
+                Node::CrawledDir(_, _) => {}
+            }
+        }
+
+        // Crawl the sub-directories in the order of the listing (depth first)
+        self.pending.extend(directories.into_iter().rev());
+    }
+}
+
 /**
-Extracts the HTML from the root URL and returns a node
+Crawls the root directory and yields its files & directories as the listings get parsed.
+
+Unlike `expand_node`, no tree is built: only the directories still to be crawled
+are kept in memory, so the entries can be processed while the crawl goes on.
+The same guards & limits apply. The stream ends after the first error.
+Like every crawl, it starts over with the scope (once it gets polled).
 */
-pub async fn get_root_dir(url: &Url, client: &reqwest::Client) -> Result<Node> {
-    println!("Fetching root HTML");
+pub fn stream(
+    url: Url,
+    session: Session,
+    limits: CrawlLimits,
+) -> impl Stream<Item = Result<DiscoveredEntry>> + Send {
+    let state = StreamState {
+        session,
+        crawl: CrawlState::new(limits),
+        root: Some(url),
+        ready: VecDeque::new(),
+        pending: vec![],
+        started: Instant::now(),
+    };
 
-    let res = client
-        .get(url.as_str())
-        .send()
-        .await
-        .unwrap()
-        .text()
-        .await
-        .unwrap();
+    stream::try_unfold(state, |mut state| async move {
+        loop {
+            if let Some(entry) = state.ready.pop_front() {
+                return Ok(Some((entry, state)));
+            }
 
-    // Sanitize the HTML
-    let html = sanitize_html(&res)?;
+            // Start with the listing of the root directory (forgetting the URLs of earlier crawls)
+            if let Some(url) = state.root.take() {
+                state.session.scope.reset(Queue::Crawl);
 
-    println!("Crawling root URL");
+                let (children, hash) = match get_root_dir(&url, &state.session).await? {
+                    (Node::CrawledDir(_, children), hash) => (children, hash),
+                    _ => return Err(OdGetError::State("Cannot expand root node".to_owned())),
+                };
 
-    let root_data = cheap_extract_from_html(&html, url)?;
+                state.queue("", children, &[hash]);
+                continue;
+            }
 
-    Ok(Node::CrawledDir(
-        DirLinkMetaData {
-            url: url.to_string(),
-            name: root_data.0,
-            description: String::new(),
-            last_modified: String::new(),
-        },
-        root_data.1,
+            let pending = match state.pending.pop() {
+                Some(pending) => pending,
+                None => {
+                    state.session.events.emit(Event::CrawlFinished {
+                        directories: state.crawl.directories,
+                        duration_ms: state.started.elapsed().as_millis() as u64,
+                    });
+
+                    return Ok(None);
+                }
+            };
+
+            let listing = crawl_dir(
+                &pending.dir,
+                &state.session,
+                &mut state.crawl,
+                &pending.ancestors,
+            )
+            .await?;
+
+            match listing {
+                Listing::Crawled { children, hash, .. } => {
+                    let mut ancestors = pending.ancestors;
+                    ancestors.push(hash);
+                    state.queue(&pending.path, children, &ancestors);
+                }
+                Listing::Skipped => {}
+                Listing::LimitReached => state.pending.clear(),
+            }
+        }
+    })
+}
+
+/// The (decoded) last segment of a URL, or the name from the listing if it has none
+fn entry_name(url: &str, name: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            url.path_segments()?
+                .rfind(|segment| !segment.is_empty())
+                .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        })
+        .unwrap_or_else(|| name.trim_end_matches('/').to_owned())
+}
+
+/**
+Fetches & parses the listing of a single directory (unless a guard skips it).
+
+The ancestors are the listing hashes of the parent directories (to detect loops).
+*/
+async fn crawl_dir(
+    dir: &DirLinkMetaData,
+    session: &Session,
+    state: &mut CrawlState,
+    ancestors: &[u64],
+) -> Result<Listing> {
+    if let Some(max_length) = state.limits.max_url_length {
+        if dir.url.len() > max_length {
+            skip_dir(session, &dir.url, &dir.name, DirSkipReason::UrlLength);
+            return Ok(Listing::Skipped);
+        }
+    }
+
+    // Don't crawl the same directory twice (e.g. when linked under another URL)
+    let url = Url::parse(&dir.url)?;
+    if !state.visited.insert(scope::normalize(&url).to_string()) {
+        skip_dir(session, &dir.url, &dir.name, DirSkipReason::Duplicate);
+        return Ok(Listing::Skipped);
+    }
+
+    if let Some(max_directories) = state.limits.max_directories {
+        if state.directories >= max_directories {
+            // Only report the limit once (the parent levels end up here too)
+            if !state.limit_reached {
+                skip_dir(session, &dir.url, &dir.name, DirSkipReason::DirectoryLimit);
+                state.limit_reached = true;
+            }
+
+            return Ok(Listing::LimitReached);
+        }
+    }
+
+    state.directories += 1;
+
+    session.events.emit(Event::CrawlingDir {
+        url: dir.url.clone(),
+        name: dir.name.clone(),
+    });
+
+    // Get the listing from the server (links are relative to the URL after redirects)
+    let (base_url, page) = fetch_listing(&url, session).await?;
+
+    // ... or when redirected to it
+    let base = scope::normalize(&base_url).to_string();
+    if base != scope::normalize(&url).as_str() && !state.visited.insert(base) {
+        skip_dir(
+            session,
+            base_url.as_str(),
+            &dir.name,
+            DirSkipReason::Duplicate,
+        );
+        return Ok(Listing::Skipped);
+    }
+
+    session.reporter.directory_crawled();
+
+    // Perse the response
+    let (name, children) = parse_listing(page, &base_url)?;
+
+    // Detect loops (e.g. symlinks to a parent directory) before the scope drops entries
+    let hash = listing_hash(&children);
+    if is_loop(&url, hash, ancestors) {
+        skip_dir(session, &dir.url, &dir.name, DirSkipReason::Loop);
+        return Ok(Listing::Skipped);
+    }
+
+    let children = enqueue_nodes(children, &session.scope);
+
+    emit_discovered(session, &children);
+
+    Ok(Listing::Crawled {
+        name,
+        children,
+        hash,
+    })
+}
+
+/// Reports a directory which won't be crawled
+fn skip_dir(session: &Session, url: &str, name: &str, reason: DirSkipReason) {
+    session.events.emit(Event::DirSkipped {
+        url: url.to_owned(),
+        name: name.to_owned(),
+        reason,
+    });
+}
+
+/// Reports the entries of a listing
+fn emit_discovered(session: &Session, nodes: &[Node]) {
+    for node in nodes {
+        match node {
+            Node::File(file) => session
+                .events
+                .emit(Event::FileDiscovered { file: file.clone() }),
+            Node::PendingDir(dir) => session
+                .events
+                .emit(Event::DirDiscovered { dir: dir.clone() }),
+            Node::CrawledDir(_, _) => {}
+        }
+    }
+}
+
+/**
+Hashes the contents of a directory listing (names, sizes & dates of its entries).
+
+URLs are left out, so the same listing served under a different path has the same hash.
+*/
+fn listing_hash(nodes: &[Node]) -> u64 {
+    let mut entries: Vec<(&str, &str, &str)> = nodes
+        .iter()
+        .map(|node| match node {
+            Node::File(file) => (
+                file.name.as_str(),
+                file.size.as_str(),
+                file.last_modified.as_str(),
+            ),
+            Node::PendingDir(dir) | Node::CrawledDir(dir, _) => {
+                (dir.name.as_str(), "", dir.last_modified.as_str())
+            }
+        })
+        .collect();
+
+    entries.sort_unstable();
+
+    let mut hasher = DefaultHasher::new();
+    entries.hash(&mut hasher);
+    hasher.finish()
+}
+
+/**
+Checks if a directory loops back to a parent directory (e.g. a symlink `loop -> ..`).
+
+Nested directories may legitimately list the same entries as their parent, so a loop
+also needs the URL to repeat a directory name: the listing of `a/b/loop/` repeats
+the one of `a/`, but only `a/b/loop/b/` (repeating `a/b/`) is skipped.
+*/
+fn is_loop(url: &Url, hash: u64, ancestors: &[u64]) -> bool {
+    if !ancestors.contains(&hash) {
+        return false;
+    }
+
+    let segments: Vec<&str> = match url.path_segments() {
+        Some(segments) => segments.filter(|segment| !segment.is_empty()).collect(),
+        None => return false,
+    };
+
+    match segments.split_last() {
+        Some((name, parents)) => parents.contains(name),
+        None => false,
+    }
+}
+
+/**
+Extracts the listing from the root URL and returns a node
+
+The hash of the listing (of all its entries, see `expand_node`) is returned along the node.
+*/
+pub async fn get_root_dir(url: &Url, session: &Session) -> Result<(Node, u64)> {
+    session.events.emit(Event::CrawlStarted {
+        url: url.to_string(),
+    });
+
+    // Never crawl the root again (e.g. when a listing links to itself)
+    session.scope.enqueue(url, Queue::Crawl);
+
+    // Links are relative to the URL after redirects
+    let (base_url, page) = fetch_listing(url, session).await?;
+    session.reporter.directory_crawled();
+
+    debug!("Crawling root URL");
+
+    let (name, children) = parse_listing(page, &base_url)?;
+    let hash = listing_hash(&children);
+    let children = enqueue_nodes(children, &session.scope);
+    emit_discovered(session, &children);
+
+    Ok((
+        Node::CrawledDir(
+            DirLinkMetaData {
+                url: url.to_string(),
+                name,
+                description: String::new(),
+                last_modified: String::new(),
+            },
+            children,
+        ),
+        hash,
     ))
 }
 
 /**
-Sanitize the HTML (use String::from_utf8 to check for valid UTF-8, the library is unsafe)
+Sanitize the HTML (decode the entities, the text is already decoded from its charset)
 */
-pub fn sanitize_html(text: &str) -> Result<String> {
-    let mut output = Vec::new();
-    decode_html_entities_to_vec(text, &mut output);
-    Ok(String::from_utf8(output)?)
+pub fn sanitize_html(text: &str) -> String {
+    decode_html_entities(text).into_owned()
 }
 
 /// Clear a lot of trailing slashes
-fn clean_url(url: &mut Url) -> () {
+fn clean_url(url: &mut Url) {
     // TODO Improve this
-    url.path_segments_mut()
-        .expect("Cannot use URL")
+    // (URLs which cannot be a base, e.g. `mailto:` links, have no segments)
+    let mut segments = match url.path_segments_mut() {
+        Ok(segments) => segments,
+        Err(()) => return,
+    };
+
+    segments
         .pop_if_empty()
         .pop_if_empty()
         .pop_if_empty()
@@ -227,3 +1077,258 @@ fn clean_url(url: &mut Url) -> () {
         .pop_if_empty()
         .pop_if_empty();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::test_server::{Reply, TestServer};
+    use futures::TryStreamExt;
+
+    fn file(url: &str, name: &str) -> Node {
+        Node::File(FileLinkMetaData {
+            url: url.to_owned(),
+            name: name.to_owned(),
+            last_modified: String::new(),
+            size: String::new(),
+            description: String::new(),
+            etag: String::new(),
+        })
+    }
+
+    fn dir(url: &str, name: &str) -> Node {
+        Node::PendingDir(DirLinkMetaData {
+            url: url.to_owned(),
+            name: name.to_owned(),
+            last_modified: String::new(),
+            description: String::new(),
+        })
+    }
+
+    fn name(node: &Node) -> &str {
+        match node {
+            Node::File(file) => &file.name,
+            Node::PendingDir(dir) | Node::CrawledDir(dir, _) => &dir.name,
+        }
+    }
+
+    #[test]
+    fn decodes_names_in_the_charset_of_the_listing() {
+        let mut nodes = vec![
+            file("http://host/pub/%E9t%E9.txt", "\u{e9}t\u{e9}.txt"),
+            file(
+                "http://host/pub/%E9t%E9-et-long-nom.txt",
+                "\u{e9}t\u{e9}-et-lo..>",
+            ),
+            dir("http://host/pub/caf%E9/", "caf?/"),
+            file("http://host/pub/%C3%A9t%C3%A9.txt", "summer.txt"),
+            file("http://host/pub/a%20b.txt", "a..>"),
+        ];
+        decode_names(&mut nodes, encoding_rs::WINDOWS_1252);
+
+        let names: Vec<&str> = nodes.iter().map(name).collect();
+        assert_eq!(
+            names,
+            [
+                "été.txt",
+                "été-et-long-nom.txt",
+                "café/",
+                // Links encoded in UTF-8 keep their listed names
+                "summer.txt",
+                "a..>",
+            ]
+        );
+    }
+
+    fn next_page(html: &str, page_url: &str) -> Option<String> {
+        next_page_url(html, &Url::parse(page_url).unwrap()).map(|url| url.to_string())
+    }
+
+    #[test]
+    fn follows_links_marked_as_next() {
+        let html = r#"<a href="?page=1">1</a> <a class="btn" rel="next" href='?p=b'>→</a>"#;
+        assert_eq!(
+            next_page(html, "http://host/pub/?p=a"),
+            Some("http://host/pub/?p=b".to_owned())
+        );
+
+        let html = r#"<link rel=next href="/pub/?offset=50">"#;
+        assert_eq!(
+            next_page(html, "http://host/pub/"),
+            Some("http://host/pub/?offset=50".to_owned())
+        );
+    }
+
+    #[test]
+    fn follows_links_labelled_as_next() {
+        for label in ["Next", "next page ›", "»", " Next » "] {
+            let html = format!(
+                r#"<a href="?page=1">Previous</a> <a href="?from=z">{}</a>"#,
+                label
+            );
+            assert_eq!(
+                next_page(&html, "http://host/pub/?page=2"),
+                Some("http://host/pub/?from=z".to_owned()),
+                "{}",
+                label
+            );
+        }
+    }
+
+    #[test]
+    fn follows_the_next_page_number() {
+        let html = r#"<a href="?page=1">1</a> <a href="?page=2">2</a> <a href="?page=3">3</a>"#;
+
+        assert_eq!(
+            next_page(html, "http://host/pub/"),
+            Some("http://host/pub/?page=2".to_owned())
+        );
+        assert_eq!(
+            next_page(html, "http://host/pub/?page=2"),
+            Some("http://host/pub/?page=3".to_owned())
+        );
+        assert_eq!(next_page(html, "http://host/pub/?page=3"), None);
+    }
+
+    #[test]
+    fn never_follows_sort_links() {
+        let html = r#"<a href="?C=N;O=D">Name</a> <a rel="next" href="?C=M;O=A">»</a>"#;
+        assert_eq!(next_page(html, "http://host/pub/"), None);
+
+        assert!(is_sort_link(
+            &Url::parse("http://host/pub/?C=N;O=D").unwrap()
+        ));
+        assert!(is_sort_link(
+            &Url::parse("http://host/pub/?page=2&O=A").unwrap()
+        ));
+        assert!(!is_sort_link(
+            &Url::parse("http://host/pub/?page=2").unwrap()
+        ));
+        assert!(!is_sort_link(&Url::parse("http://host/pub/").unwrap()));
+    }
+
+    #[test]
+    fn never_follows_other_directories() {
+        let html = r#"<a rel="next" href="/other/?page=2">Next</a>
+            <a href="next/">Next</a>
+            <a href="https://host/pub/?page=2">2</a>
+            <a href="http://mirror/pub/?page=2">2</a>
+            <a href="?page=1">1</a>"#;
+
+        assert_eq!(next_page(html, "http://host/pub/?page=1"), None);
+    }
+
+    #[test]
+    fn detects_loops_by_listing_and_path() {
+        let url = |url: &str| Url::parse(url).unwrap();
+        let ancestors = [1, 2, 3];
+
+        assert!(is_loop(&url("http://host/pub/a/b/loop/b/"), 2, &ancestors));
+        assert!(is_loop(&url("http://host/pub/self/self"), 3, &ancestors));
+
+        // A nested directory listing the same entries as its parent
+        assert!(!is_loop(&url("http://host/pub/a/b/loop/"), 2, &ancestors));
+        assert!(!is_loop(&url("http://host/pub/data/"), 1, &ancestors));
+
+        // A repeated name with another listing
+        assert!(!is_loop(&url("http://host/pub/a/b/a/"), 4, &ancestors));
+    }
+
+    /// An Apache index of a directory (names ending with `/` are directories)
+    fn index(path: &str, entries: &[&str]) -> Reply {
+        let rows: String = entries
+            .iter()
+            .map(|name| {
+                let size = if name.ends_with('/') { "  - " } else { "  1K" };
+                format!(
+                    "<tr><td valign=\"top\"></td><td><a href=\"{0}\">{0}</a></td>\
+                     <td align=\"right\">2024-01-31 12:00  </td><td align=\"right\">{1}</td>\
+                     <td>&nbsp;</td></tr>\n",
+                    name, size
+                )
+            })
+            .collect();
+        let html = format!(
+            "<html><body>\n<h1>Index of {}</h1>\n<table>\n{}</table></body></html>\n",
+            path, rows
+        );
+
+        Reply::new(200, html).header("content-type", "text/html")
+    }
+
+    /// Crawls a server & returns the paths of the files (sorted)
+    async fn crawled_files(server: &TestServer) -> Vec<String> {
+        crawl_files(server.url("/pub/"), server.session("/pub/")).await
+    }
+
+    async fn crawl_files(url: Url, session: Session) -> Vec<String> {
+        let entries: Vec<DiscoveredEntry> = stream(url, session, CrawlLimits::default())
+            .try_collect()
+            .await
+            .unwrap();
+
+        let mut files: Vec<String> = entries
+            .into_iter()
+            .filter_map(|entry| match entry {
+                DiscoveredEntry::File { path, .. } => Some(path),
+                DiscoveredEntry::Dir { .. } => None,
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[tokio::test]
+    async fn stops_at_symlink_loops() {
+        // `/pub/a/b/loop` links to `/pub/a/`
+        let server = TestServer::start(|request| {
+            let mut path = request.target.clone();
+            while let Some(index) = path.find("/b/loop/") {
+                path.replace_range(index + 1..index + 8, "");
+            }
+
+            match path.as_str() {
+                "/pub/" => index(&path, &["a/"]),
+                "/pub/a/" => index(&path, &["b/", "big.bin"]),
+                "/pub/a/b/" => index(&path, &["deep.txt", "loop/"]),
+                _ => Reply::new(404, "Not found"),
+            }
+        })
+        .await;
+
+        assert_eq!(
+            crawled_files(&server).await,
+            ["a/b/deep.txt", "a/b/loop/big.bin", "a/big.bin"]
+        );
+        assert_eq!(server.requests().len(), 5);
+    }
+
+    #[tokio::test]
+    async fn crawls_nested_directories_with_the_same_entries() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/pub/" => index("/pub/", &["same/"]),
+            "/pub/same/" => index("/pub/same/", &["same/"]),
+            "/pub/same/same/" => index("/pub/same/same/", &["data.bin"]),
+            _ => Reply::new(404, "Not found"),
+        })
+        .await;
+
+        assert_eq!(crawled_files(&server).await, ["same/same/data.bin"]);
+    }
+
+    #[tokio::test]
+    async fn streams_start_over_with_the_scope() {
+        let server = TestServer::start(|request| match request.target.as_str() {
+            "/pub/" => index("/pub/", &["a.txt", "sub/"]),
+            "/pub/sub/" => index("/pub/sub/", &["b.txt"]),
+            _ => Reply::new(404, "Not found"),
+        })
+        .await;
+        let session = server.session("/pub/");
+
+        // The second crawl starts with the URLs of the first one enqueued
+        for _ in 0..2 {
+            let files = crawl_files(server.url("/pub/"), session.clone()).await;
+            assert_eq!(files, ["a.txt", "sub/b.txt"]);
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::io;
+use thiserror::Error;
+
+/**
+The errors of the `download` module.
+
+The library never panics on bad input (e.g. unexpected listings or responses),
+it returns one of these instead.
+*/
+#[derive(Debug, Error)]
+pub enum OdGetError {
+    /// A request failed (e.g. connection errors, timeouts or error responses)
+    #[error(transparent)]
+    Network(#[from] reqwest::Error),
+
+    /// An FTP server refused a command or sent an unexpected reply
+    #[error("{0}")]
+    Ftp(String),
+
+    /// A URL couldn't be parsed (or resolved against the URL of its listing)
+    #[error("Invalid URL: {0}")]
+    Url(#[from] url::ParseError),
+
+    /// A listing, response or value couldn't be parsed
+    #[error("{0}")]
+    Parse(String),
+
+    /// Reading or writing a file failed
+    #[error(transparent)]
+    Io(#[from] io::Error),
+
+    /// A request would leave the scope of the crawl (e.g. by a redirect)
+    #[error("{0}")]
+    Scope(String),
+
+    /// The state store is invalid, incompatible or not in the expected state
+    #[error("{0}")]
+    State(String),
+
+    /// The options are invalid or incomplete (e.g. without a root URL)
+    #[error("{0}")]
+    Config(String),
+
+    /// The credentials couldn't be used
+    #[error("{0}")]
+    Auth(String),
+
+    /// A storage sink couldn't take a file (e.g. an object store rejected it)
+    #[error("{0}")]
+    Storage(String),
+}
+
+impl From<serde_json::Error> for OdGetError {
+    fn from(err: serde_json::Error) -> Self {
+        if err.is_io() {
+            Self::Io(err.into())
+        } else {
+            Self::State(format!("Invalid JSON: {}", err))
+        }
+    }
+}
+
+/// The result type of the `download` module
+pub type Result<T, E = OdGetError> = std::result::Result<T, E>;
@@ -0,0 +1,198 @@
+use super::{
+    report::SkipReason,
+    types::{DirLinkMetaData, FileLinkMetaData},
+};
+use serde::Serialize;
+use std::{
+    fmt::{self, Debug, Display},
+    sync::{Arc, PoisonError, RwLock},
+};
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Why a directory isn't crawled (or downloaded from)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DirSkipReason {
+    /// The URL of the directory is longer than the limit
+    UrlLength,
+
+    /// The directory limit of the crawl was reached
+    DirectoryLimit,
+
+    /// The directory was crawled before (e.g. under another URL redirecting to it)
+    Duplicate,
+
+    /// The listing is the same as the one of a parent directory (e.g. a symlink loop)
+    Loop,
+
+    /// The directory name matches the path filter
+    PathFilter,
+
+    /// The directory name doesn't match the path matcher
+    PathMatcher,
+
+    /// The directory wasn't crawled (so its files are unknown)
+    NotCrawled,
+}
+
+impl DirSkipReason {
+    /// The name used in the structured log events
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::UrlLength => "url_length",
+            Self::DirectoryLimit => "directory_limit",
+            Self::Duplicate => "duplicate",
+            Self::Loop => "loop",
+            Self::PathFilter => "path_filter",
+            Self::PathMatcher => "path_matcher",
+            Self::NotCrawled => "not_crawled",
+        }
+    }
+}
+
+impl Display for DirSkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::UrlLength | Self::DirectoryLimit | Self::Duplicate | Self::Loop => "Guard",
+            Self::PathFilter => "Filter",
+            Self::PathMatcher => "Matcher",
+            Self::NotCrawled => "Skip",
+        })
+    }
+}
+
+/**
+Something which happened during a crawl or download.
+
+File names are the last segments of the URLs (as used on disk).
+*/
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The crawl of a root URL started
+    CrawlStarted { url: String },
+
+    /// The listing of a directory is being fetched
+    CrawlingDir { url: String, name: String },
+
+    /// A listing contains a (not yet crawled) directory
+    DirDiscovered { dir: DirLinkMetaData },
+
+    /// A listing contains a file
+    FileDiscovered { file: FileLinkMetaData },
+
+    /// A directory is left out
+    DirSkipped {
+        url: String,
+        name: String,
+        reason: DirSkipReason,
+    },
+
+    /// The crawl is complete
+    CrawlFinished { directories: u64, duration_ms: u64 },
+
+    /// The download of a crawl starts (with the number of files found and their estimated size)
+    DownloadPlanned { files: u64, bytes: u64 },
+
+    /// A download has started (with its size, if known)
+    DownloadStarted {
+        url: String,
+        name: String,
+        bytes: Option<u64>,
+    },
+
+    /// Some more bytes of a download were written
+    DownloadProgress { url: String, bytes: u64 },
+
+    /// A download is complete
+    DownloadFinished {
+        url: String,
+        name: String,
+        bytes: u64,
+    },
+
+    /// A download failed (the file is marked as failed)
+    DownloadFailed {
+        url: String,
+        name: String,
+        error: String,
+    },
+
+    /// A file won't be downloaded (with its estimated size)
+    Skipped {
+        url: String,
+        name: String,
+        reason: SkipReason,
+        bytes: Option<u64>,
+    },
+
+    /// The download of a crawl is over
+    Finished,
+}
+
+/// A function receiving events
+pub type EventHandler = Arc<dyn Fn(&Event) + Send + Sync>;
+
+/**
+Delivers the events of a run to the registered handlers.
+
+Handlers are called synchronously (in the order they were registered), so they
+should return quickly. Clones share their handlers.
+*/
+#[derive(Clone, Default)]
+pub struct Events {
+    handlers: Arc<RwLock<Vec<EventHandler>>>,
+}
+
+impl Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("handlers", &self.handlers().len())
+            .finish()
+    }
+}
+
+impl Events {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls the handler for every event from now on
+    pub fn on(&self, handler: impl Fn(&Event) + Send + Sync + 'static) {
+        self.add(Arc::new(handler));
+    }
+
+    /// Registers a (shared) handler
+    pub fn add(&self, handler: EventHandler) {
+        self.handlers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(handler);
+    }
+
+    /// Returns a channel receiving every event from now on (until the receiver is dropped)
+    pub fn subscribe(&self) -> UnboundedReceiver<Event> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        self.on(move |event| {
+            // A dropped receiver just doesn't want any more events
+            let _ = sender.send(event.clone());
+        });
+
+        receiver
+    }
+
+    /// Delivers an event to all handlers
+    pub fn emit(&self, event: Event) {
+        for handler in self.handlers().iter() {
+            handler(&event);
+        }
+    }
+
+    fn handlers(&self) -> Vec<EventHandler> {
+        self.handlers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
@@ -0,0 +1,469 @@
+use super::{
+    error::{OdGetError, Result},
+    fetch,
+    types::{FileLinkMetaData, Node},
+};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use percent_encoding::percent_decode_str;
+use reqwest::Url;
+use std::{io::Write, str::FromStr};
+
+/// The formats a crawl can be exported to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One URL per line
+    Urls,
+
+    /// An aria2c input file (`aria2c -i`) with `dir=` & `out=` options per URL
+    Aria2c,
+
+    /// Comma-separated values with the metadata of every file
+    Csv,
+
+    /// Tab-separated values with the metadata of every file
+    Tsv,
+
+    /// A Metalink 4 (RFC 5854) XML document
+    Metalink,
+
+    /// A static HTML page listing every file
+    Html,
+}
+
+impl FromStr for ExportFormat {
+    type Err = OdGetError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "urls" => Ok(Self::Urls),
+            "aria2c" => Ok(Self::Aria2c),
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            "metalink" => Ok(Self::Metalink),
+            "html" => Ok(Self::Html),
+            other => Err(OdGetError::Parse(format!(
+                "Invalid export format \"{}\" (must be one of {})",
+                other,
+                ExportFormat::NAMES.join(", ")
+            ))),
+        }
+    }
+}
+
+impl ExportFormat {
+    /// The names of all formats (as accepted by `from_str`)
+    pub const NAMES: [&'static str; 6] = ["urls", "aria2c", "csv", "tsv", "metalink", "html"];
+}
+
+/// A file of the crawl along with its path relative to the root directory
+struct Entry<'a> {
+    file: &'a FileLinkMetaData,
+
+    /// The (decoded) directory of the file relative to the root directory (empty for the root)
+    dir: String,
+
+    /// The (decoded) file name
+    name: String,
+}
+
+impl Entry<'_> {
+    fn path(&self) -> String {
+        if self.dir.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{}/{}", self.dir, self.name)
+        }
+    }
+}
+
+/**
+Writes the files of a crawled tree in the given format.
+
+Paths are relative to the directory of the root URL (files on other hosts are put
+below a directory named after their host). The `destination` is only used for
+the `dir=` options of aria2c.
+*/
+pub fn export(
+    root: &Node,
+    format: ExportFormat,
+    destination: &str,
+    out: &mut dyn Write,
+) -> Result<()> {
+    let root_url = match root {
+        Node::CrawledDir(meta, _) => Url::parse(&meta.url)?,
+        _ => {
+            return Err(OdGetError::State(
+                "Can only export a crawled directory".to_owned(),
+            ))
+        }
+    };
+
+    let mut entries = vec![];
+    for file in root.files() {
+        let (dir, name) = relative_path(&root_url, &Url::parse(&file.url)?);
+        entries.push(Entry { file, dir, name });
+    }
+
+    match format {
+        ExportFormat::Urls => {
+            for entry in &entries {
+                writeln!(out, "{}", entry.file.url)?;
+            }
+        }
+        ExportFormat::Aria2c => {
+            for entry in &entries {
+                let dir = if entry.dir.is_empty() {
+                    destination.to_owned()
+                } else {
+                    format!("{}/{}", destination.trim_end_matches('/'), entry.dir)
+                };
+
+                writeln!(out, "{}", entry.file.url)?;
+                writeln!(out, "  dir={}", dir)?;
+                writeln!(out, "  out={}", entry.name)?;
+            }
+        }
+        ExportFormat::Csv => write_table(&entries, out, ",", csv_field)?,
+        ExportFormat::Tsv => write_table(&entries, out, "\t", tsv_field)?,
+        ExportFormat::Metalink => write_metalink(&entries, out)?,
+        ExportFormat::Html => write_html(&root_url, &entries, out)?,
+    }
+
+    Ok(())
+}
+
+/// Splits the path of a file URL (relative to the root directory) into its directory & name
+fn relative_path(root_url: &Url, url: &Url) -> (String, String) {
+    let root_path = &root_url.path()[..=root_url.path().rfind('/').unwrap_or(0)];
+
+    let relative = if url.host_str() == root_url.host_str() && url.path().starts_with(root_path) {
+        url.path()[root_path.len()..].to_owned()
+    } else {
+        format!("{}{}", url.host_str().unwrap_or("unknown"), url.path())
+    };
+
+    // Decode every segment on its own (an encoded `/` must not add a directory)
+    let mut segments: Vec<&str> = relative.split('/').collect();
+    let name = segments.pop().unwrap_or_default();
+    let dir: Vec<String> = segments
+        .into_iter()
+        .filter(|segment| !segment.is_empty())
+        .map(decode_segment)
+        .collect();
+
+    (dir.join("/"), decode_segment(name))
+}
+
+/// Percent-decodes a segment of a path into a name like the downloader stores it
+fn decode_segment(segment: &str) -> String {
+    fetch::safe_name(&percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// The header of the CSV/TSV exports
+const TABLE_COLUMNS: [&str; 8] = [
+    "url",
+    "path",
+    "name",
+    "size",
+    "size_bytes",
+    "last_modified",
+    "description",
+    "etag",
+];
+
+/// Writes a table (with a header) of the files & their metadata
+fn write_table(
+    entries: &[Entry],
+    out: &mut dyn Write,
+    separator: &str,
+    field: fn(&str) -> String,
+) -> Result<()> {
+    writeln!(out, "{}", TABLE_COLUMNS.join(separator))?;
+
+    for entry in entries {
+        let file = entry.file;
+        let size_bytes = file
+            .size_in_bytes()
+            .map(|size| size.to_string())
+            .unwrap_or_default();
+
+        let row: Vec<String> = [
+            file.url.as_str(),
+            &entry.path(),
+            &entry.name,
+            file.size.trim(),
+            &size_bytes,
+            file.last_modified.trim(),
+            file.description.trim(),
+            file.etag.as_str(),
+        ]
+        .iter()
+        .map(|value| field(value))
+        .collect();
+
+        writeln!(out, "{}", row.join(separator))?;
+    }
+
+    Ok(())
+}
+
+/// Quotes a CSV field if needed (RFC 4180)
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+/// TSV can't quote, so tabs & line breaks become spaces
+fn tsv_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+/// Writes a Metalink 4 document (sizes are only included if the listing states them exactly)
+fn write_metalink(entries: &[Entry], out: &mut dyn Write) -> Result<()> {
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+    writeln!(out, r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">"#)?;
+    writeln!(
+        out,
+        "  <generator>od-get/{}</generator>",
+        env!("CARGO_PKG_VERSION")
+    )?;
+
+    for entry in entries {
+        writeln!(
+            out,
+            r#"  <file name="{}">"#,
+            encode_double_quoted_attribute(&entry.path())
+        )?;
+
+        if let Ok(size) = entry.file.size.trim().parse::<u64>() {
+            writeln!(out, "    <size>{}</size>", size)?;
+        }
+
+        writeln!(out, "    <url>{}</url>", encode_text(&entry.file.url))?;
+        writeln!(out, "  </file>")?;
+    }
+
+    writeln!(out, "</metalink>")?;
+
+    Ok(())
+}
+
+/// Writes a static HTML page with a table of all files
+fn write_html(root_url: &Url, entries: &[Entry], out: &mut dyn Write) -> Result<()> {
+    let title = format!("Index of {}", root_url);
+
+    writeln!(out, "<!DOCTYPE html>")?;
+    writeln!(
+        out,
+        r#"<html><head><meta charset="utf-8"><title>{}</title></head>"#,
+        encode_text(&title)
+    )?;
+    writeln!(out, "<body>")?;
+    writeln!(out, "<h1>{}</h1>", encode_text(&title))?;
+    writeln!(out, "<table>")?;
+    writeln!(
+        out,
+        "<tr><th>Name</th><th>Last modified</th><th>Size</th><th>Description</th></tr>"
+    )?;
+
+    for entry in entries {
+        let file = entry.file;
+        writeln!(
+            out,
+            r#"<tr><td><a href="{}">{}</a></td><td>{}</td><td align="right">{}</td><td>{}</td></tr>"#,
+            encode_double_quoted_attribute(&file.url),
+            encode_text(&entry.path()),
+            encode_text(file.last_modified.trim()),
+            encode_text(file.size.trim()),
+            encode_text(file.description.trim())
+        )?;
+    }
+
+    writeln!(out, "</table>")?;
+    writeln!(out, "<p>{} files</p>", entries.len())?;
+    writeln!(out, "</body></html>")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::types::DirLinkMetaData;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn file(url: &str, size: &str, description: &str) -> Node {
+        Node::File(FileLinkMetaData {
+            url: url.to_owned(),
+            name: String::new(),
+            last_modified: "2024-01-31 12:00".to_owned(),
+            size: size.to_owned(),
+            description: description.to_owned(),
+            etag: String::new(),
+        })
+    }
+
+    /// A crawl of `/pub/` with a file named `a,"<&.txt`, one in a subdirectory & one on another host
+    fn crawl() -> Node {
+        let dir = |url: &str, children| {
+            Node::CrawledDir(
+                DirLinkMetaData {
+                    url: url.to_owned(),
+                    name: String::new(),
+                    last_modified: String::new(),
+                    description: String::new(),
+                },
+                children,
+            )
+        };
+
+        dir(
+            "http://host/pub/",
+            vec![
+                file(
+                    "http://host/pub/a%2C%22%3C%26.txt",
+                    "12",
+                    "Tom & \"Jerry\" <3",
+                ),
+                dir(
+                    "http://host/pub/sub%20dir/",
+                    vec![file("http://host/pub/sub%20dir/b.iso", "1.5K", "")],
+                ),
+                file("http://mirror/files/c.txt", "", ""),
+            ],
+        )
+    }
+
+    fn exported(format: ExportFormat) -> String {
+        let mut out = vec![];
+        export(&crawl(), format, "/data/", &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn splits_relative_paths() {
+        let root = url("http://host/pub/");
+
+        assert_eq!(
+            relative_path(&root, &url("http://host/pub/a.txt")),
+            (String::new(), "a.txt".to_owned())
+        );
+        assert_eq!(
+            relative_path(&root, &url("http://host/pub/sub%20dir/b%2Fc.txt")),
+            ("sub dir".to_owned(), "b_c.txt".to_owned())
+        );
+        assert_eq!(
+            relative_path(&url("http://host/pub"), &url("http://host/pub2/x.txt")),
+            ("pub2".to_owned(), "x.txt".to_owned())
+        );
+        assert_eq!(
+            relative_path(&root, &url("http://mirror/pub/a.txt")),
+            ("mirror/pub".to_owned(), "a.txt".to_owned())
+        );
+        assert_eq!(
+            relative_path(&root, &url("http://host/other/a.txt")),
+            ("host/other".to_owned(), "a.txt".to_owned())
+        );
+    }
+
+    #[test]
+    fn quotes_fields() {
+        assert_eq!(csv_field("a.txt"), "a.txt");
+        assert_eq!(csv_field(r#"a,"<&.txt"#), r#""a,""<&.txt""#);
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(tsv_field("a\tb\r\nc"), "a b  c");
+    }
+
+    #[test]
+    fn exports_urls() {
+        assert_eq!(
+            exported(ExportFormat::Urls),
+            "http://host/pub/a%2C%22%3C%26.txt\n\
+             http://host/pub/sub%20dir/b.iso\n\
+             http://mirror/files/c.txt\n"
+        );
+    }
+
+    #[test]
+    fn exports_aria2c_inputs() {
+        assert_eq!(
+            exported(ExportFormat::Aria2c),
+            "http://host/pub/a%2C%22%3C%26.txt\n  dir=/data/\n  out=a,\"<&.txt\n\
+             http://host/pub/sub%20dir/b.iso\n  dir=/data/sub dir\n  out=b.iso\n\
+             http://mirror/files/c.txt\n  dir=/data/mirror/files\n  out=c.txt\n"
+        );
+    }
+
+    #[test]
+    fn exports_csv() {
+        let csv = exported(ExportFormat::Csv);
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines[0], TABLE_COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            r#"http://host/pub/a%2C%22%3C%26.txt,"a,""<&.txt","a,""<&.txt",12,12,2024-01-31 12:00,"Tom & ""Jerry"" <3","#
+        );
+        assert_eq!(
+            lines[2],
+            "http://host/pub/sub%20dir/b.iso,sub dir/b.iso,b.iso,1.5K,1536,2024-01-31 12:00,,"
+        );
+    }
+
+    #[test]
+    fn escapes_metalink_documents() {
+        let metalink = exported(ExportFormat::Metalink);
+
+        assert!(metalink.contains(
+            "  <file name=\"a,&quot;&lt;&amp;.txt\">\n    <size>12</size>\n    \
+             <url>http://host/pub/a%2C%22%3C%26.txt</url>\n  </file>"
+        ));
+        // Rounded sizes are left out
+        assert!(metalink.contains(
+            "  <file name=\"sub dir/b.iso\">\n    <url>http://host/pub/sub%20dir/b.iso</url>"
+        ));
+
+        // The names survive a round trip through an XML parser
+        let mut reader = quick_xml::Reader::from_str(&metalink);
+        let mut names = vec![];
+        loop {
+            match reader.read_event().unwrap() {
+                quick_xml::events::Event::Start(element) if element.name().as_ref() == b"file" => {
+                    let name = element.try_get_attribute("name").unwrap().unwrap();
+                    names.push(name.unescape_value().unwrap().into_owned());
+                }
+                quick_xml::events::Event::Eof => break,
+                _ => {}
+            }
+        }
+        assert_eq!(
+            names,
+            [r#"a,"<&.txt"#, "sub dir/b.iso", "mirror/files/c.txt"]
+        );
+    }
+
+    #[test]
+    fn escapes_html_pages() {
+        let html = exported(ExportFormat::Html);
+
+        assert!(html.contains("<title>Index of http://host/pub/</title>"));
+        assert!(html.contains(
+            "<tr><td><a href=\"http://host/pub/a%2C%22%3C%26.txt\">a,\"&lt;&amp;.txt</a></td>\
+             <td>2024-01-31 12:00</td><td align=\"right\">12</td>\
+             <td>Tom &amp; \"Jerry\" &lt;3</td></tr>"
+        ));
+        assert!(html.contains("<p>3 files</p>"));
+    }
+
+    #[test]
+    fn refuses_unknown_formats() {
+        assert_eq!("csv".parse::<ExportFormat>().unwrap(), ExportFormat::Csv);
+        assert!("wget".parse::<ExportFormat>().is_err());
+    }
+}
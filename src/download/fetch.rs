@@ -1,11 +1,48 @@
-use crate::cli::CliOptions;
+use super::{
+    compression::{self, Decoder},
+    error::{OdGetError, Result},
+    events::{DirSkipReason, Event},
+    ftp,
+    report::SkipReason,
+    scope::Queue,
+    session::Session,
+    stats::Selection,
+    storage::StorageSink,
+    store::StatusMap,
+    types,
+};
+use log::{debug, info};
+use percent_encoding::percent_decode_str;
+use reqwest::{self, Method, Url};
+use std::{str::FromStr, sync::Arc};
+use types::{FileStatus, Node};
+
+/**
+Which files of a crawl to download, and where to.
+
+The files are stored below a directory named after the root directory
+(e.g. `pub/` for `https://example.com/pub/`).
+*/
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// The URL of the root directory
+    pub url: Url,
+
+    /// Receives the downloaded files (e.g. a `LocalSink` writing them to a directory)
+    pub storage: Arc<dyn StorageSink>,
+
+    /// The filters & matchers for the names of files and directories
+    pub selection: Selection,
+
+    /// The maximum number of files to download
+    pub limit_count: Option<u64>,
 
-use super::types;
-use anyhow::{bail, Result};
-use reqwest::{self, Url};
-use std::{path::Path, str::FromStr};
-use tokio::{fs, io::AsyncWriteExt};
-use types::Node;
+    /// The number of files to skip (before downloading any)
+    pub skip_count: Option<u64>,
+
+    /// The maximum number of directories to download from
+    pub recursion_limit: Option<u64>,
+}
 
 /// Several counter variables used to keep track of limits
 #[derive(Debug, Clone)]
@@ -15,6 +52,12 @@ pub struct LimitCounts {
     skipped_files: u64,
 }
 
+impl Default for LimitCounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LimitCounts {
     pub fn new() -> Self {
         Self {
@@ -27,168 +70,330 @@ impl LimitCounts {
 
 pub enum DownloadRecursiveStatus<'a> {
     Done,
-    Do(Vec<(&'a Node, &'a CliOptions, &'a reqwest::Client)>),
+    /// The sub-directories still to download, along their folders within the storage
+    Do(Vec<(&'a Node, String)>),
+}
+
+/// What to do with a file of a listing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Download,
+    Skip(SkipReason),
+}
+
+/**
+Decides whether to download a file by applying the rules in order:
+the file filter, the file matcher, the skip count, the file limit,
+and the status from earlier runs (done or failed).
+
+Files downloaded before still count towards the skip count & the limit,
+so a resumed run selects the same files as the first one.
+*/
+pub fn decide(
+    name: &str,
+    url: &str,
+    options: &DownloadOptions,
+    counters: &mut LimitCounts,
+    statuses: &StatusMap,
+) -> Decision {
+    // Skip unwanted files
+    if let Some(regex) = &options.selection.file_filter {
+        if regex.is_match(name) {
+            return Decision::Skip(SkipReason::Filter);
+        }
+    }
+
+    // Only download wanted files
+    if let Some(regex) = &options.selection.file_matcher {
+        if !regex.is_match(name) {
+            return Decision::Skip(SkipReason::Matcher);
+        }
+    }
+
+    // Skip files if desired
+    if let Some(skip) = options.skip_count {
+        if counters.skipped_files < skip {
+            counters.skipped_files += 1;
+            return Decision::Skip(SkipReason::SkipCount);
+        }
+    }
+
+    // Check for the download limit
+    if let Some(file_limit) = options.limit_count {
+        if counters.file_count >= file_limit {
+            return Decision::Skip(SkipReason::Limit);
+        }
+    }
+
+    counters.file_count += 1;
+
+    if statuses.is_done(url) {
+        return Decision::Skip(SkipReason::AlreadyDone);
+    }
+
+    // Files which failed before are only retried with --retry-failed
+    if statuses.is_failed(url) {
+        return Decision::Skip(SkipReason::PreviouslyFailed);
+    }
+
+    Decision::Download
 }
 
+/// Downloads the files of a directory into a folder of the storage (e.g. `pub/linux`)
 pub async fn download_files_to_dir(
-    destination: &Path,
+    folder: &str,
     files: &Vec<&types::FileLinkMetaData>,
-    client: &reqwest::Client,
-    options: Option<&CliOptions>,
-    mut counters: Option<&mut LimitCounts>,
-    done_list: &mut Vec<String>,
+    session: &Session,
+    options: &DownloadOptions,
+    counters: &mut LimitCounts,
+    statuses: &mut StatusMap,
 ) -> Result<()> {
     for file in files {
         let temp = Url::from_str(&file.url)?;
-        let last_segment = get_last_segment(&temp);
+        let last_segment = file_name(get_last_segment(&temp), file);
+        let last_segment = last_segment.as_str();
+
+        // Only download (new) files within the scope
+        let decision = if session.scope.enqueue(&temp, Queue::Download).is_none() {
+            Decision::Skip(SkipReason::Scope)
+        } else {
+            decide(last_segment, &file.url, options, counters, statuses)
+        };
 
-        if done_list.contains(&file.url) {
-            println!("(StateStore) Already have file {}", last_segment);
-            return Ok(());
+        if let Decision::Skip(reason) = decision {
+            skip_file(session, file, last_segment, reason);
+            statuses.skip(&file.url, reason)?;
+            continue;
         }
 
-        // Follow options (if specified)
-        if let Some(options) = options {
-            if let Some(counters) = &mut counters {
-                // Check for the download limit
-                if let Some(file_limit) = options.limit_count {
-                    if counters.file_count >= file_limit {
-                        return Ok(());
-                    }
-                }
+        statuses.set(&file.url, FileStatus::InProgress)?;
+
+        // Record failed downloads and carry on with the next file
+        match download_file(folder, file, session, options.storage.as_ref()).await {
+            Ok(bytes) => {
+                session.events.emit(Event::DownloadFinished {
+                    url: file.url.clone(),
+                    name: last_segment.to_owned(),
+                    bytes,
+                });
+                session.reporter.file_downloaded(&temp, bytes);
+                statuses.set(&file.url, FileStatus::Done)?;
+            }
+            Err(err) => {
+                session.events.emit(Event::DownloadFailed {
+                    url: file.url.clone(),
+                    name: last_segment.to_owned(),
+                    error: err.to_string(),
+                });
+                session.reporter.file_failed(&temp, &err);
+                statuses.set(
+                    &file.url,
+                    FileStatus::Failed {
+                        reason: err.to_string(),
+                    },
+                )?;
+            }
+        }
+    }
 
-                // Skip unwanted files
-                if let Some(regex) = &options.file_filter {
-                    if regex.is_match(last_segment) {
-                        // println!("(Filter) Skip file {} ({})", last_segment, file.name);
-                        println!("(Filter) Skip file {}", last_segment);
-                        continue;
-                    }
-                }
+    Ok(())
+}
 
-                // Only download wanted files
-                if let Some(regex) = &options.file_matcher {
-                    if !regex.is_match(last_segment) {
-                        // println!("(Matcher) Skip file {} ({})", last_segment, file.name);
-                        println!("(Matcher) Skip file {}", last_segment);
-                        continue;
-                    }
-                }
+/// Reports a file which won't be downloaded (to the event handlers & the report)
+fn skip_file(
+    session: &Session,
+    file: &types::FileLinkMetaData,
+    last_segment: &str,
+    reason: SkipReason,
+) {
+    session.events.emit(Event::Skipped {
+        url: file.url.clone(),
+        name: last_segment.to_owned(),
+        reason,
+        bytes: file.size_in_bytes(),
+    });
+    session.reporter.file_skipped(reason);
+}
 
-                // Skip files if desired
-                if let Some(skip) = options.skip_count {
-                    if counters.skipped_files < skip {
-                        counters.skipped_files += 1;
-                        continue;
-                    }
-                }
+/// Reports a directory which won't be downloaded from
+fn skip_dir(session: &Session, url: &str, name: &str, reason: DirSkipReason) {
+    session.events.emit(Event::DirSkipped {
+        url: url.to_owned(),
+        name: name.to_owned(),
+        reason,
+    });
+}
 
-                // Increment download counter
-                counters.file_count += 1;
-            } else {
-                panic!("Cannot specify only one of `client` or `options` (need both or niether)");
-            }
-        } else {
-            if counters.is_some() {
-                panic!("Cannot specify only one of `client` or `options` (need both or niether)");
-            }
-        }
+/// The body of a file being downloaded (over HTTP or FTP)
+enum Body {
+    Http(reqwest::Response, Decoder),
+    Ftp(Url, ftp::Download),
+}
 
-        // println!("Downloading file {} ({})", last_segment, file.name);
-        println!("Downloading file {}", last_segment);
+impl Body {
+    /// Requests a file from the server
+    async fn open(url: &str, session: &Session) -> Result<Self> {
+        let url = Url::parse(url)?;
+        if ftp::is_ftp(&url) {
+            let download = ftp::retrieve(&url, session).await?;
+            return Ok(Self::Ftp(url, download));
+        }
 
-        // Request the file from the server
-        let req = client.get(file.url.as_str()).send();
+        // Treat error responses (e.g. 404) as failures instead of saving them
+        let name = get_last_segment(&url);
+        let res = session
+            .request(
+                Method::GET,
+                url.as_str(),
+                compression::download_headers(name),
+                &[],
+            )
+            .await?
+            .error_for_status()?;
+
+        let decoder = Decoder::for_download(res.headers(), name)?;
+        Ok(Self::Http(res, decoder))
+    }
 
-        let mut res = req.await?;
+    /// The URL of the file (after redirects)
+    fn url(&self) -> &Url {
+        match self {
+            Self::Http(res, _) => res.url(),
+            Self::Ftp(url, _) => url,
+        }
+    }
 
-        // Obtain the last segment from the server to follow redirects
-        let last_segment = get_last_segment(res.url());
+    /// The exact size of the file (if the server tells it, unknown for compressed transfers)
+    fn size(&self) -> Option<u64> {
+        match self {
+            Self::Http(res, decoder) if decoder.is_identity() => res.content_length(),
+            Self::Http(_, _) => None,
+            Self::Ftp(_, download) => download.size,
+        }
+    }
 
-        let file_path = destination.join(last_segment);
+    async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Http(res, decoder) => loop {
+                // Compressed chunks may not decode to any bytes yet
+                match res.chunk().await? {
+                    Some(chunk) => {
+                        let chunk = decoder.decode(&chunk)?;
+                        if !chunk.is_empty() {
+                            return Ok(Some(chunk));
+                        }
+                    }
+                    None => {
+                        let rest = decoder.finish()?;
+                        return Ok(if rest.is_empty() { None } else { Some(rest) });
+                    }
+                }
+            },
+            Self::Ftp(_, download) => download.chunk().await,
+        }
+    }
+}
 
-        // Use Tokio to open the target file
-        let mut file_handle = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&file_path)
-            .await?;
+/// Downloads a single file into a folder of the storage (returning the number of bytes written)
+async fn download_file(
+    folder: &str,
+    file: &types::FileLinkMetaData,
+    session: &Session,
+    storage: &dyn StorageSink,
+) -> Result<u64> {
+    // Request the file from the server
+    let mut body = Body::open(&file.url, session).await?;
+
+    // Obtain the last segment from the server to follow redirects
+    let name = file_name(get_last_segment(body.url()), file);
+
+    let path = join_path(folder, &name);
+
+    // Only announce the exact size to the storage (the stated one is rounded)
+    let mut target = storage.create(&path, body.size()).await?;
+
+    // Announce the download (using the stated size if the server doesn't send one)
+    session.events.emit(Event::DownloadStarted {
+        url: file.url.clone(),
+        name,
+        bytes: body.size().or(file.size_in_bytes()),
+    });
+
+    // Store the file in chunks as they arrive from the network
+    let mut written = 0;
+    loop {
+        let chunk = match body.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(err) => {
+                // Keep the storage usable (e.g. an archive) for the next files
+                let _ = target.abort().await;
+                return Err(err);
+            }
+        };
 
-        // Write the file to disk in chunks as they arrive from the network
-        while let Some(chunk) = res.chunk().await? {
-            file_handle.write_all(&chunk).await?;
+        if let Err(err) = target.write(&chunk).await {
+            let _ = target.abort().await;
+            return Err(err);
         }
 
-        // Append the file URL to the done_list
-        done_list.push(file.url.clone());
+        session.events.emit(Event::DownloadProgress {
+            url: file.url.clone(),
+            bytes: chunk.len() as u64,
+        });
+        session.reporter.transferred(body.url(), chunk.len() as u64);
+        written += chunk.len() as u64;
     }
 
-    Ok(())
+    target.finish().await?;
+
+    Ok(written)
 }
 
+/// Downloads the files of a crawled directory to a folder (returning the sub-directories to download next)
 pub async fn download_recursive<'a>(
     node: &'a Node,
-    options: &'a CliOptions,
-    client: &'a reqwest::Client,
-    counters: &'a mut LimitCounts,
-    done_list: &mut Vec<String>,
+    folder: &str,
+    options: &DownloadOptions,
+    session: &Session,
+    counters: &mut LimitCounts,
+    statuses: &mut StatusMap,
 ) -> Result<DownloadRecursiveStatus<'a>> {
     // ) -> Box<dyn Future<Output = ()>> {
     // Pin<Box<dyn Future<Output = Result<()>>>>
     // Pin<Box<dyn Future<Output = ()>>>
 
     if let Node::CrawledDir(meta, children) = node {
-        // If no download is desired, skip the download
-        if options.no_download {
-            println!("Skipped download");
-            return Ok(DownloadRecursiveStatus::Done);
-        };
+        // Stop if the recursion limit is reached (by the directories queued before)
+        if let Some(rec_limit) = options.recursion_limit {
+            if counters.recursion_depth >= rec_limit {
+                return Ok(DownloadRecursiveStatus::Done);
+            }
+        }
 
         // Increment the recursion depth
         counters.recursion_depth += 1;
 
         // The folder name from the server
-        let server_path = meta.name.split('/').last().expect("Can't split");
+        let server_path = dir_name(meta);
 
         // Skip unwanted folders
-        if let Some(regex) = &options.path_filter {
-            if regex.is_match(&server_path) {
-                println!("(Filter) Skip directory {}", server_path);
+        if let Some(regex) = &options.selection.path_filter {
+            if regex.is_match(server_path) {
+                skip_dir(session, &meta.url, server_path, DirSkipReason::PathFilter);
                 return Ok(DownloadRecursiveStatus::Done);
             }
         }
 
         // Only download wanted folders
-        if let Some(regex) = &options.path_matcher {
-            if !regex.is_match(&server_path) {
-                println!("(Matcher) Skip directory {}", server_path);
+        if let Some(regex) = &options.selection.path_matcher {
+            if !regex.is_match(server_path) {
+                skip_dir(session, &meta.url, server_path, DirSkipReason::PathMatcher);
                 return Ok(DownloadRecursiveStatus::Done);
             }
         }
 
-        let mut url = options.url.clone();
-        url.path_segments_mut()
-            .unwrap()
-            // TODO improve this somehow
-            .pop_if_empty()
-            .pop_if_empty()
-            .pop_if_empty()
-            .pop_if_empty()
-            .pop_if_empty()
-            .pop_if_empty()
-            .pop_if_empty()
-            .pop_if_empty();
-
-        let last_segment = url.path_segments().unwrap().last().unwrap();
-
-        // Create the directory (if it doesn't exist)
-        let folder_path = Path::new(&options.destination)
-            .join(last_segment)
-            .join(server_path);
-
-        println!("{}", folder_path.to_str().unwrap());
-
-        fs::create_dir_all(&folder_path).await?;
+        debug!("Writing files to {}", folder);
 
         // Make a list of files
         let mut files = vec![];
@@ -200,85 +405,304 @@ pub async fn download_recursive<'a>(
         }
 
         // Download all the files (if they pass the filters)
-        download_files_to_dir(
-            &folder_path,
-            &files,
-            client,
-            Some(options),
-            Some(counters),
-            done_list,
-        )
-        .await?;
+        download_files_to_dir(folder, &files, session, options, counters, statuses).await?;
 
         // A list of tuples containing arguments which which this function should be called again
         let mut to_do = vec![];
 
         // Iterate over the sub directories
         for directory in children {
-            if let Node::CrawledDir(_, _) = directory {
+            if let Node::CrawledDir(dir, _) = directory {
                 // Stop if the recursion limit is reached
                 if let Some(rec_limit) = options.recursion_limit {
                     if counters.recursion_depth >= rec_limit {
-                        println!("Reached recursion limit at {}", counters.recursion_depth);
+                        info!("Reached recursion limit at {}", counters.recursion_depth);
                         return Ok(DownloadRecursiveStatus::Done);
                     }
                 }
 
                 if let Some(file_limit) = options.limit_count {
                     if counters.file_count >= file_limit {
-                        println!("File limit reached at {} files", counters.file_count);
+                        info!("File limit reached at {} files", counters.file_count);
                         return Ok(DownloadRecursiveStatus::Done);
                     }
                 }
 
-                // TODO await the recursion (or maybe keep the return vector)
-                // (*(download_recursive(directory, options, client, counters).await))?;
-
-                // Start the next recursive iteration
-                to_do.push((directory, options, client));
+                // The folders mirror the directories below the root
+                to_do.push((directory, join_path(folder, &safe_name(dir_name(dir)))));
             } else if let Node::PendingDir(directory) = directory {
-                let mut url = Url::from_str(&directory.url)?;
-                url.path_segments_mut()
-                    .unwrap()
-                    // TODO improve this somehow
-                    .pop_if_empty()
-                    .pop_if_empty()
-                    .pop_if_empty()
-                    .pop_if_empty()
-                    .pop_if_empty()
-                    .pop_if_empty()
-                    .pop_if_empty()
-                    .pop_if_empty();
-
-                let last_segment = url.path_segments().unwrap().last().unwrap();
-
-                println!("(Skip) Directory not initialized: {}", last_segment);
+                let url = Url::from_str(&directory.url)?;
+                let last_segment = get_last_dir_segment(&url);
+
+                skip_dir(
+                    session,
+                    &directory.url,
+                    last_segment,
+                    DirSkipReason::NotCrawled,
+                );
             }
         }
 
         // Return the to_do list of tuples containing arguments with which this function should be called again
-        if to_do.len() == 0 {
+        if to_do.is_empty() {
             Ok(DownloadRecursiveStatus::Done)
         } else {
             Ok(DownloadRecursiveStatus::Do(to_do))
         }
     } else {
-        bail!("Cannot work with pending directory")
+        Err(OdGetError::State(
+            "Cannot work with pending directory".to_owned(),
+        ))
     }
 }
 
 /// Returns a reference to the last segment of a given URL as a &str
 fn get_last_segment(url: &Url) -> &str {
     url.path_segments()
-        .and_then(|segments| segments.last())
+        .and_then(|mut segments| segments.next_back())
         .and_then(|name| if name.is_empty() { None } else { Some(name) })
-        .unwrap_or_else(|| {
-            // TODO this might need to be fixed
-            // (url.clone().path_segments_mut().unwrap().pop_if_empty());
-            // get_last_segment(url);
-            return "unknown_segment";
-        })
+        // TODO this might need to be fixed
+        // (url.clone().path_segments_mut().unwrap().pop_if_empty());
+        // get_last_segment(url);
+        .unwrap_or("unknown_segment")
 
     // TODO Maybe provide a fallback
     // See https://rust-lang-nursery.github.io/rust-cookbook/web/clients/download.html
 }
+
+/**
+Returns the name of a file within the storage (the percent-decoded segment of its URL).
+
+Segments which are not UTF-8 (encoded in the charset of a legacy listing) fall back to
+the name decoded while crawling. Separators & dot-segments never leave the folder.
+*/
+fn file_name(segment: &str, file: &types::FileLinkMetaData) -> String {
+    let name = match percent_decode_str(segment).decode_utf8() {
+        Ok(name) => name.into_owned(),
+        Err(_) if !file.name.is_empty() => file.name.clone(),
+        Err(_) => percent_decode_str(segment).decode_utf8_lossy().into_owned(),
+    };
+
+    safe_name(&name)
+}
+
+/// Returns the name of a file within the storage (see `file_name`)
+pub(crate) fn stored_name(file: &types::FileLinkMetaData) -> String {
+    match Url::parse(&file.url) {
+        Ok(url) => file_name(get_last_segment(&url), file),
+        Err(_) => safe_name(&file.name),
+    }
+}
+
+/// Keeps a name within its folder (replacing separators & dot-segments)
+pub(crate) fn safe_name(name: &str) -> String {
+    match name {
+        "" | "." | ".." => "unknown_segment".to_owned(),
+        _ => name.replace(['/', '\\', '\0'], "_"),
+    }
+}
+
+/// Returns the name of a crawled directory (the last segment of its path, e.g. `linux` for `/pub/linux/`)
+fn dir_name(dir: &types::DirLinkMetaData) -> &str {
+    dir.name
+        .rsplit('/')
+        .find(|segment| !segment.is_empty())
+        .unwrap_or_default()
+}
+
+/// Returns the folder of the root directory within the storage (e.g. `pub` for `https://example.com/pub/`)
+pub fn root_folder(url: &Url) -> String {
+    get_last_dir_segment(url).to_owned()
+}
+
+/// Joins the parts of a storage path (leaving out empty ones)
+fn join_path(parent: &str, name: &str) -> String {
+    match (parent.is_empty(), name.is_empty()) {
+        (_, true) => parent.to_owned(),
+        (true, false) => name.to_owned(),
+        (false, false) => format!("{}/{}", parent, name),
+    }
+}
+
+/// Returns the last non-empty segment of a URL (e.g. the name of a directory)
+fn get_last_dir_segment(url: &Url) -> &str {
+    url.path_segments()
+        .and_then(|mut segments| segments.rfind(|segment| !segment.is_empty()))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::storage::LocalSink;
+    use regex::Regex;
+    use std::collections::HashMap;
+
+    fn options() -> DownloadOptions {
+        DownloadOptions {
+            url: Url::parse("http://example.com/pub/").unwrap(),
+            storage: Arc::new(LocalSink::new(".")),
+            selection: Selection::default(),
+            limit_count: None,
+            skip_count: None,
+            recursion_limit: None,
+        }
+    }
+
+    fn url(name: &str) -> String {
+        format!("http://example.com/pub/{}", name)
+    }
+
+    /// Decides on the files in order (with fresh counters)
+    fn decide_all(
+        names: &[&str],
+        options: &DownloadOptions,
+        statuses: &StatusMap,
+    ) -> Vec<Decision> {
+        let mut counters = LimitCounts::new();
+        names
+            .iter()
+            .map(|name| decide(name, &url(name), options, &mut counters, statuses))
+            .collect()
+    }
+
+    fn statuses(entries: &[(&str, FileStatus)]) -> StatusMap {
+        StatusMap::new(
+            entries
+                .iter()
+                .map(|(name, status)| (url(name), status.clone()))
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn downloads_without_rules() {
+        let decisions = decide_all(&["a.txt", "b.txt"], &options(), &StatusMap::default());
+        assert_eq!(decisions, vec![Decision::Download, Decision::Download]);
+    }
+
+    #[test]
+    fn filter_comes_before_matcher() {
+        let mut options = options();
+        options.selection.file_filter = Some(Regex::new("^a").unwrap());
+        options.selection.file_matcher = Some(Regex::new("iso$").unwrap());
+
+        let decisions = decide_all(
+            &["a.iso", "b.iso", "b.txt"],
+            &options,
+            &StatusMap::default(),
+        );
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Skip(SkipReason::Filter),
+                Decision::Download,
+                Decision::Skip(SkipReason::Matcher),
+            ]
+        );
+    }
+
+    #[test]
+    fn filtered_files_dont_count() {
+        let mut options = options();
+        options.selection.file_matcher = Some(Regex::new("iso$").unwrap());
+        options.skip_count = Some(1);
+        options.limit_count = Some(1);
+
+        let decisions = decide_all(
+            &["a.txt", "a.iso", "b.txt", "b.iso", "c.iso"],
+            &options,
+            &StatusMap::default(),
+        );
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Skip(SkipReason::Matcher),
+                Decision::Skip(SkipReason::SkipCount),
+                Decision::Skip(SkipReason::Matcher),
+                Decision::Download,
+                Decision::Skip(SkipReason::Limit),
+            ]
+        );
+    }
+
+    #[test]
+    fn skip_count_comes_before_limit() {
+        let mut options = options();
+        options.skip_count = Some(2);
+        options.limit_count = Some(1);
+
+        let decisions = decide_all(&["a", "b", "c", "d"], &options, &StatusMap::default());
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Skip(SkipReason::SkipCount),
+                Decision::Skip(SkipReason::SkipCount),
+                Decision::Download,
+                Decision::Skip(SkipReason::Limit),
+            ]
+        );
+    }
+
+    #[test]
+    fn earlier_runs_count_towards_the_limit() {
+        let mut options = options();
+        options.limit_count = Some(2);
+        let statuses = statuses(&[
+            ("a", FileStatus::Done),
+            (
+                "b",
+                FileStatus::Failed {
+                    reason: "timeout".to_owned(),
+                },
+            ),
+        ]);
+
+        let decisions = decide_all(&["a", "b", "c"], &options, &statuses);
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Skip(SkipReason::AlreadyDone),
+                Decision::Skip(SkipReason::PreviouslyFailed),
+                Decision::Skip(SkipReason::Limit),
+            ]
+        );
+    }
+
+    #[test]
+    fn earlier_runs_come_after_the_rules() {
+        let mut options = options();
+        options.selection.file_filter = Some(Regex::new("^a").unwrap());
+        options.skip_count = Some(1);
+        let statuses = statuses(&[("a", FileStatus::Done), ("b", FileStatus::Done)]);
+
+        let decisions = decide_all(&["a", "b", "c"], &options, &statuses);
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Skip(SkipReason::Filter),
+                Decision::Skip(SkipReason::SkipCount),
+                Decision::Download,
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_skips_files_instead_of_stopping() {
+        let mut options = options();
+        options.selection.file_filter = Some(Regex::new("^x").unwrap());
+        options.limit_count = Some(1);
+
+        // Every later file still gets its own decision (the filter before the limit)
+        let decisions = decide_all(&["a", "b", "x", "c"], &options, &StatusMap::default());
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::Download,
+                Decision::Skip(SkipReason::Limit),
+                Decision::Skip(SkipReason::Filter),
+                Decision::Skip(SkipReason::Limit),
+            ]
+        );
+    }
+}
@@ -0,0 +1,992 @@
+use super::{
+    error::{OdGetError, Result},
+    session::Session,
+};
+use lazy_static::lazy_static;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use reqwest::Url;
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time,
+};
+use tokio_native_tls::{native_tls, TlsConnector};
+
+const DEFAULT_PORT: u16 = 21;
+
+/// The port of implicit FTPS (`ftps://`)
+const DEFAULT_TLS_PORT: u16 = 990;
+
+/// How long to wait for a connection, a reply or data from the server
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The size of the chunks in which files are downloaded
+const CHUNK_SIZE: usize = 64 << 10;
+
+lazy_static! {
+    /// The port of an extended passive reply (e.g. `229 Entering Extended Passive Mode (|||6446|)`)
+    static ref RX_EPSV: Regex = Regex::new(r"\(\|\|\|(\d+)\|\)").unwrap();
+
+    /// The address of a passive reply (e.g. `227 Entering Passive Mode (192,168,1,2,25,46)`)
+    static ref RX_PASV: Regex = Regex::new(r"(\d+),(\d+),(\d+),(\d+),(\d+),(\d+)").unwrap();
+
+    /// A Unix-style `LIST` line (e.g. `-rw-r--r-- 1 ftp ftp 1024 Jan 31 12:00 file.iso`)
+    static ref RX_UNIX: Regex = Regex::new(
+        r"^([-dlbcps])\S*\s+\d+\s+\S+(?:\s+\S+)?\s+(\d+)\s+(\w{3}\s+\d{1,2}\s+(?:\d{1,2}:\d{2}|\d{4}))\s(.+)$"
+    ).unwrap();
+
+    /// A DOS-style `LIST` line (e.g. `01-31-24  12:00PM  <DIR>  pub`)
+    static ref RX_DOS: Regex = Regex::new(
+        r"^(\d{2}-\d{2}-\d{2,4}\s+\d{1,2}:\d{2}[AP]M)\s+(<DIR>|\d+)\s+(.+)$"
+    ).unwrap();
+}
+
+/// The control & data connections (plain or TLS)
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Checks if a URL is served over FTP (`ftp://` or `ftps://`)
+pub fn is_ftp(url: &Url) -> bool {
+    matches!(url.scheme(), "ftp" | "ftps")
+}
+
+/// An entry of an FTP directory listing
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub dir: bool,
+    pub size: Option<u64>,
+
+    /// Whether the entry is a symbolic link (resolved to a file or a directory by `list`)
+    pub link: bool,
+
+    /// The modification time as stated by the server (e.g. `2024-01-31 12:00` or `Jan 31 12:00`)
+    pub modified: String,
+}
+
+/**
+Lists an FTP directory (using `MLSD` if the server supports it, `LIST` otherwise).
+
+The parent & current directory entries are left out. Links which can be entered
+with `CWD` are listed as directories (loops are caught by the crawler), other links as files.
+*/
+pub async fn list(url: &Url, session: &Session) -> Result<Vec<Entry>> {
+    let endpoint = Endpoint::new(url, session)?;
+    let path = path(url)?;
+
+    wait(url, session).await;
+    let mut connection = session.ftp.connect(&endpoint).await?;
+    let (entries, bytes) = connection.list(&path).await?;
+    session.ftp.release(connection);
+
+    session.reporter.transferred(url, bytes);
+    Ok(entries)
+}
+
+/// Starts the download of a file from an FTP server
+pub async fn retrieve(url: &Url, session: &Session) -> Result<Download> {
+    let endpoint = Endpoint::new(url, session)?;
+    let path = path(url)?;
+
+    wait(url, session).await;
+    let mut connection = session.ftp.connect(&endpoint).await?;
+
+    // Not every server supports SIZE
+    let reply = connection.send(&format!("SIZE {}", path)).await?;
+    let size = match reply.code {
+        213 => reply.text.trim().parse().ok(),
+        _ => None,
+    };
+
+    let command = format!("RETR {}", path);
+    let data = connection.transfer(&command).await?;
+
+    Ok(Download {
+        pool: session.ftp.clone(),
+        connection: Some(connection),
+        data: Some(data),
+        command,
+        size,
+    })
+}
+
+/**
+A file being downloaded from an FTP server.
+
+The connection goes back to the pool of the session once the file is complete.
+*/
+pub struct Download {
+    pool: Pool,
+    connection: Option<Connection>,
+    data: Option<Box<dyn Stream>>,
+
+    /// The `RETR` command (to report a failed transfer)
+    command: String,
+
+    /// The size of the file (if the server told it)
+    pub size: Option<u64>,
+}
+
+impl Download {
+    /// Reads the next chunk of the file (or `None` once it is complete)
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        let data = match &mut self.data {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let read = read_data(data, &mut chunk).await?;
+        if read > 0 {
+            chunk.truncate(read);
+            return Ok(Some(chunk));
+        }
+
+        // The server confirms the transfer after closing the data connection
+        if let Some(data) = self.data.take() {
+            close(data).await;
+        }
+        if let Some(mut connection) = self.connection.take() {
+            connection.reply(&self.command, &[226, 250]).await?;
+            self.pool.release(connection);
+        }
+
+        Ok(None)
+    }
+}
+
+/**
+Idle FTP connections (re-used by the next listing or download on the same server).
+
+Clones share the same connections.
+*/
+#[derive(Clone, Default)]
+pub struct Pool {
+    idle: Arc<Mutex<Vec<Connection>>>,
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("Pool").field("idle", &idle.len()).finish()
+    }
+}
+
+impl Pool {
+    /// Takes an idle connection to the endpoint (if it's still alive), or opens a new one
+    async fn connect(&self, endpoint: &Endpoint) -> Result<Connection> {
+        loop {
+            let idle = {
+                let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+                idle.iter()
+                    .position(|connection| connection.endpoint == *endpoint)
+                    .map(|index| idle.swap_remove(index))
+            };
+
+            let mut connection = match idle {
+                Some(connection) => connection,
+                None => return Connection::open(endpoint.clone()).await,
+            };
+
+            // Servers close idle connections after a while
+            if let Ok(Reply { code: 200, .. }) = connection.send("NOOP").await {
+                return Ok(connection);
+            }
+        }
+    }
+
+    fn release(&self, connection: Connection) {
+        self.idle
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(connection);
+    }
+}
+
+/// The server (and the user) of an FTP connection
+#[derive(Clone, PartialEq, Eq)]
+struct Endpoint {
+    tls: bool,
+    host: String,
+    port: u16,
+    username: String,
+    password: String,
+}
+
+impl Endpoint {
+    /// Logs in with the credentials of the session (if they apply to the URL), or anonymously
+    fn new(url: &Url, session: &Session) -> Result<Self> {
+        let tls = url.scheme() == "ftps";
+        let host = url
+            .host_str()
+            .ok_or_else(|| OdGetError::Ftp(format!("No host in {}", url)))?
+            .to_owned();
+
+        let (username, password) = match &session.auth {
+            Some(auth) if auth.applies_to(url) => {
+                let credentials = auth.credentials();
+                (
+                    credentials.username.clone(),
+                    credentials.password.clone().unwrap_or_default(),
+                )
+            }
+            _ => ("anonymous".to_owned(), "anonymous@".to_owned()),
+        };
+
+        Ok(Self {
+            tls,
+            host,
+            port: url
+                .port()
+                .unwrap_or(if tls { DEFAULT_TLS_PORT } else { DEFAULT_PORT }),
+            username,
+            password,
+        })
+    }
+}
+
+/// A reply of the server (the text of multi-line replies is joined by newlines)
+struct Reply {
+    code: u16,
+    text: String,
+}
+
+/// A logged-in control connection
+struct Connection {
+    endpoint: Endpoint,
+    control: BufReader<Box<dyn Stream>>,
+
+    /// The address of the server (used for the data connections)
+    peer: IpAddr,
+
+    /// Whether the server supports machine-readable listings
+    mlsd: bool,
+}
+
+impl Connection {
+    /// Connects & logs in (FTPS connections use implicit TLS and encrypt the data connections too)
+    async fn open(endpoint: Endpoint) -> Result<Self> {
+        let tcp = with_timeout(TcpStream::connect((endpoint.host.as_str(), endpoint.port))).await?;
+        let peer = tcp.peer_addr()?.ip();
+
+        let stream: Box<dyn Stream> = if endpoint.tls {
+            Box::new(secure(&endpoint.host, tcp).await?)
+        } else {
+            Box::new(tcp)
+        };
+
+        let mut connection = Self {
+            endpoint,
+            control: BufReader::new(stream),
+            peer,
+            mlsd: false,
+        };
+
+        connection.reply("the connection", &[220]).await?;
+
+        if connection.endpoint.tls {
+            connection.expect("PBSZ 0", &[200]).await?;
+            connection.expect("PROT P", &[200]).await?;
+        }
+
+        let user = format!("USER {}", connection.endpoint.username);
+        if connection.expect(&user, &[230, 331]).await?.code == 331 {
+            let pass = format!("PASS {}", connection.endpoint.password);
+            connection.expect(&pass, &[202, 230]).await?;
+        }
+
+        connection.expect("TYPE I", &[200]).await?;
+
+        // Prefer MLSD (the format of LIST differs between servers)
+        let features = connection.send("FEAT").await?;
+        connection.mlsd = features.code == 211
+            && features
+                .text
+                .lines()
+                .any(|line| line.trim().to_ascii_uppercase().starts_with("MLST"));
+
+        Ok(connection)
+    }
+
+    /// Sends a command and reads the reply
+    async fn send(&mut self, command: &str) -> Result<Reply> {
+        let line = format!("{}\r\n", command);
+        with_timeout(self.control.get_mut().write_all(line.as_bytes())).await?;
+        self.read_reply().await
+    }
+
+    /// Sends a command, expecting one of the given reply codes
+    async fn expect(&mut self, command: &str, codes: &[u16]) -> Result<Reply> {
+        let reply = self.send(command).await?;
+        self.check(command, codes, reply)
+    }
+
+    /// Reads the next reply (e.g. the end of a transfer), expecting one of the given codes
+    async fn reply(&mut self, command: &str, codes: &[u16]) -> Result<Reply> {
+        let reply = self.read_reply().await?;
+        self.check(command, codes, reply)
+    }
+
+    fn check(&self, command: &str, codes: &[u16], reply: Reply) -> Result<Reply> {
+        if codes.contains(&reply.code) {
+            return Ok(reply);
+        }
+
+        // Don't reveal the password
+        let command = if command.starts_with("PASS ") {
+            "PASS"
+        } else {
+            command
+        };
+
+        Err(OdGetError::Ftp(format!(
+            "The FTP server {} refused {} ({} {})",
+            self.endpoint.host, command, reply.code, reply.text
+        )))
+    }
+
+    async fn read_reply(&mut self) -> Result<Reply> {
+        let first = self.read_line().await?;
+        let code: u16 = first
+            .get(..3)
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| OdGetError::Ftp(format!("Invalid FTP reply: {}", first)))?;
+        let mut text = first[3..].trim_start_matches(&['-', ' '][..]).to_owned();
+
+        // Multi-line replies end with a line starting with the code and a space
+        if first.as_bytes().get(3) == Some(&b'-') {
+            let end = format!("{} ", code);
+
+            loop {
+                let line = self.read_line().await?;
+                text.push('\n');
+
+                match line.strip_prefix(&end) {
+                    Some(rest) => {
+                        text.push_str(rest);
+                        break;
+                    }
+                    None => text.push_str(&line),
+                }
+            }
+        }
+
+        Ok(Reply { code, text })
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = vec![];
+        if with_timeout(self.control.read_until(b'\n', &mut line)).await? == 0 {
+            return Err(OdGetError::Ftp(format!(
+                "The FTP server {} closed the connection",
+                self.endpoint.host
+            )));
+        }
+
+        Ok(String::from_utf8_lossy(&line).trim_end().to_owned())
+    }
+
+    /**
+    Opens a passive data connection and issues a transfer command (e.g. `RETR`) on it.
+
+    The announced address is ignored in favor of the one of the control connection
+    (servers behind NAT often announce a private one).
+    */
+    async fn transfer(&mut self, command: &str) -> Result<Box<dyn Stream>> {
+        let reply = self.send("EPSV").await?;
+        let port = match reply.code {
+            229 => epsv_port(&reply.text),
+            _ => None,
+        };
+
+        let port = match port {
+            Some(port) => port,
+            None => {
+                let reply = self.expect("PASV", &[227]).await?;
+                pasv_port(&reply.text).ok_or_else(|| {
+                    OdGetError::Ftp(format!("Invalid passive reply: {}", reply.text))
+                })?
+            }
+        };
+
+        let tcp = with_timeout(TcpStream::connect((self.peer, port))).await?;
+        self.expect(command, &[125, 150]).await?;
+
+        // The server only starts the TLS handshake after the command
+        if self.endpoint.tls {
+            Ok(Box::new(secure(&self.endpoint.host, tcp).await?))
+        } else {
+            Ok(Box::new(tcp))
+        }
+    }
+
+    /// Lists a directory (returning the entries and the size of the listing)
+    async fn list(&mut self, path: &str) -> Result<(Vec<Entry>, u64)> {
+        let command = if self.mlsd {
+            format!("MLSD {}", path)
+        } else {
+            format!("LIST {}", path)
+        };
+
+        let mut data = self.transfer(&command).await?;
+        let mut listing = vec![];
+        let mut chunk = vec![0; CHUNK_SIZE];
+        loop {
+            match read_data(&mut data, &mut chunk).await? {
+                0 => break,
+                read => listing.extend_from_slice(&chunk[..read]),
+            }
+        }
+        close(data).await;
+
+        self.reply(&command, &[226, 250]).await?;
+
+        let parse = if self.mlsd { parse_mlsd } else { parse_list };
+        let mut entries: Vec<_> = String::from_utf8_lossy(&listing)
+            .lines()
+            .filter_map(parse)
+            .filter(|entry| entry.name != "." && entry.name != "..")
+            .collect();
+
+        // Links don't tell what they point to, so try to enter them
+        for entry in entries
+            .iter_mut()
+            .filter(|entry| entry.link && !entry.name.contains('\r'))
+        {
+            let target = format!("{}/{}", path.trim_end_matches('/'), entry.name);
+            if self.send(&format!("CWD {}", target)).await?.code == 250 {
+                entry.dir = true;
+                entry.size = None;
+            }
+        }
+
+        Ok((entries, listing.len() as u64))
+    }
+}
+
+/// Applies the rate limit & counts the request
+async fn wait(url: &Url, session: &Session) {
+    if let Some(rate_limiter) = &session.rate_limiter {
+        rate_limiter.wait().await;
+    }
+
+    session.reporter.request(url);
+}
+
+/// The (decoded) path of a URL as sent in FTP commands
+fn path(url: &Url) -> Result<String> {
+    let path = percent_decode_str(url.path()).decode_utf8_lossy();
+
+    // Line breaks would inject further commands
+    if path.contains(&['\r', '\n'][..]) {
+        return Err(OdGetError::Ftp(format!("Invalid FTP path: {}", url)));
+    }
+
+    Ok(path.into_owned())
+}
+
+/// Wraps a connection in TLS
+async fn secure(host: &str, tcp: TcpStream) -> Result<tokio_native_tls::TlsStream<TcpStream>> {
+    let tls_error = |err: native_tls::Error| OdGetError::Ftp(format!("TLS failed: {}", err));
+
+    let connector = TlsConnector::from(native_tls::TlsConnector::new().map_err(tls_error)?);
+    time::timeout(TIMEOUT, connector.connect(host, tcp))
+        .await
+        .map_err(|_| timed_out())?
+        .map_err(tls_error)
+}
+
+/**
+Reads from a data connection (returning 0 at its end).
+
+TLS connections closed without a notice count as ended,
+the reply on the control connection tells if the transfer was complete.
+*/
+async fn read_data(data: &mut Box<dyn Stream>, buffer: &mut [u8]) -> Result<usize> {
+    match with_timeout(data.read(buffer)).await {
+        Err(OdGetError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+        result => result,
+    }
+}
+
+/// Closes a data connection (TLS connections with a notice, as some servers insist on it)
+async fn close(mut data: Box<dyn Stream>) {
+    let _ = time::timeout(TIMEOUT, data.shutdown()).await;
+}
+
+async fn with_timeout<T>(future: impl Future<Output = io::Result<T>>) -> Result<T> {
+    Ok(time::timeout(TIMEOUT, future)
+        .await
+        .map_err(|_| timed_out())??)
+}
+
+fn timed_out() -> OdGetError {
+    OdGetError::Ftp(format!(
+        "The FTP server didn't respond within {} seconds",
+        TIMEOUT.as_secs()
+    ))
+}
+
+/// Returns the port of an extended passive reply (e.g. `229 Entering Extended Passive Mode (|||6446|)`)
+fn epsv_port(text: &str) -> Option<u16> {
+    RX_EPSV.captures(text)?[1].parse().ok()
+}
+
+/// Returns the port of a passive reply (the last two numbers of the address)
+fn pasv_port(text: &str) -> Option<u16> {
+    let captures = RX_PASV.captures(text)?;
+    let high: u8 = captures[5].parse().ok()?;
+    let low: u8 = captures[6].parse().ok()?;
+    Some(u16::from(high) << 8 | u16::from(low))
+}
+
+/// Parses a line of an `MLSD` listing (e.g. `type=file;size=1024;modify=20240131120000; file.iso`)
+fn parse_mlsd(line: &str) -> Option<Entry> {
+    let (facts, name) = line.split_once(' ')?;
+    let mut entry = Entry {
+        name: name.to_owned(),
+        dir: false,
+        size: None,
+        link: false,
+        modified: String::new(),
+    };
+
+    for fact in facts.split(';') {
+        let (key, value) = match fact.split_once('=') {
+            Some(fact) => fact,
+            None => continue,
+        };
+
+        match key.to_ascii_lowercase().as_str() {
+            "type" => match value.to_ascii_lowercase().as_str() {
+                "dir" => entry.dir = true,
+                "cdir" | "pdir" => return None,
+                // e.g. `OS.unix=symlink` or `OS.unix=slink:/target`
+                other if other.contains("link") => entry.link = true,
+                // Special files are treated as files
+                _ => {}
+            },
+            "size" => entry.size = value.parse().ok(),
+            "modify" => {
+                if let (Some(date), Some(time)) = (value.get(..8), value.get(8..12)) {
+                    entry.modified = format!(
+                        "{}-{}-{} {}:{}",
+                        &date[..4],
+                        &date[4..6],
+                        &date[6..],
+                        &time[..2],
+                        &time[2..]
+                    );
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Some(entry)
+}
+
+/// Parses a line of a `LIST` listing (in the Unix or the DOS format)
+fn parse_list(line: &str) -> Option<Entry> {
+    if let Some(captures) = RX_DOS.captures(line) {
+        let dir = &captures[2] == "<DIR>";
+
+        return Some(Entry {
+            name: captures[3].to_owned(),
+            dir,
+            size: if dir { None } else { captures[2].parse().ok() },
+            link: false,
+            modified: collapse_whitespace(&captures[1]),
+        });
+    }
+
+    let captures = RX_UNIX.captures(line)?;
+    let dir = &captures[1] == "d";
+    let link = &captures[1] == "l";
+
+    // Links state their target after the name
+    let name = if link {
+        captures[4].split(" -> ").next().unwrap_or_default()
+    } else {
+        &captures[4]
+    };
+
+    Some(Entry {
+        name: name.to_owned(),
+        dir,
+        size: if dir { None } else { captures[2].parse().ok() },
+        link,
+        modified: collapse_whitespace(&captures[3]),
+    })
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::scope::{RedirectPolicy, Scope};
+    use tokio::net::TcpListener;
+
+    /// The `LIST` listing of `/pub/` served by the scripted server
+    const LIST: &str = "\
+-rw-r--r--    1 ftp      ftp             5 Jan 31 12:00 a.txt\r
+drwxr-xr-x    2 ftp      ftp          4096 Jan 31 12:00 docs\r
+lrwxrwxrwx    1 ftp      ftp             4 Jan 31 12:00 latest -> docs\r
+lrwxrwxrwx    1 ftp      ftp             5 Jan 31 12:00 alias.txt -> a.txt\r
+";
+
+    /// The `MLSD` listing of `/pub/` served by the scripted server
+    const MLSD: &str = "\
+type=cdir;modify=20240131120000; .\r
+type=file;size=5;modify=20240131120000; a.txt\r
+type=dir;modify=20240131120000; docs\r
+type=OS.unix=symlink;size=4;modify=20240131120000; latest\r
+type=OS.unix=slink:/pub/a.txt;size=5;modify=20240131120000; alias.txt\r
+";
+
+    /**
+    Starts a local FTP server serving `/pub/` (with or without `EPSV` & `MLSD`).
+
+    Returns the URL of `/pub/` and the commands received so far.
+    */
+    async fn scripted_server(epsv: bool, mlsd: bool) -> (Url, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ftp://{}/pub/", listener.local_addr().unwrap())).unwrap();
+        let commands = Arc::new(Mutex::new(vec![]));
+
+        let received = commands.clone();
+        tokio::spawn(async move {
+            while let Ok((control, _)) = listener.accept().await {
+                tokio::spawn(serve(control, epsv, mlsd, received.clone()));
+            }
+        });
+
+        (url, commands)
+    }
+
+    /// Answers the commands of a control connection
+    async fn serve(control: TcpStream, epsv: bool, mlsd: bool, received: Arc<Mutex<Vec<String>>>) {
+        let mut control = BufReader::new(control);
+        let mut data: Option<TcpListener> = None;
+        control
+            .get_mut()
+            .write_all(b"220 Welcome\r\n")
+            .await
+            .unwrap();
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if control.read_line(&mut line).await.unwrap() == 0 {
+                return;
+            }
+
+            let command = line.trim_end().to_owned();
+            received.lock().unwrap().push(command.clone());
+            let (verb, argument) = command.split_once(' ').unwrap_or((&command, ""));
+
+            // The contents of a transfer (sent on the data connection)
+            let mut transfer = None;
+            let reply = match (verb, argument) {
+                ("USER", "anonymous") => "331 Password required".to_owned(),
+                ("PASS", _) => "230 Logged in".to_owned(),
+                ("TYPE", "I") | ("NOOP", _) => "200 OK".to_owned(),
+                ("FEAT", _) if mlsd => {
+                    "211-Features:\r\n MLST type*;size*;modify*;\r\n211 End".to_owned()
+                }
+                ("FEAT", _) => "211-Features:\r\n SIZE\r\n211 End".to_owned(),
+                ("EPSV", _) if epsv => {
+                    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                    let port = listener.local_addr().unwrap().port();
+                    data = Some(listener);
+                    format!("229 Entering Extended Passive Mode (|||{}|)", port)
+                }
+                ("PASV", _) => {
+                    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                    let port = listener.local_addr().unwrap().port();
+                    data = Some(listener);
+                    format!(
+                        "227 Entering Passive Mode (127,0,0,1,{},{})",
+                        port >> 8,
+                        port & 0xff
+                    )
+                }
+                ("LIST", "/pub/") => {
+                    transfer = Some(LIST);
+                    "150 Here comes the listing".to_owned()
+                }
+                ("MLSD", "/pub/") if mlsd => {
+                    transfer = Some(MLSD);
+                    "150 Here comes the listing".to_owned()
+                }
+                ("CWD", "/pub/latest") => "250 Directory changed".to_owned(),
+                ("CWD", _) => "550 Not a directory".to_owned(),
+                ("SIZE", "/pub/a.txt") => "213 5".to_owned(),
+                ("RETR", "/pub/a.txt") => {
+                    transfer = Some("hello");
+                    "150 Opening data connection".to_owned()
+                }
+                _ => "500 Unknown command".to_owned(),
+            };
+
+            let stream = control.get_mut();
+            stream
+                .write_all(format!("{}\r\n", reply).as_bytes())
+                .await
+                .unwrap();
+
+            if let Some(contents) = transfer {
+                let (mut connection, _) = data.take().unwrap().accept().await.unwrap();
+                connection.write_all(contents.as_bytes()).await.unwrap();
+                connection.shutdown().await.unwrap();
+                stream
+                    .write_all(b"226 Transfer complete\r\n")
+                    .await
+                    .unwrap();
+            }
+        }
+    }
+
+    fn session(url: &Url) -> Session {
+        let scope = Scope::new(url, true, vec![], RedirectPolicy::Refuse);
+        Session::new(reqwest::Client::new(), scope, None, None)
+    }
+
+    /// The name, whether it's a directory & the size of the listed entries
+    fn listed(entries: Vec<Entry>) -> Vec<(String, bool, Option<u64>)> {
+        entries
+            .into_iter()
+            .map(|entry| (entry.name, entry.dir, entry.size))
+            .collect()
+    }
+
+    fn expected_listing() -> Vec<(String, bool, Option<u64>)> {
+        vec![
+            ("a.txt".to_owned(), false, Some(5)),
+            ("docs".to_owned(), true, None),
+            ("latest".to_owned(), true, None),
+            ("alias.txt".to_owned(), false, Some(5)),
+        ]
+    }
+
+    fn received(commands: &Mutex<Vec<String>>) -> Vec<String> {
+        commands.lock().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn lists_with_list_and_passive_mode() {
+        let (url, commands) = scripted_server(false, false).await;
+
+        let entries = list(&url, &session(&url)).await.unwrap();
+        assert_eq!(listed(entries), expected_listing());
+
+        assert_eq!(
+            received(&commands),
+            [
+                "USER anonymous",
+                "PASS anonymous@",
+                "TYPE I",
+                "FEAT",
+                "EPSV",
+                "PASV",
+                "LIST /pub/",
+                "CWD /pub/latest",
+                "CWD /pub/alias.txt",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_with_mlsd_and_extended_passive_mode() {
+        let (url, commands) = scripted_server(true, true).await;
+
+        let entries = list(&url, &session(&url)).await.unwrap();
+        assert_eq!(listed(entries), expected_listing());
+
+        let commands = received(&commands);
+        assert!(commands.contains(&"MLSD /pub/".to_owned()));
+        assert!(!commands.contains(&"PASV".to_owned()));
+        assert!(!commands.iter().any(|command| command.starts_with("LIST")));
+    }
+
+    #[tokio::test]
+    async fn retrieves_files_and_reuses_the_connection() {
+        let (url, commands) = scripted_server(true, false).await;
+        let session = session(&url);
+
+        let mut download = retrieve(&url.join("a.txt").unwrap(), &session)
+            .await
+            .unwrap();
+        assert_eq!(download.size, Some(5));
+
+        let mut contents = vec![];
+        while let Some(chunk) = download.chunk().await.unwrap() {
+            contents.extend(chunk);
+        }
+        assert_eq!(contents, b"hello");
+
+        // The next request takes the idle connection
+        list(&url, &session).await.unwrap();
+
+        let commands = received(&commands);
+        assert_eq!(
+            commands[4..],
+            [
+                "SIZE /pub/a.txt",
+                "EPSV",
+                "RETR /pub/a.txt",
+                "NOOP",
+                "EPSV",
+                "LIST /pub/",
+                "CWD /pub/latest",
+                "CWD /pub/alias.txt",
+            ]
+        );
+        assert_eq!(
+            commands
+                .iter()
+                .filter(|command| command.starts_with("USER"))
+                .count(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_refused_files() {
+        let (url, _) = scripted_server(true, false).await;
+
+        let result = retrieve(&url.join("missing.txt").unwrap(), &session(&url)).await;
+        assert!(matches!(result, Err(OdGetError::Ftp(_))));
+    }
+
+    /// The name, whether it's a directory, the size & the modification time of an entry
+    fn fields(entry: Entry) -> (String, bool, Option<u64>, String) {
+        (entry.name, entry.dir, entry.size, entry.modified)
+    }
+
+    fn entry(
+        name: &str,
+        dir: bool,
+        size: Option<u64>,
+        modified: &str,
+    ) -> (String, bool, Option<u64>, String) {
+        (name.to_owned(), dir, size, modified.to_owned())
+    }
+
+    #[test]
+    fn parses_extended_passive_replies() {
+        assert_eq!(
+            epsv_port("Entering Extended Passive Mode (|||6446|)"),
+            Some(6446)
+        );
+        assert_eq!(epsv_port("Entering Extended Passive Mode"), None);
+        assert_eq!(
+            epsv_port("Entering Extended Passive Mode (|||70000|)"),
+            None
+        );
+    }
+
+    #[test]
+    fn parses_passive_replies() {
+        assert_eq!(
+            pasv_port("Entering Passive Mode (192,168,1,2,25,46)."),
+            Some(25 * 256 + 46)
+        );
+        assert_eq!(pasv_port("Entering Passive Mode 10,0,0,1,4,1"), Some(1025));
+        assert_eq!(
+            pasv_port("Entering Passive Mode (192,168,1,2,256,46)"),
+            None
+        );
+        assert_eq!(pasv_port("Entering Passive Mode"), None);
+    }
+
+    #[test]
+    fn parses_mlsd_lines() {
+        assert_eq!(
+            parse_mlsd("type=file;size=1024;modify=20240131120000; file name.iso").map(fields),
+            Some(entry(
+                "file name.iso",
+                false,
+                Some(1024),
+                "2024-01-31 12:00"
+            ))
+        );
+        assert_eq!(
+            parse_mlsd("Type=DIR;Modify=20240131120000.123;UNIX.mode=0755; pub").map(fields),
+            Some(entry("pub", true, None, "2024-01-31 12:00"))
+        );
+        assert_eq!(
+            parse_mlsd("type=OS.unix=symlink;size=7; latest").map(fields),
+            Some(entry("latest", false, Some(7), ""))
+        );
+        assert!(
+            parse_mlsd("type=OS.unix=slink:/pub/v2;size=7; latest")
+                .unwrap()
+                .link
+        );
+        assert!(!parse_mlsd("type=file;size=7; latest").unwrap().link);
+        assert!(parse_mlsd("type=cdir;modify=20240131120000; .").is_none());
+        assert!(parse_mlsd("type=pdir;modify=20240131120000; ..").is_none());
+        assert!(parse_mlsd("no-facts").is_none());
+    }
+
+    #[test]
+    fn parses_unix_list_lines() {
+        assert_eq!(
+            parse_list("-rw-r--r--    1 ftp      ftp          1024 Jan 31 12:00 file name.iso")
+                .map(fields),
+            Some(entry("file name.iso", false, Some(1024), "Jan 31 12:00"))
+        );
+        assert_eq!(
+            parse_list("drwxr-xr-x    2 ftp      ftp          4096 Mar  3  2021 pub").map(fields),
+            Some(entry("pub", true, None, "Mar 3 2021"))
+        );
+        assert_eq!(
+            parse_list("lrwxrwxrwx   1 0        0              11 Jan 31 12:00 latest -> pub/v2.0")
+                .map(fields),
+            Some(entry("latest", false, Some(11), "Jan 31 12:00"))
+        );
+
+        // Some servers leave out the group
+        assert_eq!(
+            parse_list("-rw-r--r--   1 ftp  512 Jan  1 00:00 a.txt").map(fields),
+            Some(entry("a.txt", false, Some(512), "Jan 1 00:00"))
+        );
+    }
+
+    #[test]
+    fn parses_dos_list_lines() {
+        assert_eq!(
+            parse_list("01-31-24  12:00PM       <DIR>          pub").map(fields),
+            Some(entry("pub", true, None, "01-31-24 12:00PM"))
+        );
+        assert_eq!(
+            parse_list("01-31-2024  09:05AM             1024 file name.iso").map(fields),
+            Some(entry(
+                "file name.iso",
+                false,
+                Some(1024),
+                "01-31-2024 09:05AM"
+            ))
+        );
+    }
+
+    #[test]
+    fn skips_other_list_lines() {
+        assert!(parse_list("total 12").is_none());
+        assert!(parse_list("").is_none());
+    }
+}
@@ -1,5 +1,23 @@
+pub mod auth;
+pub mod bucket;
+pub mod charset;
+pub mod compression;
 pub mod crawl;
+pub mod error;
+pub mod events;
+pub mod export;
 pub mod fetch;
+pub mod ftp;
+pub mod report;
+pub mod scope;
+pub mod session;
+pub mod stats;
+pub mod storage;
+pub mod store;
 pub mod types;
+pub mod webdav;
 
 mod selectors;
+
+#[cfg(test)]
+mod test_server;
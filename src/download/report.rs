@@ -0,0 +1,268 @@
+use super::error::OdGetError;
+use chrono::Utc;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    sync::{Mutex, PoisonError},
+    time::Instant,
+};
+
+/// Why a file wasn't downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SkipReason {
+    /// The file was downloaded by an earlier run (according to the state store)
+    AlreadyDone,
+
+    /// The download of the file failed in an earlier run (see `--retry-failed`)
+    PreviouslyFailed,
+
+    /// The file lies outside of the scope (or was already enqueued)
+    Scope,
+
+    /// The file name matches the file filter
+    Filter,
+
+    /// The file name doesn't match the file matcher
+    Matcher,
+
+    /// The file is one of the first files to be skipped
+    SkipCount,
+
+    /// The file limit was reached
+    Limit,
+}
+
+impl SkipReason {
+    /// The name used in the structured log events
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AlreadyDone => "already_done",
+            Self::PreviouslyFailed => "previously_failed",
+            Self::Scope => "scope",
+            Self::Filter => "filter",
+            Self::Matcher => "matcher",
+            Self::SkipCount => "skip_count",
+            Self::Limit => "limit",
+        }
+    }
+}
+
+impl Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::AlreadyDone => "StateStore",
+            Self::PreviouslyFailed => "Failed",
+            Self::Scope => "Scope",
+            Self::Filter => "Filter",
+            Self::Matcher => "Matcher",
+            Self::SkipCount => "Skip",
+            Self::Limit => "Limit",
+        })
+    }
+}
+
+/// A file which couldn't be downloaded
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedFile {
+    pub url: String,
+    pub error: String,
+}
+
+/// The outcome of a single root URL
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RootOutcome {
+    pub url: String,
+    pub ok: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The requests & transfers of a single host
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostStats {
+    pub requests: u64,
+    pub bytes: u64,
+    pub files_downloaded: u64,
+    pub files_failed: u64,
+}
+
+/**
+The summary of a run (written by `--report` for tools wrapping od-get).
+
+Byte counts only include the bodies of responses (listings & files).
+*/
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunReport {
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub wall_time_secs: f64,
+
+    pub roots: Vec<RootOutcome>,
+
+    pub directories_crawled: u64,
+    pub files_found: u64,
+    pub files_downloaded: u64,
+    pub files_skipped: u64,
+    pub skipped_by_reason: BTreeMap<SkipReason, u64>,
+    pub files_failed: u64,
+    pub failures: Vec<FailedFile>,
+
+    /// The bytes of all the files downloaded
+    pub bytes_downloaded: u64,
+
+    /// The bytes of all responses (listings included)
+    pub bytes_transferred: u64,
+
+    pub hosts: BTreeMap<String, HostStats>,
+}
+
+impl Default for RunReport {
+    fn default() -> Self {
+        Self {
+            started_at: Utc::now().to_rfc3339(),
+            finished_at: None,
+            wall_time_secs: 0.0,
+            roots: vec![],
+            directories_crawled: 0,
+            files_found: 0,
+            files_downloaded: 0,
+            files_skipped: 0,
+            skipped_by_reason: BTreeMap::new(),
+            files_failed: 0,
+            failures: vec![],
+            bytes_downloaded: 0,
+            bytes_transferred: 0,
+            hosts: BTreeMap::new(),
+        }
+    }
+}
+
+/**
+Collects the statistics of a run into a `RunReport`.
+
+Wrapped in an `Arc`, one reporter can be shared by several sessions.
+*/
+#[derive(Debug)]
+pub struct Reporter {
+    report: Mutex<RunReport>,
+    started: Instant,
+}
+
+impl Default for Reporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reporter {
+    pub fn new() -> Self {
+        Self {
+            report: Mutex::new(RunReport::default()),
+            started: Instant::now(),
+        }
+    }
+
+    /// A request was sent
+    pub fn request(&self, url: &Url) {
+        self.update(|report| report.host(url).requests += 1);
+    }
+
+    /// Some bytes of a response body were received
+    pub fn transferred(&self, url: &Url, bytes: u64) {
+        self.update(|report| {
+            report.bytes_transferred += bytes;
+            report.host(url).bytes += bytes;
+        });
+    }
+
+    /// A directory listing was crawled
+    pub fn directory_crawled(&self) {
+        self.update(|report| report.directories_crawled += 1);
+    }
+
+    /// The crawl of a root found some files
+    pub fn files_found(&self, files: u64) {
+        self.update(|report| report.files_found += files);
+    }
+
+    /// A file was downloaded completely
+    pub fn file_downloaded(&self, url: &Url, bytes: u64) {
+        self.update(|report| {
+            report.files_downloaded += 1;
+            report.bytes_downloaded += bytes;
+            report.host(url).files_downloaded += 1;
+        });
+    }
+
+    /// A file won't be downloaded
+    pub fn file_skipped(&self, reason: SkipReason) {
+        self.update(|report| {
+            report.files_skipped += 1;
+            *report.skipped_by_reason.entry(reason).or_default() += 1;
+        });
+    }
+
+    /// A file couldn't be downloaded
+    pub fn file_failed(&self, url: &Url, error: &OdGetError) {
+        self.update(|report| {
+            report.files_failed += 1;
+            report.host(url).files_failed += 1;
+            report.failures.push(FailedFile {
+                url: url.to_string(),
+                error: error.to_string(),
+            });
+        });
+    }
+
+    /// A root URL was processed (successfully or not)
+    pub fn root_done(&self, url: &Url, error: Option<&dyn Display>) {
+        self.update(|report| {
+            report.roots.push(RootOutcome {
+                url: url.to_string(),
+                ok: error.is_none(),
+                error: error.map(|error| format!("{:#}", error)),
+            })
+        });
+    }
+
+    /// The number of files which failed so far
+    pub fn files_failed(&self) -> u64 {
+        self.report
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .files_failed
+    }
+
+    /// Returns the report of the run so far (with the wall time up to now)
+    pub fn finish(&self) -> RunReport {
+        let mut report = self
+            .report
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        report.finished_at = Some(Utc::now().to_rfc3339());
+        report.wall_time_secs = self.started.elapsed().as_secs_f64();
+        report
+    }
+
+    fn update(&self, f: impl FnOnce(&mut RunReport)) {
+        f(&mut self.report.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+impl RunReport {
+    /// The statistics of the host of a URL
+    fn host(&mut self, url: &Url) -> &mut HostStats {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_owned(),
+            (None, _) => "unknown".to_owned(),
+        };
+
+        self.hosts.entry(host).or_default()
+    }
+}
@@ -0,0 +1,300 @@
+use super::error::{OdGetError, Result};
+use reqwest::Url;
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::{Mutex, PoisonError},
+};
+
+/// What to do when a server redirects to a URL outside of the scope
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectPolicy {
+    /// Follow the redirect anyway (credentials are never sent off-scope)
+    Follow,
+
+    /// Treat the redirect as an error
+    Refuse,
+}
+
+impl FromStr for RedirectPolicy {
+    type Err = OdGetError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "follow" => Ok(Self::Follow),
+            "refuse" => Ok(Self::Refuse),
+            other => Err(OdGetError::Parse(format!(
+                "Invalid redirect policy \"{}\" (must be `follow` or `refuse`)",
+                other
+            ))),
+        }
+    }
+}
+
+/// The queues through which URLs get enqueued
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Queue {
+    /// URLs found while crawling directory listings
+    Crawl,
+
+    /// URLs of files about to be downloaded
+    Download,
+}
+
+/**
+Decides which URLs belong to a crawl, and keeps track of the URLs already enqueued.
+
+A URL is in scope if it is on the root host and (with `no_parent`) below the
+directory of the root URL, or if it is on one of the explicitly allowed hosts.
+The root URL is a listing, so it is a directory even without a trailing `/`.
+*/
+#[derive(Debug)]
+pub struct Scope {
+    root: Url,
+
+    /// The path of the root directory (always ending with a `/`)
+    root_dir: String,
+
+    no_parent: bool,
+    allowed_hosts: Vec<String>,
+    pub redirect_policy: RedirectPolicy,
+
+    /// The normalized URLs which were already enqueued
+    seen: Mutex<HashSet<(Queue, String)>>,
+}
+
+impl Scope {
+    pub fn new(
+        root: &Url,
+        no_parent: bool,
+        allowed_hosts: Vec<String>,
+        redirect_policy: RedirectPolicy,
+    ) -> Self {
+        Self {
+            root: normalize(root),
+            root_dir: dir_path(root),
+            no_parent,
+            allowed_hosts: allowed_hosts
+                .into_iter()
+                .map(|host| host.to_ascii_lowercase())
+                .collect(),
+            redirect_policy,
+            seen: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// Checks if a URL lies within the scope
+    pub fn contains(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https" | "ftp" | "ftps") {
+            return false;
+        }
+
+        let host = match url.host_str() {
+            Some(host) => host,
+            None => return false,
+        };
+
+        if self.allowed_hosts.iter().any(|allowed| allowed == host) {
+            return true;
+        }
+
+        let same_host = Some(host) == self.root.host_str()
+            && url.port_or_known_default() == self.root.port_or_known_default();
+
+        same_host && (!self.no_parent || url.path().starts_with(&self.root_dir))
+    }
+
+    /**
+    Enqueues a URL (if it is in scope and wasn't enqueued before).
+
+    Returns the normalized URL, or `None` if the URL is to be skipped.
+    */
+    pub fn enqueue(&self, url: &Url, queue: Queue) -> Option<Url> {
+        let url = normalize(url);
+
+        if !self.contains(&url) {
+            return None;
+        }
+
+        let is_new = self
+            .seen
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert((queue, url.to_string()));
+
+        if is_new {
+            Some(url)
+        } else {
+            None
+        }
+    }
+
+    /// Forgets the URLs enqueued to a queue (at the start of a run)
+    pub fn reset(&self, queue: Queue) {
+        self.seen
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|(seen, _)| *seen != queue);
+    }
+
+    /// Returns an error if a redirect to the given URL must not be followed
+    pub fn check_redirect(&self, from: &Url, to: &Url) -> Result<()> {
+        if self.redirect_policy == RedirectPolicy::Refuse && !self.contains(to) {
+            return Err(OdGetError::Scope(format!(
+                "Refusing to follow redirect from {} to off-scope URL {}",
+                from, to
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/**
+Brings a URL into its canonical form (to detect duplicates).
+
+The scheme and host are already lower-cased, default ports removed,
+and dot-segments resolved by the URL parser.
+*/
+pub fn normalize(url: &Url) -> Url {
+    let mut url = url.clone();
+
+    // Fragments never change the resource
+    url.set_fragment(None);
+
+    // Drop an empty query (i.e. a trailing `?`)
+    if url.query() == Some("") {
+        url.set_query(None);
+    }
+
+    url
+}
+
+/// Returns the path of a directory URL with a trailing `/` (e.g. `/pub/` for `https://host/pub`)
+fn dir_path(url: &Url) -> String {
+    let path = url.path();
+
+    if path.ends_with('/') {
+        path.to_owned()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn scope(root: &str, no_parent: bool) -> Scope {
+        Scope::new(&url(root), no_parent, vec![], RedirectPolicy::Refuse)
+    }
+
+    #[test]
+    fn keeps_to_the_root_directory() {
+        for root in ["http://host/pub", "http://host/pub/"] {
+            let scope = scope(root, true);
+
+            assert!(scope.contains(&url("http://host/pub/")), "{}", root);
+            assert!(scope.contains(&url("http://host/pub/a/b.txt")), "{}", root);
+            assert!(!scope.contains(&url("http://host/")), "{}", root);
+            assert!(!scope.contains(&url("http://host/other/")), "{}", root);
+
+            // Siblings sharing the prefix of the root are other directories
+            assert!(!scope.contains(&url("http://host/pub2/")), "{}", root);
+            assert!(!scope.contains(&url("http://host/pub.txt")), "{}", root);
+        }
+    }
+
+    #[test]
+    fn follows_parents_on_request() {
+        let scope = scope("http://host/pub/", false);
+
+        assert!(scope.contains(&url("http://host/")));
+        assert!(scope.contains(&url("http://host/pub2/")));
+        assert!(!scope.contains(&url("http://other/pub/")));
+    }
+
+    #[test]
+    fn allows_other_hosts_on_request() {
+        let scope = Scope::new(
+            &url("http://host/pub/"),
+            true,
+            vec!["Mirror.example".to_owned()],
+            RedirectPolicy::Refuse,
+        );
+
+        assert!(scope.contains(&url("https://mirror.example/anything/")));
+        assert!(!scope.contains(&url("http://cdn.example/pub/")));
+    }
+
+    #[test]
+    fn compares_ports() {
+        let scope = scope("http://host/pub/", true);
+
+        assert!(scope.contains(&url("http://host:80/pub/a")));
+        assert!(!scope.contains(&url("http://host:8080/pub/a")));
+        assert!(!scope.contains(&url("https://host/pub/a")));
+    }
+
+    #[test]
+    fn only_allows_http_and_ftp() {
+        let scope = scope("ftp://host/pub/", true);
+
+        assert!(scope.contains(&url("ftp://host/pub/a")));
+        assert!(!scope.contains(&url("file:///pub/a")));
+        assert!(!scope.contains(&url("mailto:admin@host")));
+        assert!(!scope.contains(&url("sftp://host/pub/a")));
+    }
+
+    #[test]
+    fn checks_redirects() {
+        let from = url("http://host/pub/a");
+        let mut scope = scope("http://host/pub/", true);
+
+        assert!(scope
+            .check_redirect(&from, &url("http://host/pub/b"))
+            .is_ok());
+        assert!(matches!(
+            scope.check_redirect(&from, &url("http://evil/pub/b")),
+            Err(OdGetError::Scope(_))
+        ));
+
+        scope.redirect_policy = RedirectPolicy::Follow;
+        assert!(scope
+            .check_redirect(&from, &url("http://evil/pub/b"))
+            .is_ok());
+    }
+
+    #[test]
+    fn enqueues_urls_once_per_queue() {
+        let scope = scope("http://host/pub/", true);
+        let file = url("http://host/pub/a.txt#top");
+
+        assert_eq!(
+            scope.enqueue(&file, Queue::Crawl),
+            Some(url("http://host/pub/a.txt"))
+        );
+        assert_eq!(
+            scope.enqueue(&url("http://host/pub/a.txt"), Queue::Crawl),
+            None
+        );
+        assert!(scope.enqueue(&file, Queue::Download).is_some());
+        assert_eq!(scope.enqueue(&url("http://host/other"), Queue::Crawl), None);
+
+        scope.reset(Queue::Crawl);
+        assert!(scope.enqueue(&file, Queue::Crawl).is_some());
+        assert_eq!(scope.enqueue(&file, Queue::Download), None);
+    }
+
+    #[test]
+    fn normalizes_urls() {
+        assert_eq!(
+            normalize(&url("HTTP://Host:80/a/../pub/?#frag")),
+            url("http://host/pub/")
+        );
+    }
+}
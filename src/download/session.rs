@@ -0,0 +1,210 @@
+use super::{
+    auth::Auth,
+    crawl::ListingFormat,
+    error::{OdGetError, Result},
+    events::Events,
+    ftp,
+    report::Reporter,
+    scope::Scope,
+};
+use reqwest::{
+    self,
+    header::{HeaderMap, LOCATION},
+    Method, StatusCode, Url,
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::Mutex,
+    time::{self, Instant},
+};
+
+/**
+Spaces out requests to stay below a maximum number of requests per second.
+
+Wrapped in an `Arc`, one limiter can be shared by several sessions.
+*/
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next_slot: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn per_second(requests: u64) -> Self {
+        Self {
+            interval: Duration::from_secs_f64(1.0 / requests.max(1) as f64),
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits until the next request may be issued
+    pub async fn wait(&self) {
+        let mut next_slot = self.next_slot.lock().await;
+
+        let now = Instant::now();
+        if *next_slot > now {
+            time::sleep_until(*next_slot).await;
+        }
+
+        *next_slot = (*next_slot).max(now) + self.interval;
+    }
+}
+
+/// The maximum number of redirects followed for a single request
+const MAX_REDIRECTS: usize = 10;
+
+/**
+Everything needed to issue requests for one root URL.
+
+Bundles the (shared) HTTP client and rate limiter with the scope of the crawl
+and the credentials scoped to the root host (which are also used to log into FTP servers).
+
+The client should be built with `reqwest::redirect::Policy::none()`,
+so the session can check every redirect against the scope.
+*/
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub client: reqwest::Client,
+    pub scope: Arc<Scope>,
+    pub auth: Option<Auth>,
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// Delivers the events of the run (to no handler by default)
+    pub events: Events,
+
+    /// Collects the statistics of the run (may be shared by several sessions)
+    pub reporter: Arc<Reporter>,
+
+    /// The idle connections to FTP servers
+    pub ftp: ftp::Pool,
+
+    /// How the listings of HTTP(S) directories are requested
+    pub listing_format: ListingFormat,
+}
+
+impl Session {
+    pub fn new(
+        client: reqwest::Client,
+        scope: Scope,
+        auth: Option<Auth>,
+        rate_limiter: Option<Arc<RateLimiter>>,
+    ) -> Self {
+        Self {
+            client,
+            scope: Arc::new(scope),
+            auth,
+            rate_limiter,
+            events: Events::new(),
+            reporter: Arc::new(Reporter::new()),
+            ftp: ftp::Pool::default(),
+            listing_format: ListingFormat::default(),
+        }
+    }
+
+    /**
+    Issues a GET request, following redirects as permitted by the scope.
+
+    Redirects to off-scope URLs are only followed if the redirect policy allows it.
+    */
+    pub async fn get(&self, url: &str) -> Result<reqwest::Response> {
+        self.request(Method::GET, url, HeaderMap::new(), &[]).await
+    }
+
+    /**
+    Issues a request (e.g. a WebDAV `PROPFIND`), following redirects like `get`.
+
+    The headers & body are sent again with every redirect and authentication retry.
+    */
+    pub async fn request(
+        &self,
+        method: Method,
+        url: &str,
+        headers: HeaderMap,
+        body: &[u8],
+    ) -> Result<reqwest::Response> {
+        let mut url = Url::parse(url)?;
+
+        for _ in 0..MAX_REDIRECTS {
+            let res = self.authenticated(&method, &url, &headers, body).await?;
+
+            if !res.status().is_redirection() {
+                return Ok(res);
+            }
+
+            let next = match res.headers().get(LOCATION).map(|value| value.to_str()) {
+                Some(Ok(location)) => url.join(location)?,
+                _ => return Ok(res),
+            };
+
+            self.scope.check_redirect(&url, &next)?;
+            url = next;
+        }
+
+        Err(OdGetError::Scope(format!(
+            "Too many redirects (more than {})",
+            MAX_REDIRECTS
+        )))
+    }
+
+    /**
+    Issues a single request, authenticating if the server demands it.
+
+    Credentials are only sent to the root host. If the server answers with
+    `401 Unauthorized`, the request is retried once with an answer to its challenge.
+    */
+    async fn authenticated(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<reqwest::Response> {
+        self.reporter.request(url);
+        let res = self
+            .authorized(method, url, headers, body)
+            .await?
+            .send()
+            .await?;
+
+        if res.status() == StatusCode::UNAUTHORIZED {
+            if let Some(auth) = &self.auth {
+                if auth.applies_to(url) && auth.accept_challenge(&res) {
+                    self.reporter.request(url);
+                    return Ok(self
+                        .authorized(method, url, headers, body)
+                        .await?
+                        .send()
+                        .await?);
+                }
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Prepares a request carrying the credentials (if they apply to the URL)
+    async fn authorized(
+        &self,
+        method: &Method,
+        url: &Url,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<reqwest::RequestBuilder> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.wait().await;
+        }
+
+        let mut request = self
+            .client
+            .request(method.clone(), url.as_str())
+            .headers(headers.clone());
+        if !body.is_empty() {
+            request = request.body(body.to_vec());
+        }
+
+        match &self.auth {
+            Some(auth) if auth.applies_to(url) => auth.authorize(request, method, url),
+            _ => Ok(request),
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use super::{
+    fetch,
+    types::{FileLinkMetaData, Node},
+};
+use chrono::{Datelike, NaiveDateTime};
+use regex::Regex;
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt::{self, Display},
+};
+
+/// The date formats used by the listings (Apache's fancy & plain indices)
+const DATE_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%d-%b-%Y %H:%M",
+    "%d-%b-%Y %H:%M:%S",
+];
+
+/// The width of the bars of the date histograms
+const BAR_WIDTH: u64 = 40;
+
+/**
+The filters deciding which files would be downloaded.
+
+File filters & matchers apply to file names, path filters & matchers to the names
+of the sub-directories (a skipped directory skips everything below it).
+*/
+#[derive(Debug, Default, Clone)]
+pub struct Selection {
+    pub file_filter: Option<Regex>,
+    pub file_matcher: Option<Regex>,
+    pub path_filter: Option<Regex>,
+    pub path_matcher: Option<Regex>,
+}
+
+impl Selection {
+    /// Checks if no filter is set (i.e. everything is selected)
+    pub fn is_empty(&self) -> bool {
+        self.file_filter.is_none()
+            && self.file_matcher.is_none()
+            && self.path_filter.is_none()
+            && self.path_matcher.is_none()
+    }
+
+    fn wants_file(&self, name: &str) -> bool {
+        !matches!(&self.file_filter, Some(regex) if regex.is_match(name))
+            && !matches!(&self.file_matcher, Some(regex) if !regex.is_match(name))
+    }
+
+    fn wants_dir(&self, name: &str) -> bool {
+        !matches!(&self.path_filter, Some(regex) if regex.is_match(name))
+            && !matches!(&self.path_matcher, Some(regex) if !regex.is_match(name))
+    }
+}
+
+/// The number & (estimated) size of a group of files
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct Total {
+    pub files: u64,
+    pub bytes: u64,
+}
+
+impl Total {
+    fn add(&mut self, file: &FileLinkMetaData) {
+        self.files += 1;
+        self.bytes += file.size_in_bytes().unwrap_or_default();
+    }
+}
+
+/// A file of the largest-files list
+#[derive(Debug, Clone, Serialize)]
+pub struct LargeFile {
+    pub url: String,
+    pub bytes: u64,
+}
+
+/**
+A summary of the files of a crawl.
+
+Sizes are estimates, as listings round them (e.g. `4.5K`).
+*/
+#[derive(Debug, Serialize)]
+pub struct CrawlStats {
+    pub total: Total,
+
+    /// The files the selection would download (`None` without filters)
+    pub selected: Option<Total>,
+
+    pub by_extension: BTreeMap<String, Total>,
+    pub by_directory: BTreeMap<String, Total>,
+    pub largest: Vec<LargeFile>,
+    pub by_year: BTreeMap<String, Total>,
+    pub by_month: BTreeMap<String, Total>,
+}
+
+impl CrawlStats {
+    /// Collects the statistics of a crawled tree (keeping the `top` largest files)
+    pub fn collect(root: &Node, selection: &Selection, top: usize) -> Self {
+        let mut stats = Self {
+            total: Total::default(),
+            selected: if selection.is_empty() {
+                None
+            } else {
+                Some(Total::default())
+            },
+            by_extension: BTreeMap::new(),
+            by_directory: BTreeMap::new(),
+            largest: vec![],
+            by_year: BTreeMap::new(),
+            by_month: BTreeMap::new(),
+        };
+
+        if let Node::CrawledDir(_, children) = root {
+            for child in children {
+                let top_dir = match child {
+                    Node::File(_) => "(root)".to_owned(),
+                    Node::PendingDir(dir) | Node::CrawledDir(dir, _) => {
+                        dir_name(&dir.name).to_owned()
+                    }
+                };
+
+                stats.visit(child, &top_dir, selection, true);
+            }
+        }
+
+        stats
+            .largest
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.url.cmp(&b.url)));
+        stats.largest.truncate(top);
+
+        stats
+    }
+
+    fn visit(&mut self, node: &Node, top_dir: &str, selection: &Selection, selected: bool) {
+        match node {
+            Node::File(file) => {
+                // Listed names may be truncated, so use the names the downloader stores the files under
+                let name = fetch::stored_name(file);
+                self.total.add(file);
+
+                if let Some(total) = &mut self.selected {
+                    if selected && selection.wants_file(&name) {
+                        total.add(file);
+                    }
+                }
+
+                self.by_extension
+                    .entry(extension(&name))
+                    .or_default()
+                    .add(file);
+                self.by_directory
+                    .entry(top_dir.to_owned())
+                    .or_default()
+                    .add(file);
+
+                let (year, month) = match parse_date(&file.last_modified) {
+                    Some(date) => (
+                        date.year().to_string(),
+                        format!("{}-{:02}", date.year(), date.month()),
+                    ),
+                    None => ("unknown".to_owned(), "unknown".to_owned()),
+                };
+                self.by_year.entry(year).or_default().add(file);
+                self.by_month.entry(month).or_default().add(file);
+
+                self.largest.push(LargeFile {
+                    url: file.url.clone(),
+                    bytes: file.size_in_bytes().unwrap_or_default(),
+                });
+            }
+            Node::PendingDir(_) => {}
+            Node::CrawledDir(dir, children) => {
+                let selected = selected && selection.wants_dir(dir_name(&dir.name));
+
+                for child in children {
+                    self.visit(child, top_dir, selection, selected);
+                }
+            }
+        }
+    }
+}
+
+impl Display for CrawlStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Total: {}", self.total)?;
+
+        if let Some(selected) = &self.selected {
+            let share = if self.total.bytes > 0 {
+                selected.bytes as f64 / self.total.bytes as f64 * 100.0
+            } else {
+                0.0
+            };
+
+            writeln!(
+                f,
+                "Selected by the filters: {} ({:.1}% of the bytes)",
+                selected, share
+            )?;
+        }
+
+        write_totals(f, "By extension", &self.by_extension)?;
+        write_totals(f, "By top-level directory", &self.by_directory)?;
+
+        writeln!(f, "\nLargest files:")?;
+        for file in &self.largest {
+            writeln!(f, "  {:>10}  {}", human_bytes(file.bytes), file.url)?;
+        }
+
+        write_histogram(f, "Last modified (by year)", &self.by_year)?;
+        write_histogram(f, "Last modified (by month)", &self.by_month)
+    }
+}
+
+impl Display for Total {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} files, {}", self.files, human_bytes(self.bytes))
+    }
+}
+
+/// Writes groups of files (largest first)
+fn write_totals(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    totals: &BTreeMap<String, Total>,
+) -> fmt::Result {
+    let mut totals: Vec<_> = totals.iter().collect();
+    totals.sort_by_key(|(_, total)| Reverse(total.bytes));
+
+    writeln!(f, "\n{}:", title)?;
+    for (name, total) in totals {
+        writeln!(
+            f,
+            "  {:>10}  {:>6} files  {}",
+            human_bytes(total.bytes),
+            total.files,
+            name
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Writes groups of files as a histogram (scaled to the largest group)
+fn write_histogram(
+    f: &mut fmt::Formatter<'_>,
+    title: &str,
+    totals: &BTreeMap<String, Total>,
+) -> fmt::Result {
+    let max = totals
+        .values()
+        .map(|total| total.files)
+        .max()
+        .unwrap_or_default()
+        .max(1);
+
+    writeln!(f, "\n{}:", title)?;
+    for (name, total) in totals {
+        let bar = "#".repeat((total.files * BAR_WIDTH).div_ceil(max) as usize);
+        writeln!(f, "  {:>7}  {:>6}  {}", name, total.files, bar)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the last segment of a directory name (crawled directories are named by their path)
+fn dir_name(name: &str) -> &str {
+    name.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(name)
+}
+
+/// Returns the (lower-case) extension of a file name
+fn extension(name: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() && !extension.is_empty() => {
+            extension.to_ascii_lowercase()
+        }
+        _ => "(none)".to_owned(),
+    }
+}
+
+/// Parses the last-modified date of a listing entry
+fn parse_date(text: &str) -> Option<NaiveDateTime> {
+    DATE_FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text.trim(), format).ok())
+}
+
+/// Formats a number of bytes with a binary unit (e.g. `4.5 GiB`)
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::types::DirLinkMetaData;
+
+    fn file(url: &str, name: &str, size: &str, last_modified: &str) -> Node {
+        Node::File(FileLinkMetaData {
+            url: url.to_owned(),
+            name: name.to_owned(),
+            last_modified: last_modified.to_owned(),
+            size: size.to_owned(),
+            description: String::new(),
+            etag: String::new(),
+        })
+    }
+
+    fn dir(name: &str, children: Vec<Node>) -> Node {
+        Node::CrawledDir(
+            DirLinkMetaData {
+                url: format!("http://host/pub/{}", name),
+                name: name.to_owned(),
+                last_modified: String::new(),
+                description: String::new(),
+            },
+            children,
+        )
+    }
+
+    fn crawl() -> Node {
+        dir(
+            "",
+            vec![
+                file("http://host/pub/README", "README", "1K", "2023-05-01 10:00"),
+                // The listing truncates the name (and its extension)
+                file(
+                    "http://host/pub/a-very-long-name.ISO",
+                    "a-very-long-na..>",
+                    "2G",
+                    "01-Jan-2024 12:00",
+                ),
+                dir(
+                    "isos/",
+                    vec![
+                        file(
+                            "http://host/pub/isos/b.iso",
+                            "b.iso",
+                            "1.5G",
+                            "2024-02-03 04:05",
+                        ),
+                        dir(
+                            "isos/old/",
+                            vec![file("http://host/pub/isos/old/c.iso", "c.iso", "500M", "")],
+                        ),
+                    ],
+                ),
+            ],
+        )
+    }
+
+    fn totals(totals: &BTreeMap<String, Total>) -> Vec<(&str, (u64, u64))> {
+        totals
+            .iter()
+            .map(|(name, total)| (name.as_str(), (total.files, total.bytes)))
+            .collect()
+    }
+
+    #[test]
+    fn collects_the_totals() {
+        let stats = CrawlStats::collect(&crawl(), &Selection::default(), 2);
+
+        assert_eq!(
+            (stats.total.files, stats.total.bytes),
+            (4, (1 << 10) + (2 << 30) + (3 << 29) + (500 << 20))
+        );
+        assert!(stats.selected.is_none());
+
+        assert_eq!(
+            totals(&stats.by_extension),
+            [
+                ("(none)", (1, 1 << 10)),
+                ("iso", (3, (2 << 30) + (3 << 29) + (500 << 20))),
+            ]
+        );
+        assert_eq!(
+            totals(&stats.by_directory),
+            [
+                ("(root)", (2, (1 << 10) + (2 << 30))),
+                ("isos", (2, (3 << 29) + (500 << 20))),
+            ]
+        );
+        assert_eq!(
+            totals(&stats.by_year),
+            [
+                ("2023", (1, 1 << 10)),
+                ("2024", (2, (2 << 30) + (3 << 29))),
+                ("unknown", (1, 500 << 20)),
+            ]
+        );
+        assert_eq!(
+            stats.by_month.keys().collect::<Vec<_>>(),
+            ["2023-05", "2024-01", "2024-02", "unknown"]
+        );
+
+        let largest: Vec<&str> = stats.largest.iter().map(|file| file.url.as_str()).collect();
+        assert_eq!(
+            largest,
+            [
+                "http://host/pub/a-very-long-name.ISO",
+                "http://host/pub/isos/b.iso"
+            ]
+        );
+    }
+
+    #[test]
+    fn estimates_the_selection() {
+        let selection = Selection {
+            file_matcher: Some(Regex::new(r"\.(?i:iso)$").unwrap()),
+            path_filter: Some(Regex::new("^old$").unwrap()),
+            ..Selection::default()
+        };
+        let stats = CrawlStats::collect(&crawl(), &selection, 10);
+
+        let selected = stats.selected.unwrap();
+        assert_eq!((selected.files, selected.bytes), (2, (2 << 30) + (3 << 29)));
+        assert_eq!(stats.total.files, 4);
+    }
+
+    #[test]
+    fn formats_bytes() {
+        assert_eq!(human_bytes(0), "0 B");
+        assert_eq!(human_bytes(1023), "1023 B");
+        assert_eq!(human_bytes(1024), "1.0 KiB");
+        assert_eq!(human_bytes(1536), "1.5 KiB");
+        assert_eq!(human_bytes(5 << 30), "5.0 GiB");
+        assert_eq!(human_bytes(3 << 50), "3072.0 TiB");
+    }
+}
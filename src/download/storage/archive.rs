@@ -0,0 +1,533 @@
+use super::{SinkFile, StorageSink};
+use crate::download::error::{OdGetError, Result};
+use async_trait::async_trait;
+use std::{
+    fmt::{self, Debug},
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::Arc,
+    time::SystemTime,
+};
+use tar::{EntryType, Header};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex, task};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+/// The size of a tar block
+const BLOCK: u64 = 512;
+
+/// The length of the name field of a tar header
+const NAME_LENGTH: usize = 100;
+
+/// The size of the chunks copied from spilled files into the archive
+const COPY_CHUNK: usize = 64 * 1024;
+
+/// An archive format which entries can be streamed into (one at a time)
+pub trait Archive: Send {
+    /// Whether an entry can be written before its whole contents are known
+    fn can_stream(&self, size: Option<u64>) -> bool;
+
+    /// Starts an entry
+    fn start(&mut self, path: &str, size: Option<u64>) -> Result<()>;
+
+    /// Appends to the current entry
+    fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Completes the current entry
+    fn end(&mut self) -> Result<()>;
+
+    /// Gives up on the current entry (leaving the archive valid)
+    fn abort(&mut self) -> Result<()>;
+
+    /// Writes the end of the archive
+    fn close(&mut self) -> Result<()>;
+}
+
+/**
+Writes the files into an archive.
+
+Files are streamed into the archive while they are downloaded if the format
+allows it (zip archives, one file at a time). Otherwise, they are spilled to
+temporary files and written into the archive once they are complete.
+
+The archive is written on the blocking threads of the runtime (one operation at a time),
+so copying a large file into it doesn't hold up the other downloads.
+*/
+pub struct ArchiveSink<A> {
+    state: Arc<Mutex<ArchiveState<A>>>,
+}
+
+/// Writes the files into a tar archive (which can be a stream, e.g. stdout)
+pub type TarSink = ArchiveSink<TarArchive>;
+
+/// Writes the files into a zip archive (which must be a seekable file)
+pub type ZipSink = ArchiveSink<ZipArchive>;
+
+struct ArchiveState<A> {
+    archive: A,
+
+    /// Whether an entry is being streamed
+    streaming: bool,
+
+    /// Whether the end of the archive was written
+    closed: bool,
+
+    /// Spilled files waiting for the streamed entry to complete
+    pending: Vec<(String, File, u64)>,
+}
+
+impl<A: Archive> ArchiveState<A> {
+    /// Writes a whole (spilled) file as an entry
+    fn write_entry(&mut self, path: &str, file: &mut File, size: u64) -> Result<()> {
+        self.archive.start(path, Some(size))?;
+
+        file.seek(SeekFrom::Start(0))?;
+        let mut chunk = vec![0; COPY_CHUNK];
+        loop {
+            let length = file.read(&mut chunk)?;
+            if length == 0 {
+                break;
+            }
+            self.archive.write(&chunk[..length])?;
+        }
+
+        self.archive.end()
+    }
+
+    /// Writes the spilled files (once no entry is streamed anymore)
+    fn write_pending(&mut self) -> Result<()> {
+        for (path, mut file, size) in std::mem::take(&mut self.pending) {
+            self.write_entry(&path, &mut file, size)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<A: Archive> ArchiveSink<A> {
+    fn new(archive: A) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ArchiveState {
+                archive,
+                streaming: false,
+                closed: false,
+                pending: vec![],
+            })),
+        }
+    }
+}
+
+impl<A> Debug for ArchiveSink<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveSink").finish_non_exhaustive()
+    }
+}
+
+/**
+Runs blocking work on the state of an archive (on a blocking thread of the runtime).
+
+The lock is awaited without blocking, and held until the work is done.
+*/
+async fn with_state<A, T>(
+    state: &Arc<Mutex<ArchiveState<A>>>,
+    work: impl FnOnce(&mut ArchiveState<A>) -> Result<T> + Send + 'static,
+) -> Result<T>
+where
+    A: Archive + 'static,
+    T: Send + 'static,
+{
+    let mut state = state.clone().lock_owned().await;
+
+    task::spawn_blocking(move || work(&mut state))
+        .await
+        .map_err(|err| OdGetError::Storage(format!("Writing the archive failed: {}", err)))?
+}
+
+/// How a new file is added to the archive
+enum NewEntry {
+    Streamed,
+    Spilled(File),
+}
+
+#[async_trait]
+impl<A: Archive + 'static> StorageSink for ArchiveSink<A> {
+    async fn create(&self, path: &str, size: Option<u64>) -> Result<Box<dyn SinkFile>> {
+        let entry_path = path.to_owned();
+        let entry = with_state(&self.state, move |state| {
+            if state.closed {
+                return Err(OdGetError::Storage(format!(
+                    "Cannot add {} to the archive (it is already complete)",
+                    entry_path
+                )));
+            }
+
+            if !state.streaming && state.archive.can_stream(size) {
+                state.archive.start(&entry_path, size)?;
+                state.streaming = true;
+                Ok(NewEntry::Streamed)
+            } else {
+                Ok(NewEntry::Spilled(tempfile::tempfile()?))
+            }
+        })
+        .await?;
+
+        Ok(match entry {
+            NewEntry::Streamed => Box::new(StreamedEntry {
+                state: self.state.clone(),
+            }),
+            NewEntry::Spilled(file) => Box::new(SpilledEntry {
+                state: self.state.clone(),
+                path: path.to_owned(),
+                file: fs::File::from_std(file),
+                size: 0,
+            }),
+        })
+    }
+
+    async fn finish(&self) -> Result<()> {
+        with_state(&self.state, |state| {
+            if !state.closed {
+                state.write_pending()?;
+                state.archive.close()?;
+                state.closed = true;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// A file written into the archive as it arrives
+struct StreamedEntry<A> {
+    state: Arc<Mutex<ArchiveState<A>>>,
+}
+
+#[async_trait]
+impl<A: Archive + 'static> SinkFile for StreamedEntry<A> {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        let chunk = chunk.to_vec();
+        with_state(&self.state, move |state| state.archive.write(&chunk)).await
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        with_state(&self.state, |state| {
+            state.streaming = false;
+
+            let result = state.archive.end();
+            state.write_pending()?;
+            result
+        })
+        .await
+    }
+
+    async fn abort(self: Box<Self>) -> Result<()> {
+        with_state(&self.state, |state| {
+            state.streaming = false;
+
+            state.archive.abort()?;
+            state.write_pending()
+        })
+        .await
+    }
+}
+
+/**
+A file collected in a temporary file (and written into the archive when complete).
+
+Aborted files never reach the archive, the temporary file is removed when dropped.
+*/
+struct SpilledEntry<A> {
+    state: Arc<Mutex<ArchiveState<A>>>,
+    path: String,
+    file: fs::File,
+    size: u64,
+}
+
+#[async_trait]
+impl<A: Archive + 'static> SinkFile for SpilledEntry<A> {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.file.write_all(chunk).await?;
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+
+    async fn finish(self: Box<Self>) -> Result<()> {
+        let SpilledEntry {
+            state,
+            path,
+            mut file,
+            size,
+        } = *self;
+
+        file.flush().await?;
+        let mut file = file.into_std().await;
+
+        with_state(&state, move |state| {
+            if state.streaming {
+                state.pending.push((path, file, size));
+                Ok(())
+            } else {
+                state.write_entry(&path, &mut file, size)
+            }
+        })
+        .await
+    }
+}
+
+/**
+Writes tar entries (GNU format).
+
+The size of an entry is written before its contents, and a stream (e.g. stdout)
+can't take back an entry. So no entry is streamed: the header is only written
+once a file is complete (see `SpilledEntry`), and failed downloads leave no trace.
+*/
+pub struct TarArchive {
+    writer: Box<dyn Write + Send>,
+
+    /// The size announced for the current entry
+    expected: u64,
+
+    /// The bytes written to the current entry
+    written: u64,
+}
+
+impl TarSink {
+    /// Writes a tar archive to a stream (e.g. stdout)
+    pub fn tar(writer: impl Write + Send + 'static) -> Self {
+        Self::new(TarArchive {
+            writer: Box::new(writer),
+            expected: 0,
+            written: 0,
+        })
+    }
+
+    /// Writes a tar archive to a file (appending to it if it exists, e.g. to resume a download)
+    pub fn tar_file(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        // Continue after the last entry (overwriting the end of the archive)
+        let end = tar_end(&mut file).map_err(|err| {
+            OdGetError::Storage(format!("Cannot append to {}: {}", path.display(), err))
+        })?;
+        file.set_len(end)?;
+        file.seek(SeekFrom::Start(end))?;
+
+        Ok(Self::tar(file))
+    }
+}
+
+/// Finds the end of the entries of a tar archive (by skipping from header to header)
+fn tar_end(file: &mut File) -> io::Result<u64> {
+    let length = file.metadata()?.len();
+    let mut position = 0;
+    let mut block = [0; BLOCK as usize];
+
+    while position + BLOCK <= length {
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut block)?;
+
+        // An empty block marks the end of the archive
+        if block.iter().all(|byte| *byte == 0) {
+            return Ok(position);
+        }
+
+        let header = Header::from_byte_slice(&block);
+        header.cksum()?;
+        position += BLOCK + padded(header.entry_size()?);
+    }
+
+    if position == length {
+        Ok(position)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a complete tar archive",
+        ))
+    }
+}
+
+/// Rounds a size up to whole blocks
+fn padded(size: u64) -> u64 {
+    size.div_ceil(BLOCK) * BLOCK
+}
+
+impl TarArchive {
+    fn write_header(&mut self, path: &str, size: u64, entry_type: EntryType) -> Result<()> {
+        let mut header = Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_size(size);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|time| time.as_secs())
+                .unwrap_or_default(),
+        );
+
+        // The name gets truncated (long names precede the entry, see `start`)
+        let name = &mut header.as_old_mut().name;
+        let length = path.len().min(NAME_LENGTH);
+        name[..length].copy_from_slice(&path.as_bytes()[..length]);
+
+        header.set_cksum();
+        self.writer.write_all(header.as_bytes())?;
+        Ok(())
+    }
+
+    /// Pads the current entry to whole blocks
+    fn pad(&mut self, size: u64) -> Result<()> {
+        let padding = padded(size) - size;
+        self.writer.write_all(&vec![0; padding as usize])?;
+        Ok(())
+    }
+}
+
+impl Archive for TarArchive {
+    fn can_stream(&self, _size: Option<u64>) -> bool {
+        false
+    }
+
+    fn start(&mut self, path: &str, size: Option<u64>) -> Result<()> {
+        let size = size.unwrap_or_default();
+
+        // Names not fitting into the header precede it as an entry of their own
+        if path.len() > NAME_LENGTH {
+            let name = [path.as_bytes(), &[0]].concat();
+            self.write_header("././@LongLink", name.len() as u64, EntryType::GNULongName)?;
+            self.writer.write_all(&name)?;
+            self.pad(name.len() as u64)?;
+        }
+
+        self.write_header(path, size, EntryType::Regular)?;
+        self.expected = size;
+        self.written = 0;
+
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        if self.written + chunk.len() as u64 > self.expected {
+            return Err(OdGetError::Storage(
+                "The file is larger than its announced size".to_owned(),
+            ));
+        }
+
+        self.writer.write_all(chunk)?;
+        self.written += chunk.len() as u64;
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<()> {
+        let complete = self.written == self.expected;
+
+        // Keep the archive valid either way
+        self.abort()?;
+
+        if complete {
+            Ok(())
+        } else {
+            Err(OdGetError::Storage(
+                "The file is smaller than its announced size".to_owned(),
+            ))
+        }
+    }
+
+    fn abort(&mut self) -> Result<()> {
+        let missing = self.expected - self.written;
+        self.writer.write_all(&vec![0; missing as usize])?;
+        self.pad(self.expected)?;
+
+        self.expected = 0;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.writer.write_all(&[0; 2 * BLOCK as usize])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Writes zip entries (streaming all of them, aborted entries are removed)
+pub struct ZipArchive {
+    writer: Option<ZipWriter<File>>,
+}
+
+impl ZipSink {
+    /// Writes a zip archive to a file (appending to it if it exists, e.g. to resume a download)
+    pub fn zip_file(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let writer = if file.metadata()?.len() > 0 {
+            ZipWriter::new_append(file).map_err(|err| {
+                OdGetError::Storage(format!("Cannot append to {}: {}", path.display(), err))
+            })?
+        } else {
+            ZipWriter::new(file)
+        };
+
+        Ok(Self::new(ZipArchive {
+            writer: Some(writer),
+        }))
+    }
+}
+
+impl ZipArchive {
+    fn writer(&mut self) -> Result<&mut ZipWriter<File>> {
+        self.writer
+            .as_mut()
+            .ok_or_else(|| OdGetError::Storage("The zip archive is already complete".to_owned()))
+    }
+}
+
+impl Archive for ZipArchive {
+    fn can_stream(&self, _size: Option<u64>) -> bool {
+        true
+    }
+
+    fn start(&mut self, path: &str, size: Option<u64>) -> Result<()> {
+        // Files of unknown size might need the 64 bit extensions
+        let options =
+            SimpleFileOptions::default().large_file(size.unwrap_or(u64::MAX) >= u32::MAX as u64);
+
+        self.writer()?
+            .start_file(path, options)
+            .map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        self.writer()?.write_all(chunk)?;
+        Ok(())
+    }
+
+    fn end(&mut self) -> Result<()> {
+        // The entry is completed by the next one (or the end of the archive)
+        Ok(())
+    }
+
+    fn abort(&mut self) -> Result<()> {
+        self.writer()?.abort_file().map_err(io::Error::from)?;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            writer.finish().map_err(io::Error::from)?;
+        }
+
+        Ok(())
+    }
+}
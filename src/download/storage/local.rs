@@ -0,0 +1,91 @@
+use super::{SinkFile, StorageSink};
+use crate::download::error::{OdGetError, Result};
+use async_trait::async_trait;
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tokio::{
+    fs,
+    io::{self, AsyncWrite, AsyncWriteExt},
+};
+
+/// Writes the files below a local directory (creating the sub-directories as needed)
+#[derive(Debug, Clone)]
+pub struct LocalSink {
+    root: PathBuf,
+}
+
+impl LocalSink {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[async_trait]
+impl StorageSink for LocalSink {
+    async fn create(&self, path: &str, _size: Option<u64>) -> Result<Box<dyn SinkFile>> {
+        let path = self.root.join(path);
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .await?;
+
+        Ok(Box::new(WriterFile { writer: file }))
+    }
+}
+
+/**
+Writes a single file to stdout (e.g. to pipe it into another program).
+
+Any further file is refused, as the contents would be mixed up.
+*/
+#[derive(Debug, Default)]
+pub struct StdoutSink {
+    used: AtomicBool,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl StorageSink for StdoutSink {
+    async fn create(&self, path: &str, _size: Option<u64>) -> Result<Box<dyn SinkFile>> {
+        if self.used.swap(true, Ordering::SeqCst) {
+            return Err(OdGetError::Storage(format!(
+                "Cannot write {} to stdout (it only takes a single file)",
+                path
+            )));
+        }
+
+        Ok(Box::new(WriterFile {
+            writer: io::stdout(),
+        }))
+    }
+}
+
+/// A file written to an async writer (a local file or stdout)
+struct WriterFile<W> {
+    writer: W,
+}
+
+#[async_trait]
+impl<W: AsyncWrite + Unpin + Send> SinkFile for WriterFile<W> {
+    async fn write(&mut self, chunk: &[u8]) -> Result<()> {
+        Ok(self.writer.write_all(chunk).await?)
+    }
+
+    async fn finish(mut self: Box<Self>) -> Result<()> {
+        Ok(self.writer.flush().await?)
+    }
+}
@@ -0,0 +1,42 @@
+mod archive;
+mod local;
+mod s3;
+
+pub use archive::{Archive, ArchiveSink, TarArchive, TarSink, ZipArchive, ZipSink};
+pub use local::{LocalSink, StdoutSink};
+pub use s3::{S3Config, S3Sink};
+
+use super::error::Result;
+use async_trait::async_trait;
+use std::fmt::Debug;
+
+/**
+Receives the downloaded files (e.g. writes them to disk, into an archive or an object store).
+
+Paths are relative and separated by `/` (e.g. `pub/linux/file.iso`).
+*/
+#[async_trait]
+pub trait StorageSink: Send + Sync + Debug {
+    /// Starts a new file (with its exact size, if known)
+    async fn create(&self, path: &str, size: Option<u64>) -> Result<Box<dyn SinkFile>>;
+
+    /// Completes the storage after the last file (e.g. writes the end of an archive)
+    async fn finish(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A file being written to a storage sink
+#[async_trait]
+pub trait SinkFile: Send {
+    /// Appends a chunk of data
+    async fn write(&mut self, chunk: &[u8]) -> Result<()>;
+
+    /// Completes the file
+    async fn finish(self: Box<Self>) -> Result<()>;
+
+    /// Gives up on the file (e.g. after a failed download), keeping the storage usable
+    async fn abort(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
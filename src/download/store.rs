@@ -0,0 +1,1167 @@
+use super::{
+    error::{OdGetError, Result},
+    report::SkipReason,
+    types::{CrawlingState, DirLinkMetaData, FileLinkMetaData, FileStatus, Node, StateStore},
+};
+use chrono::Utc;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/**
+A line of the state store file (JSON Lines).
+
+The file starts with a header (naming the format version), followed by the crawled tree in pre-order
+(each entry with its depth below the root), and the status of the files.
+Status changes & modifications are appended as they happen, so the file
+never has to be rewritten during a run (the last status of a URL wins).
+*/
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Record {
+    Header {
+        version: u64,
+        created_at: String,
+    },
+    Dir {
+        depth: usize,
+        crawled: bool,
+        url: String,
+        name: String,
+        last_modified: String,
+        description: String,
+    },
+    File {
+        depth: usize,
+        url: String,
+        name: String,
+        last_modified: String,
+        size: String,
+        description: String,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        etag: String,
+    },
+    CrawlComplete {
+        at: String,
+    },
+    /// A downloaded file (format version 2, superseded by `Status`)
+    Downloaded {
+        url: String,
+    },
+    Status {
+        url: String,
+        #[serde(flatten)]
+        status: FileStatus,
+    },
+    Modified {
+        at: String,
+    },
+}
+
+/// The state store format before the JSON Lines one (a single JSON document)
+#[derive(Debug, Deserialize)]
+struct StateStoreV1 {
+    created_at: String,
+    last_modified: String,
+    crawling_state: CrawlingState,
+    downloaded_urls: Vec<String>,
+}
+
+impl From<StateStoreV1> for StateStore {
+    fn from(old: StateStoreV1) -> Self {
+        Self {
+            created_at: old.created_at,
+            last_modified: old.last_modified,
+            crawling_state: old.crawling_state,
+            file_status: old
+                .downloaded_urls
+                .into_iter()
+                .map(|url| (url, FileStatus::Done))
+                .collect(),
+            stale_entries: 0,
+        }
+    }
+}
+
+impl StateStore {
+    /**
+    Loads a state store from disk (or makes a new one if the file doesn't exist or is empty).
+
+    Stores written by other versions of od-get are refused: newer ones can't be read,
+    older ones have to be migrated explicitly first (see `StateStore::migrate`).
+    */
+    pub fn load(path: &Path) -> Result<Self> {
+        match detect_version(path)? {
+            None => Ok(Self::new()),
+            Some(Self::VERSION) => read_records(path),
+            Some(version) if version > Self::VERSION => Err(OdGetError::State(format!(
+                "The state store {} was written by a newer version of od-get (format version {}, supported up to {})",
+                path.display(),
+                version,
+                Self::VERSION
+            ))),
+            Some(version) => Err(OdGetError::State(format!(
+                "The state store {} has the old format version {} (current is {}), migrate it with `od-get migrate {}`",
+                path.display(),
+                version,
+                Self::VERSION,
+                path.display()
+            ))),
+        }
+    }
+
+    /**
+    Migrates a state store to the current format version (if needed).
+
+    The old file is kept next to the store (e.g. with a `.v1.bak` suffix).
+    Returns the version the store had before, or `None` if it is missing.
+    */
+    pub fn migrate(path: &Path) -> Result<Option<u64>> {
+        let version = match detect_version(path)? {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+
+        if version > Self::VERSION {
+            return Err(OdGetError::State(format!(
+                "Cannot migrate the state store {} (format version {} is newer than {})",
+                path.display(),
+                version,
+                Self::VERSION
+            )));
+        }
+
+        if version == Self::VERSION {
+            return Ok(Some(version));
+        }
+
+        // Every migration reads one of the old formats into the current state store
+        let store = match version {
+            1 => read_v1(path)?,
+            // Version 2 only differs by its `downloaded` entries (read as done)
+            2 => read_records(path)?,
+            other => {
+                return Err(OdGetError::State(format!(
+                    "Unknown state store format version {}",
+                    other
+                )))
+            }
+        };
+
+        let backup = with_suffix(path, &format!(".v{}.bak", version));
+        fs::copy(path, &backup)?;
+        store.save(path)?;
+
+        info!(
+            "Migrated the state store {} from version {} to {} (backup at {})",
+            path.display(),
+            version,
+            Self::VERSION,
+            backup.display()
+        );
+
+        Ok(Some(version))
+    }
+
+    /**
+    Writes the whole state store to disk (compacting the appended entries).
+
+    The file is replaced atomically, so a crash never leaves a broken store behind.
+    */
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temp_path = with_suffix(path, ".tmp");
+        let mut out = BufWriter::new(File::create(&temp_path)?);
+
+        write_record(
+            &mut out,
+            &Record::Header {
+                version: Self::VERSION,
+                created_at: self.created_at.clone(),
+            },
+        )?;
+
+        let (root, complete) = match &self.crawling_state {
+            CrawlingState::Complete(root) => (Some(root), true),
+            CrawlingState::Partial(root) => (Some(root), false),
+            CrawlingState::None => (None, false),
+        };
+
+        if let Some(root) = root {
+            write_node(&mut out, root, 0)?;
+        }
+
+        if complete {
+            write_record(
+                &mut out,
+                &Record::CrawlComplete {
+                    at: self.last_modified.clone(),
+                },
+            )?;
+        }
+
+        // Pending files don't need an entry (sorted to keep the file stable)
+        let mut statuses: Vec<_> = self
+            .file_status
+            .iter()
+            .filter(|(_, status)| **status != FileStatus::Pending)
+            .collect();
+        statuses.sort_by_key(|(url, _)| url.as_str());
+
+        for (url, status) in statuses {
+            write_record(
+                &mut out,
+                &Record::Status {
+                    url: url.clone(),
+                    status: status.clone(),
+                },
+            )?;
+        }
+
+        write_record(
+            &mut out,
+            &Record::Modified {
+                at: self.last_modified.clone(),
+            },
+        )?;
+
+        out.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+        fs::rename(&temp_path, path)?;
+
+        Ok(())
+    }
+}
+
+/**
+Detects the format version of a state store (`None` if it is missing or empty).
+
+JSON Lines stores start with a header naming their version.
+Version 1 stores are a single (pretty-printed) JSON document without a version.
+*/
+fn detect_version(path: &Path) -> Result<Option<u64>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(io::Error::new(
+                err.kind(),
+                format!("Cannot open the state store {}: {}", path.display(), err),
+            )
+            .into())
+        }
+    };
+
+    let mut first = String::new();
+    BufReader::new(file).read_line(&mut first)?;
+
+    if first.trim().is_empty() {
+        return Ok(None);
+    }
+
+    if let Ok(Record::Header { version, .. }) = serde_json::from_str(&first) {
+        return Ok(Some(version));
+    }
+
+    if first.trim() == "{" {
+        return Ok(Some(1));
+    }
+
+    Err(OdGetError::State(format!(
+        "The state store {} is corrupted or not a state store (it starts with neither a header nor a v1 document)",
+        path.display()
+    )))
+}
+
+/**
+Builds the state store from the records of a JSON Lines file.
+
+A broken last line (e.g. from a crash while appending) is ignored with a warning,
+any other broken line is an error.
+*/
+fn read_records(path: &Path) -> Result<StateStore> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut store = StateStore::new();
+
+    // The directories above the current entry (with their children so far)
+    let mut stack: Vec<(DirLinkMetaData, bool, Vec<Node>)> = vec![];
+    let mut root = None;
+    let mut complete = false;
+    let mut modified: usize = 0;
+
+    let mut line = String::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+
+        number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: Record = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(_) if !line.ends_with('\n') => {
+                warn!(
+                    "Ignoring the incomplete last entry (line {}) of the state store {}",
+                    number,
+                    path.display()
+                );
+                break;
+            }
+            Err(err) => {
+                return Err(OdGetError::State(format!(
+                    "Invalid entry in line {} of the state store {}: {}",
+                    number,
+                    path.display(),
+                    err
+                )))
+            }
+        };
+
+        match record {
+            Record::Header {
+                version,
+                created_at,
+            } => {
+                if number != 1 {
+                    return Err(OdGetError::State(format!(
+                        "Unexpected header in line {} of the state store",
+                        number
+                    )));
+                }
+
+                debug!("Reading state store format version {}", version);
+                store.last_modified = created_at.clone();
+                store.created_at = created_at;
+            }
+            Record::Dir {
+                depth,
+                crawled,
+                url,
+                name,
+                last_modified,
+                description,
+            } => {
+                close_dirs(&mut stack, &mut root, depth)?;
+                stack.push((
+                    DirLinkMetaData {
+                        url,
+                        name,
+                        last_modified,
+                        description,
+                    },
+                    crawled,
+                    vec![],
+                ));
+            }
+            Record::File {
+                depth,
+                url,
+                name,
+                last_modified,
+                size,
+                description,
+                etag,
+            } => {
+                close_dirs(&mut stack, &mut root, depth)?;
+                stack
+                    .last_mut()
+                    .ok_or_else(|| {
+                        OdGetError::State(format!(
+                            "File outside of a directory in line {} of the state store",
+                            number
+                        ))
+                    })?
+                    .2
+                    .push(Node::File(FileLinkMetaData {
+                        url,
+                        name,
+                        last_modified,
+                        size,
+                        description,
+                        etag,
+                    }));
+            }
+            Record::CrawlComplete { at } => {
+                complete = true;
+                store.last_modified = at;
+            }
+            Record::Downloaded { url } => {
+                if store.file_status.insert(url, FileStatus::Done).is_some() {
+                    store.stale_entries += 1;
+                }
+            }
+            Record::Status { url, status } => {
+                if store.file_status.insert(url, status).is_some() {
+                    store.stale_entries += 1;
+                }
+            }
+            Record::Modified { at } => {
+                modified += 1;
+                store.last_modified = at;
+            }
+        }
+    }
+
+    // Only the last modification time is kept
+    store.stale_entries += modified.saturating_sub(1);
+
+    close_dirs(&mut stack, &mut root, 0)?;
+
+    store.crawling_state = match (root, complete) {
+        (Some(root), true) => CrawlingState::Complete(root),
+        (Some(root), false) => CrawlingState::Partial(root),
+        (None, _) => CrawlingState::None,
+    };
+
+    Ok(store)
+}
+
+/// Reads a version 1 state store (a single JSON document)
+fn read_v1(path: &Path) -> Result<StateStore> {
+    let old: StateStoreV1 = serde_json::from_str(&fs::read_to_string(path)?).map_err(|err| {
+        OdGetError::State(format!(
+            "Invalid v1 state store {}: {}",
+            path.display(),
+            err
+        ))
+    })?;
+
+    Ok(StateStore::from(old))
+}
+
+/// Appends a suffix to a path (e.g. `state.json` to `state.json.tmp`)
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Attaches the directories deeper than `depth` to their parents (the last one becomes the root)
+fn close_dirs(
+    stack: &mut Vec<(DirLinkMetaData, bool, Vec<Node>)>,
+    root: &mut Option<Node>,
+    depth: usize,
+) -> Result<()> {
+    if depth > stack.len() || (depth == 0 && root.is_some()) {
+        return Err(OdGetError::State(format!(
+            "Invalid depth {} in the state store",
+            depth
+        )));
+    }
+
+    while stack.len() > depth {
+        let (meta, crawled, children) = stack.pop().expect("Stack is not empty");
+        let node = if crawled {
+            Node::CrawledDir(meta, children)
+        } else {
+            Node::PendingDir(meta)
+        };
+
+        match stack.last_mut() {
+            Some(parent) => parent.2.push(node),
+            None => *root = Some(node),
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a node and its children in pre-order
+fn write_node(out: &mut impl Write, node: &Node, depth: usize) -> Result<()> {
+    match node {
+        Node::File(file) => write_record(
+            out,
+            &Record::File {
+                depth,
+                url: file.url.clone(),
+                name: file.name.clone(),
+                last_modified: file.last_modified.clone(),
+                size: file.size.clone(),
+                description: file.description.clone(),
+                etag: file.etag.clone(),
+            },
+        ),
+        Node::PendingDir(dir) => write_record(out, &dir_record(dir, depth, false)),
+        Node::CrawledDir(dir, children) => {
+            write_record(out, &dir_record(dir, depth, true))?;
+
+            for child in children {
+                write_node(out, child, depth + 1)?;
+            }
+
+            Ok(())
+        }
+    }
+}
+
+fn dir_record(dir: &DirLinkMetaData, depth: usize, crawled: bool) -> Record {
+    Record::Dir {
+        depth,
+        crawled,
+        url: dir.url.clone(),
+        name: dir.name.clone(),
+        last_modified: dir.last_modified.clone(),
+        description: dir.description.clone(),
+    }
+}
+
+fn write_record(out: &mut impl Write, record: &Record) -> Result<()> {
+    serde_json::to_writer(&mut *out, record)?;
+    out.write_all(b"\n")?;
+    Ok(())
+}
+
+/**
+The download status of every file (with constant-time lookups).
+
+Files without a status are pending. With a journal, every status change is
+appended to the state store right away, so an interrupted run loses no progress.
+*/
+#[derive(Debug, Default)]
+pub struct StatusMap {
+    statuses: HashMap<String, FileStatus>,
+    journal: Option<(File, PathBuf)>,
+
+    /// The entries of the journal superseded by later ones
+    stale_entries: usize,
+}
+
+impl StatusMap {
+    /// Makes a status map which only lives in memory
+    pub fn new(statuses: HashMap<String, FileStatus>) -> Self {
+        Self {
+            statuses,
+            journal: None,
+            stale_entries: 0,
+        }
+    }
+
+    /// Makes a status map appending to the state store at the given path (see `StateStore::stale_entries`)
+    pub fn with_journal(
+        statuses: HashMap<String, FileStatus>,
+        stale_entries: usize,
+        path: &Path,
+    ) -> Result<Self> {
+        Ok(Self {
+            statuses,
+            journal: Some((open_journal(path)?, path.to_owned())),
+            stale_entries,
+        })
+    }
+
+    /// Returns the status of a file (`Pending` if it has none)
+    pub fn get(&self, url: &str) -> &FileStatus {
+        self.statuses.get(url).unwrap_or(&FileStatus::Pending)
+    }
+
+    pub fn is_done(&self, url: &str) -> bool {
+        *self.get(url) == FileStatus::Done
+    }
+
+    pub fn is_failed(&self, url: &str) -> bool {
+        matches!(self.get(url), FileStatus::Failed { .. })
+    }
+
+    /// Iterates over all files with a status (other than pending)
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &FileStatus)> {
+        self.statuses
+            .iter()
+            .filter(|(_, status)| **status != FileStatus::Pending)
+            .map(|(url, status)| (url.as_str(), status))
+    }
+
+    /// The number of files which were downloaded completely
+    pub fn done(&self) -> usize {
+        self.iter()
+            .filter(|(_, status)| **status == FileStatus::Done)
+            .count()
+    }
+
+    /// The files which failed (with the reasons)
+    pub fn failed(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.iter().filter_map(|(url, status)| match status {
+            FileStatus::Failed { reason } => Some((url, reason.as_str())),
+            _ => None,
+        })
+    }
+
+    /// Sets the status of a file (and appends it to the journal if it changed)
+    pub fn set(&mut self, url: &str, status: FileStatus) -> Result<()> {
+        if self.get(url) == &status {
+            return Ok(());
+        }
+
+        if let Some((journal, _)) = &mut self.journal {
+            write_record(
+                journal,
+                &Record::Status {
+                    url: url.to_owned(),
+                    status: status.clone(),
+                },
+            )?;
+        }
+
+        // Files re-queued by `retry_failed` still have their old entry
+        if self.statuses.insert(url.to_owned(), status).is_some() {
+            self.stale_entries += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Marks a file as skipped (unless it was downloaded or failed before)
+    pub fn skip(&mut self, url: &str, reason: SkipReason) -> Result<()> {
+        match self.get(url) {
+            FileStatus::Done | FileStatus::Failed { .. } => Ok(()),
+            _ => self.set(url, FileStatus::Skipped { reason }),
+        }
+    }
+
+    /**
+    Re-queues the files which failed (making them pending again), returning their number.
+
+    This only changes the map in memory, the new status is journaled once the files are retried.
+    */
+    pub fn retry_failed(&mut self) -> usize {
+        let mut retried = 0;
+        for status in self.statuses.values_mut() {
+            if let FileStatus::Failed { .. } = status {
+                *status = FileStatus::Pending;
+                retried += 1;
+            }
+        }
+
+        retried
+    }
+
+    /**
+    Records the end of a run (in the journal).
+
+    Once the superseded entries of the journal outnumber the statuses, the state store
+    is rewritten compactly instead (with the crawl of the given store).
+    */
+    pub fn touch(&mut self, store: &mut StateStore) -> Result<()> {
+        let (journal, path) = match &mut self.journal {
+            Some(journal) => journal,
+            None => return Ok(()),
+        };
+
+        if self.stale_entries > self.statuses.len() {
+            store.file_status = self.statuses.clone();
+            store.update_modified_time();
+            store.save(path)?;
+
+            // The store was replaced by a new file
+            *journal = open_journal(path)?;
+            self.stale_entries = 0;
+
+            debug!("Compacted the state store {}", path.display());
+            return Ok(());
+        }
+
+        write_record(
+            journal,
+            &Record::Modified {
+                at: Utc::now().to_rfc3339(),
+            },
+        )?;
+        journal.sync_all()?;
+        self.stale_entries += 1;
+
+        Ok(())
+    }
+}
+
+fn open_journal(path: &Path) -> Result<File> {
+    Ok(fs::OpenOptions::new().append(true).open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn dir(url: &str, name: &str) -> DirLinkMetaData {
+        DirLinkMetaData {
+            url: url.to_owned(),
+            name: name.to_owned(),
+            last_modified: "2024-01-31 12:00".to_owned(),
+            description: String::new(),
+        }
+    }
+
+    fn file(url: &str, name: &str, size: &str) -> Node {
+        Node::File(FileLinkMetaData {
+            url: url.to_owned(),
+            name: name.to_owned(),
+            last_modified: "2024-01-31 12:00".to_owned(),
+            size: size.to_owned(),
+            description: String::new(),
+            etag: String::new(),
+        })
+    }
+
+    /// A crawl with a file at the root, a crawled and a pending directory
+    fn tree() -> Node {
+        Node::CrawledDir(
+            dir("https://host/pub/", "pub/"),
+            vec![
+                file("https://host/pub/a.txt", "a.txt", "1K"),
+                Node::CrawledDir(
+                    dir("https://host/pub/b/", "b/"),
+                    vec![file("https://host/pub/b/c.iso", "c.iso", "4.2G")],
+                ),
+                Node::PendingDir(dir("https://host/pub/d/", "d/")),
+            ],
+        )
+    }
+
+    fn store_path(temp: &TempDir) -> PathBuf {
+        temp.path().join("state.jsonl")
+    }
+
+    /// Saves a store with the crawl of `tree` and the given statuses
+    fn saved_store(path: &Path, statuses: &[(&str, FileStatus)]) -> StateStore {
+        let mut store = StateStore::new();
+        store.crawling_state = CrawlingState::Complete(tree());
+        store.file_status = statuses
+            .iter()
+            .map(|(url, status)| (url.to_string(), status.clone()))
+            .collect();
+        store.save(path).unwrap();
+        store
+    }
+
+    /// The crawl of a store (as JSON, the nodes can't be compared directly)
+    fn crawl(store: &StateStore) -> serde_json::Value {
+        serde_json::to_value(&store.crawling_state).unwrap()
+    }
+
+    fn failed(reason: &str) -> FileStatus {
+        FileStatus::Failed {
+            reason: reason.to_owned(),
+        }
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        let saved = saved_store(
+            &path,
+            &[
+                ("https://host/pub/a.txt", FileStatus::Done),
+                ("https://host/pub/b/c.iso", failed("timeout")),
+            ],
+        );
+
+        let loaded = StateStore::load(&path).unwrap();
+        assert_eq!(crawl(&loaded), crawl(&saved));
+        assert_eq!(loaded.file_status, saved.file_status);
+        assert_eq!(loaded.created_at, saved.created_at);
+        assert_eq!(loaded.stale_entries, 0);
+        assert!(matches!(loaded.crawling_state, CrawlingState::Complete(_)));
+    }
+
+    #[test]
+    fn starts_a_new_store_if_there_is_none() {
+        let temp = tempfile::tempdir().unwrap();
+
+        let store = StateStore::load(&store_path(&temp)).unwrap();
+        assert!(matches!(store.crawling_state, CrawlingState::None));
+        assert!(store.file_status.is_empty());
+    }
+
+    #[test]
+    fn ignores_a_truncated_last_line() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        saved_store(&path, &[]);
+
+        // A crash while appending leaves half a line behind
+        let mut journal = open_journal(&path).unwrap();
+        journal
+            .write_all(
+                b"{\"type\":\"status\",\"url\":\"https://host/pub/a.txt\",\"status\":\"done\"}\n",
+            )
+            .unwrap();
+        journal
+            .write_all(b"{\"type\":\"status\",\"url\":\"https://host/pub/b/c.iso\",\"sta")
+            .unwrap();
+
+        let store = StateStore::load(&path).unwrap();
+        assert_eq!(store.file_status.len(), 1);
+        assert_eq!(
+            store.file_status["https://host/pub/a.txt"],
+            FileStatus::Done
+        );
+    }
+
+    #[test]
+    fn refuses_a_broken_line_in_the_middle() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        saved_store(&path, &[]);
+
+        let mut journal = open_journal(&path).unwrap();
+        journal.write_all(b"{\"type\":\"sta\n").unwrap();
+        journal
+            .write_all(b"{\"type\":\"modified\",\"at\":\"2024-01-31T12:00:00+00:00\"}\n")
+            .unwrap();
+
+        assert!(StateStore::load(&path).is_err());
+    }
+
+    #[test]
+    fn journals_status_changes() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        let store = saved_store(&path, &[]);
+
+        let mut statuses =
+            StatusMap::with_journal(store.file_status, store.stale_entries, &path).unwrap();
+        statuses
+            .set("https://host/pub/a.txt", FileStatus::InProgress)
+            .unwrap();
+        statuses
+            .set("https://host/pub/a.txt", FileStatus::Done)
+            .unwrap();
+
+        // The last status of a URL wins
+        let loaded = StateStore::load(&path).unwrap();
+        assert_eq!(
+            loaded.file_status["https://host/pub/a.txt"],
+            FileStatus::Done
+        );
+        assert_eq!(loaded.stale_entries, 1);
+    }
+
+    #[test]
+    fn compacts_a_mostly_stale_journal() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        let mut store = saved_store(&path, &[]);
+
+        let mut statuses =
+            StatusMap::with_journal(store.file_status.clone(), store.stale_entries, &path).unwrap();
+
+        // Two statuses, but five entries for them in the journal (three of them stale)
+        for status in &[
+            FileStatus::InProgress,
+            failed("reset"),
+            FileStatus::InProgress,
+            FileStatus::Done,
+        ] {
+            statuses
+                .set("https://host/pub/a.txt", status.clone())
+                .unwrap();
+        }
+        statuses
+            .set("https://host/pub/b/c.iso", failed("timeout"))
+            .unwrap();
+
+        let journaled = fs::read_to_string(&path).unwrap().lines().count();
+        statuses.touch(&mut store).unwrap();
+
+        // The store was rewritten with one entry per status
+        let compacted = fs::read_to_string(&path).unwrap().lines().count();
+        assert!(compacted < journaled);
+
+        let loaded = StateStore::load(&path).unwrap();
+        assert_eq!(loaded.stale_entries, 0);
+        assert_eq!(crawl(&loaded), crawl(&store));
+        assert_eq!(
+            loaded.file_status["https://host/pub/a.txt"],
+            FileStatus::Done
+        );
+        assert_eq!(
+            loaded.file_status["https://host/pub/b/c.iso"],
+            failed("timeout")
+        );
+
+        // The journal appends to the new file afterwards
+        statuses
+            .set("https://host/pub/b/c.iso", FileStatus::Done)
+            .unwrap();
+        let loaded = StateStore::load(&path).unwrap();
+        assert_eq!(
+            loaded.file_status["https://host/pub/b/c.iso"],
+            FileStatus::Done
+        );
+    }
+
+    #[test]
+    fn appends_to_a_mostly_current_journal() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        let mut store = saved_store(&path, &[]);
+
+        let mut statuses =
+            StatusMap::with_journal(HashMap::new(), store.stale_entries, &path).unwrap();
+        statuses
+            .set("https://host/pub/a.txt", FileStatus::Done)
+            .unwrap();
+        statuses
+            .set("https://host/pub/b/c.iso", FileStatus::Done)
+            .unwrap();
+
+        let before = fs::read_to_string(&path).unwrap();
+        statuses.touch(&mut store).unwrap();
+        let after = fs::read_to_string(&path).unwrap();
+
+        // Only the modification time was appended
+        assert!(after.starts_with(&before));
+        assert_eq!(after.lines().count(), before.lines().count() + 1);
+    }
+
+    /// A store as written by version 1 (a single pretty-printed JSON document)
+    const STORE_V1: &str = r#"{
+  "created_at": "2021-03-01T10:00:00+00:00",
+  "last_modified": "2021-03-02T10:00:00+00:00",
+  "crawling_state": {
+    "Complete": {
+      "CrawledDir": [
+        {
+          "url": "https://host/pub/",
+          "name": "pub/",
+          "last_modified": "2024-01-31 12:00",
+          "description": ""
+        },
+        [
+          {
+            "File": {
+              "url": "https://host/pub/a.txt",
+              "name": "a.txt",
+              "last_modified": "2024-01-31 12:00",
+              "size": "1K",
+              "description": ""
+            }
+          },
+          {
+            "CrawledDir": [
+              {
+                "url": "https://host/pub/b/",
+                "name": "b/",
+                "last_modified": "2024-01-31 12:00",
+                "description": ""
+              },
+              [
+                {
+                  "File": {
+                    "url": "https://host/pub/b/c.iso",
+                    "name": "c.iso",
+                    "last_modified": "2024-01-31 12:00",
+                    "size": "4.2G",
+                    "description": ""
+                  }
+                }
+              ]
+            ]
+          },
+          {
+            "PendingDir": {
+              "url": "https://host/pub/d/",
+              "name": "d/",
+              "last_modified": "2024-01-31 12:00",
+              "description": ""
+            }
+          }
+        ]
+      ]
+    }
+  },
+  "downloaded_urls": ["https://host/pub/a.txt"]
+}
+"#;
+
+    /// A store as written by version 2 (JSON Lines with `downloaded` entries)
+    const STORE_V2: &str = r#"{"type":"header","version":2,"created_at":"2022-05-01T10:00:00+00:00"}
+{"type":"dir","depth":0,"crawled":true,"url":"https://host/pub/","name":"pub/","last_modified":"2024-01-31 12:00","description":""}
+{"type":"file","depth":1,"url":"https://host/pub/a.txt","name":"a.txt","last_modified":"2024-01-31 12:00","size":"1K","description":""}
+{"type":"dir","depth":1,"crawled":true,"url":"https://host/pub/b/","name":"b/","last_modified":"2024-01-31 12:00","description":""}
+{"type":"file","depth":2,"url":"https://host/pub/b/c.iso","name":"c.iso","last_modified":"2024-01-31 12:00","size":"4.2G","description":""}
+{"type":"dir","depth":1,"crawled":false,"url":"https://host/pub/d/","name":"d/","last_modified":"2024-01-31 12:00","description":""}
+{"type":"crawl_complete","at":"2022-05-01T11:00:00+00:00"}
+{"type":"downloaded","url":"https://host/pub/b/c.iso"}
+{"type":"modified","at":"2022-05-02T10:00:00+00:00"}
+"#;
+
+    /// Migrates a store, checking the backup of the old file
+    fn migrated(old: &str, version: u64) -> StateStore {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        fs::write(&path, old).unwrap();
+
+        assert!(StateStore::load(&path).is_err());
+        assert_eq!(StateStore::migrate(&path).unwrap(), Some(version));
+
+        let backup = temp.path().join(format!("state.jsonl.v{}.bak", version));
+        assert_eq!(fs::read_to_string(backup).unwrap(), old);
+
+        // Migrating again leaves the store alone
+        let current = fs::read_to_string(&path).unwrap();
+        assert_eq!(
+            StateStore::migrate(&path).unwrap(),
+            Some(StateStore::VERSION)
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), current);
+
+        StateStore::load(&path).unwrap()
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let store = migrated(STORE_V1, 1);
+
+        assert_eq!(
+            crawl(&store),
+            serde_json::to_value(CrawlingState::Complete(tree())).unwrap()
+        );
+        assert_eq!(store.created_at, "2021-03-01T10:00:00+00:00");
+        assert_eq!(store.last_modified, "2021-03-02T10:00:00+00:00");
+        assert_eq!(
+            store.file_status,
+            [("https://host/pub/a.txt".to_owned(), FileStatus::Done)]
+                .iter()
+                .cloned()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn migrates_version_2() {
+        let store = migrated(STORE_V2, 2);
+
+        assert_eq!(
+            crawl(&store),
+            serde_json::to_value(CrawlingState::Complete(tree())).unwrap()
+        );
+        assert_eq!(store.created_at, "2022-05-01T10:00:00+00:00");
+        assert_eq!(store.last_modified, "2022-05-02T10:00:00+00:00");
+        assert_eq!(
+            store.file_status,
+            [("https://host/pub/b/c.iso".to_owned(), FileStatus::Done)]
+                .iter()
+                .cloned()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        let header = format!(
+            "{{\"type\":\"header\",\"version\":{},\"created_at\":\"2030-01-01T00:00:00+00:00\"}}\n",
+            StateStore::VERSION + 1
+        );
+        fs::write(&path, &header).unwrap();
+
+        assert!(StateStore::load(&path).is_err());
+        assert!(StateStore::migrate(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), header);
+    }
+
+    #[test]
+    fn refuses_files_which_are_no_state_store() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        fs::write(&path, "https://host/pub/a.txt\n").unwrap();
+
+        assert!(StateStore::load(&path).is_err());
+        assert!(StateStore::migrate(&path).is_err());
+    }
+
+    #[test]
+    fn skips_migrating_missing_stores() {
+        let temp = tempfile::tempdir().unwrap();
+
+        assert_eq!(StateStore::migrate(&store_path(&temp)).unwrap(), None);
+    }
+
+    #[test]
+    fn skipping_keeps_done_and_failed_files() {
+        let mut statuses = StatusMap::new(HashMap::new());
+        statuses.set("https://host/a", FileStatus::Done).unwrap();
+        statuses.set("https://host/b", failed("timeout")).unwrap();
+        statuses
+            .set("https://host/c", FileStatus::InProgress)
+            .unwrap();
+
+        for url in &[
+            "https://host/a",
+            "https://host/b",
+            "https://host/c",
+            "https://host/d",
+        ] {
+            statuses.skip(url, SkipReason::Filter).unwrap();
+        }
+
+        let skipped = FileStatus::Skipped {
+            reason: SkipReason::Filter,
+        };
+        assert_eq!(statuses.get("https://host/a"), &FileStatus::Done);
+        assert_eq!(statuses.get("https://host/b"), &failed("timeout"));
+        assert_eq!(statuses.get("https://host/c"), &skipped);
+        assert_eq!(statuses.get("https://host/d"), &skipped);
+    }
+
+    #[test]
+    fn retrying_makes_failed_files_pending() {
+        let mut statuses = StatusMap::new(HashMap::new());
+        statuses.set("https://host/a", FileStatus::Done).unwrap();
+        statuses.set("https://host/b", failed("timeout")).unwrap();
+        statuses.set("https://host/c", failed("404")).unwrap();
+
+        assert_eq!(statuses.retry_failed(), 2);
+        assert_eq!(statuses.get("https://host/a"), &FileStatus::Done);
+        assert_eq!(statuses.get("https://host/b"), &FileStatus::Pending);
+        assert!(!statuses.is_failed("https://host/c"));
+        assert_eq!(statuses.failed().count(), 0);
+        assert_eq!(statuses.iter().count(), 1);
+
+        // Retried files can be skipped (e.g. by the download limit) like new ones
+        statuses.skip("https://host/b", SkipReason::Filter).unwrap();
+        assert!(matches!(
+            statuses.get("https://host/b"),
+            FileStatus::Skipped { .. }
+        ));
+    }
+
+    #[test]
+    fn journals_retried_files_once_they_change() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = store_path(&temp);
+        let store = saved_store(&path, &[("https://host/pub/a.txt", failed("timeout"))]);
+
+        let mut statuses =
+            StatusMap::with_journal(store.file_status, store.stale_entries, &path).unwrap();
+        assert_eq!(statuses.retry_failed(), 1);
+
+        // Re-queuing alone doesn't touch the store
+        let loaded = StateStore::load(&path).unwrap();
+        assert_eq!(
+            loaded.file_status["https://host/pub/a.txt"],
+            failed("timeout")
+        );
+
+        statuses
+            .set("https://host/pub/a.txt", FileStatus::Done)
+            .unwrap();
+        let loaded = StateStore::load(&path).unwrap();
+        assert_eq!(
+            loaded.file_status["https://host/pub/a.txt"],
+            FileStatus::Done
+        );
+    }
+}
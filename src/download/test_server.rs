@@ -0,0 +1,194 @@
+/*!
+A local HTTP server answering with scripted replies (for the tests of the listing & storage modules).
+
+Every connection carries a single request, which is recorded for the assertions of the test.
+*/
+
+use super::{
+    scope::{RedirectPolicy, Scope},
+    session::Session,
+};
+use reqwest::Url;
+use std::sync::{Arc, Mutex};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+/// A request received by the server
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: String,
+
+    /// The path & query (e.g. `/bucket?list-type=2`)
+    pub target: String,
+
+    /// The headers (with lower-cased names)
+    pub headers: Vec<(String, String)>,
+
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// A reply of the server
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Reply {
+    pub fn new(status: u16, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+type Handler = dyn Fn(&Request) -> Reply + Send + Sync;
+
+pub struct TestServer {
+    url: Url,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Starts a server answering every request with the reply of the handler
+    pub async fn start(handler: impl Fn(&Request) -> Reply + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let requests = Arc::new(Mutex::new(vec![]));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = requests.clone();
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let recorded = recorded.clone();
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let _ = serve(stream, &*handler, &recorded).await;
+                });
+            }
+        });
+
+        Self {
+            url,
+            requests,
+            task,
+        }
+    }
+
+    /// The URL of a path on the server (e.g. `/pub/`)
+    pub fn url(&self, path: &str) -> Url {
+        self.url.join(path).unwrap()
+    }
+
+    /// The requests received so far
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// A session crawling the server (below the given path)
+    pub fn session(&self, path: &str) -> Session {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap();
+        let scope = Scope::new(&self.url(path), true, vec![], RedirectPolicy::Refuse);
+
+        Session::new(client, scope, None, None)
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Reads a single request from a connection and answers it
+async fn serve(
+    stream: TcpStream,
+    handler: &Handler,
+    recorded: &Mutex<Vec<Request>>,
+) -> std::io::Result<()> {
+    let mut stream = BufReader::new(stream);
+
+    let mut line = String::new();
+    stream.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let target = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = vec![];
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await?;
+        match line.trim_end().split_once(':') {
+            Some((name, value)) => {
+                headers.push((name.to_ascii_lowercase(), value.trim().to_owned()))
+            }
+            None => break,
+        }
+    }
+
+    let mut request = Request {
+        method,
+        target,
+        headers,
+        body: vec![],
+    };
+
+    if request.header("transfer-encoding") == Some("chunked") {
+        loop {
+            line.clear();
+            stream.read_line(&mut line).await?;
+            let size = usize::from_str_radix(line.trim(), 16).unwrap_or_default();
+            let mut chunk = vec![0; size + 2];
+            stream.read_exact(&mut chunk).await?;
+            if size == 0 {
+                break;
+            }
+            request.body.extend_from_slice(&chunk[..size]);
+        }
+    } else if let Some(length) = request.header("content-length") {
+        request.body = vec![0; length.parse().unwrap_or_default()];
+        stream.read_exact(&mut request.body).await?;
+    }
+
+    let reply = handler(&request);
+    recorded.lock().unwrap().push(request);
+
+    let mut head = format!(
+        "HTTP/1.1 {} Stub\r\ncontent-length: {}\r\nconnection: close\r\n",
+        reply.status,
+        reply.body.len()
+    );
+    for (name, value) in &reply.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+
+    let stream = stream.get_mut();
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&reply.body).await?;
+    stream.shutdown().await
+}
@@ -1,6 +1,10 @@
-use anyhow::{bail, Result};
+use super::{
+    error::{OdGetError, Result},
+    report::SkipReason,
+};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /**
 This enum defines an entry in an open-directory listing.
@@ -21,6 +25,27 @@ pub enum Node {
     CrawledDir(DirLinkMetaData, Vec<Node>),
 }
 
+impl Node {
+    /// Returns all files in this (crawled) tree
+    pub fn files(&self) -> Vec<&FileLinkMetaData> {
+        let mut files = vec![];
+        self.collect_files(&mut files);
+        files
+    }
+
+    fn collect_files<'a>(&'a self, files: &mut Vec<&'a FileLinkMetaData>) {
+        match self {
+            Node::File(file) => files.push(file),
+            Node::PendingDir(_) => {}
+            Node::CrawledDir(_, children) => {
+                for child in children {
+                    child.collect_files(files);
+                }
+            }
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileLinkMetaData {
     pub url: String,
@@ -28,6 +53,37 @@ pub struct FileLinkMetaData {
     pub last_modified: String,
     pub size: String,
     pub description: String,
+
+    /// The entity tag stated in the listing (e.g. by S3 buckets)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub etag: String,
+}
+
+impl FileLinkMetaData {
+    /**
+    Parses the size stated in the listing (e.g. `123`, `4.5K` or `1.2G`).
+
+    The result is only an estimate, as listings round the sizes.
+    */
+    pub fn size_in_bytes(&self) -> Option<u64> {
+        let size = self.size.trim();
+        let (number, unit) = match size.find(|c: char| c.is_ascii_alphabetic()) {
+            Some(index) => size.split_at(index),
+            None => (size, ""),
+        };
+
+        let factor: u64 = match unit.to_ascii_uppercase().as_str() {
+            "" | "B" => 1,
+            "K" | "KB" | "KIB" => 1 << 10,
+            "M" | "MB" | "MIB" => 1 << 20,
+            "G" | "GB" | "GIB" => 1 << 30,
+            "T" | "TB" | "TIB" => 1 << 40,
+            _ => return None,
+        };
+
+        let number: f64 = number.trim().parse().ok()?;
+        Some((number * factor as f64) as u64)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -38,6 +94,41 @@ pub struct DirLinkMetaData {
     pub description: String,
 }
 
+/**
+A file or directory found by a streaming crawl (see `crawl::stream`).
+
+The path is relative to the root directory (e.g. `a/b/file.txt`, directories end with a `/`).
+*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiscoveredEntry {
+    File {
+        path: String,
+        #[serde(flatten)]
+        file: FileLinkMetaData,
+    },
+    Dir {
+        path: String,
+        #[serde(flatten)]
+        dir: DirLinkMetaData,
+    },
+}
+
+impl DiscoveredEntry {
+    pub fn path(&self) -> &str {
+        match self {
+            Self::File { path, .. } | Self::Dir { path, .. } => path,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        match self {
+            Self::File { file, .. } => &file.url,
+            Self::Dir { dir, .. } => &dir.url,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum CrawlingState {
     Complete(Node),
@@ -45,8 +136,30 @@ pub enum CrawlingState {
     None,
 }
 
+/// The download status of a file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileStatus {
+    /// The file wasn't handled yet
+    Pending,
+
+    /// The download of the file started (but didn't finish yet)
+    InProgress,
+
+    /// The file was downloaded completely
+    Done,
+
+    /// The download of the file failed
+    Failed { reason: String },
+
+    /// The file was left out (e.g. by a filter)
+    Skipped { reason: SkipReason },
+}
+
 /**
+The persisted state of a crawl (and the progress of its download)
 
+See the `store` module for how it is persisted.
 */
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StateStore {
@@ -54,12 +167,22 @@ pub struct StateStore {
     pub last_modified: String,
     pub crawling_state: CrawlingState,
 
-    // TODO use borrowed string slices instead
-    pub downloaded_urls: Vec<String>,
+    /// The status of every file which was touched by a download (files without one are pending)
+    pub file_status: HashMap<String, FileStatus>,
+
+    /// The entries of the file superseded by later ones (compacted away by a `save`)
+    #[serde(skip)]
+    pub stale_entries: usize,
+}
+
+impl Default for StateStore {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl StateStore {
-    pub const VERSION: u64 = 1;
+    pub const VERSION: u64 = 3;
 
     pub fn new() -> Self {
         let now = Utc::now().to_rfc3339();
@@ -68,7 +191,8 @@ impl StateStore {
             created_at: now.clone(),
             last_modified: now,
             crawling_state: CrawlingState::None,
-            downloaded_urls: vec![],
+            file_status: HashMap::new(),
+            stale_entries: 0,
         }
     }
 
@@ -80,7 +204,7 @@ impl StateStore {
         if let CrawlingState::Complete(root) = &self.crawling_state {
             Ok(root)
         } else {
-            bail!("Crawl is not complete")
+            Err(OdGetError::State("Crawl is not complete".to_owned()))
         }
     }
 
@@ -88,7 +212,7 @@ impl StateStore {
         if let CrawlingState::Complete(root) = &mut self.crawling_state {
             Ok(root)
         } else {
-            bail!("Crawl is not complete")
+            Err(OdGetError::State("Crawl is not complete".to_owned()))
         }
     }
 }
@@ -0,0 +1,354 @@
+use super::{
+    charset, compression,
+    error::{OdGetError, Result},
+    session::Session,
+};
+use chrono::DateTime;
+use quick_xml::events::Event as XmlEvent;
+use reqwest::{
+    header::{HeaderValue, CONTENT_TYPE},
+    Method, StatusCode, Url,
+};
+
+/// Asks for the properties needed for the nodes (instead of all of them)
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:">
+  <prop>
+    <resourcetype/>
+    <getcontentlength/>
+    <getlastmodified/>
+  </prop>
+</propfind>
+"#;
+
+/// A member of a WebDAV collection
+#[derive(Debug, Clone, Default)]
+pub struct Entry {
+    /// The (possibly relative) URL of the member
+    pub href: String,
+
+    /// Whether the member is a collection (a directory)
+    pub dir: bool,
+
+    pub size: Option<u64>,
+
+    /// The modification time (e.g. `2024-01-31 12:00`)
+    pub modified: String,
+}
+
+/**
+Lists a WebDAV collection (with a `PROPFIND` request of depth 1).
+
+Returns the URL after redirects (to which the hrefs are relative) along the members.
+The collection itself is left out.
+*/
+pub async fn list(url: &Url, session: &Session) -> Result<(Url, Vec<Entry>)> {
+    let mut headers = compression::listing_headers();
+    headers.insert("depth", HeaderValue::from_static("1"));
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+
+    let method = Method::from_bytes(b"PROPFIND").expect("Invalid method name");
+    let res = session
+        .request(method, url.as_str(), headers, PROPFIND_BODY.as_bytes())
+        .await?;
+
+    // Servers without WebDAV reject the method or answer with a plain page instead
+    let status = res.status();
+    if status == StatusCode::METHOD_NOT_ALLOWED
+        || status == StatusCode::NOT_IMPLEMENTED
+        || (status.is_success() && status != StatusCode::MULTI_STATUS)
+    {
+        return Err(OdGetError::Parse(format!(
+            "{} is no WebDAV collection (status {})",
+            url, status
+        )));
+    }
+
+    let res = res.error_for_status()?;
+    let base_url = res.url().clone();
+    let content_type = charset::content_type(res.headers());
+
+    let body = compression::read_listing(res, session).await?;
+    let (text, _) = charset::decode(&body, content_type.as_deref());
+
+    let entries = parse_multistatus(&text)?
+        .into_iter()
+        .filter(|entry| !is_same_dir(&base_url, &entry.href))
+        .collect();
+
+    Ok((base_url, entries))
+}
+
+/**
+Parses a multistatus response into its members.
+
+Elements are matched by their local names (servers use different namespace prefixes),
+and properties are only taken from successful `propstat` elements.
+*/
+fn parse_multistatus(xml: &str) -> Result<Vec<Entry>> {
+    let mut reader = quick_xml::Reader::from_str(xml);
+    reader.trim_text(true);
+
+    let mut entries = vec![];
+
+    // The member being parsed and the properties of the current propstat
+    let mut entry: Option<Entry> = None;
+    let mut props = Entry::default();
+    let mut path: Vec<Vec<u8>> = vec![];
+
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|err| OdGetError::Parse(format!("Invalid WebDAV response: {}", err)))?;
+
+        match event {
+            XmlEvent::Start(element) => {
+                let name = element.local_name().as_ref().to_ascii_lowercase();
+                match name.as_slice() {
+                    b"response" => entry = Some(Entry::default()),
+                    b"propstat" => props = Entry::default(),
+                    b"collection" if path.last().map(Vec::as_slice) == Some(b"resourcetype") => {
+                        props.dir = true
+                    }
+                    _ => {}
+                }
+                path.push(name);
+            }
+            XmlEvent::Empty(element) => {
+                let name = element.local_name().as_ref().to_ascii_lowercase();
+                if name == b"collection" && path.last().map(Vec::as_slice) == Some(b"resourcetype")
+                {
+                    props.dir = true;
+                }
+            }
+            XmlEvent::Text(text) => {
+                let text = text.unescape().map_err(|err| {
+                    OdGetError::Parse(format!("Invalid WebDAV response: {}", err))
+                })?;
+                let text = text.trim();
+
+                match path.last().map(Vec::as_slice) {
+                    Some(b"href") if path.len() >= 2 && path[path.len() - 2] == b"response" => {
+                        if let Some(entry) = &mut entry {
+                            entry.href = text.to_owned();
+                        }
+                    }
+                    Some(b"getcontentlength") => props.size = text.parse().ok(),
+                    Some(b"getlastmodified") => props.modified = format_date(text),
+                    // Properties which are not found come with a 404 status
+                    Some(b"status")
+                        if path.len() >= 2
+                            && path[path.len() - 2] == b"propstat"
+                            && !text.contains(" 200") =>
+                    {
+                        props = Entry::default()
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::End(element) => {
+                path.pop();
+
+                match element
+                    .local_name()
+                    .as_ref()
+                    .to_ascii_lowercase()
+                    .as_slice()
+                {
+                    b"propstat" => {
+                        if let Some(entry) = &mut entry {
+                            entry.dir |= props.dir;
+                            entry.size = entry.size.or(props.size);
+                            if entry.modified.is_empty() {
+                                entry.modified = std::mem::take(&mut props.modified);
+                            }
+                        }
+                        props = Entry::default();
+                    }
+                    b"response" => {
+                        if let Some(entry) = entry.take().filter(|entry| !entry.href.is_empty()) {
+                            entries.push(entry);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Checks if an href points to the listed collection itself
+fn is_same_dir(base_url: &Url, href: &str) -> bool {
+    match base_url.join(href) {
+        Ok(url) => url.path().trim_end_matches('/') == base_url.path().trim_end_matches('/'),
+        Err(_) => false,
+    }
+}
+
+/// Formats an HTTP date (e.g. `Wed, 31 Jan 2024 12:00:00 GMT`) like the dates of HTML listings
+fn format_date(date: &str) -> String {
+    match DateTime::parse_from_rfc2822(date) {
+        Ok(date) => date.format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => date.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::test_server::{Reply, TestServer};
+
+    /// A multistatus response with the given namespace prefix (e.g. `D:`)
+    fn multistatus(prefix: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<{p}multistatus xmlns:{n}="DAV:">
+  <{p}response>
+    <{p}href>/pub/</{p}href>
+    <{p}propstat>
+      <{p}prop><{p}resourcetype><{p}collection/></{p}resourcetype></{p}prop>
+      <{p}status>HTTP/1.1 200 OK</{p}status>
+    </{p}propstat>
+  </{p}response>
+  <{p}response>
+    <{p}href>/pub/docs/</{p}href>
+    <{p}propstat>
+      <{p}prop>
+        <{p}resourcetype><{p}collection/></{p}resourcetype>
+        <{p}getlastmodified>Wed, 31 Jan 2024 12:00:00 GMT</{p}getlastmodified>
+      </{p}prop>
+      <{p}status>HTTP/1.1 200 OK</{p}status>
+    </{p}propstat>
+  </{p}response>
+  <{p}response>
+    <{p}href>/pub/a%20b.txt</{p}href>
+    <{p}propstat>
+      <{p}prop>
+        <{p}resourcetype/>
+        <{p}getcontentlength>1024</{p}getcontentlength>
+        <{p}getlastmodified>Thu, 01 Feb 2024 08:30:00 GMT</{p}getlastmodified>
+      </{p}prop>
+      <{p}status>HTTP/1.1 200 OK</{p}status>
+    </{p}propstat>
+  </{p}response>
+</{p}multistatus>"#,
+            p = prefix,
+            n = prefix.trim_end_matches(':'),
+        )
+    }
+
+    /// The href, whether it's a directory, the size & the modification time of an entry
+    fn fields(entry: &Entry) -> (&str, bool, Option<u64>, &str) {
+        (&entry.href, entry.dir, entry.size, &entry.modified)
+    }
+
+    #[test]
+    fn parses_prefixed_multistatus() {
+        for prefix in &["D:", "d:", "lp1:"] {
+            let entries = parse_multistatus(&multistatus(prefix)).unwrap();
+            let entries: Vec<_> = entries.iter().map(fields).collect();
+
+            assert_eq!(
+                entries,
+                [
+                    ("/pub/", true, None, ""),
+                    ("/pub/docs/", true, None, "2024-01-31 12:00"),
+                    ("/pub/a%20b.txt", false, Some(1024), "2024-02-01 08:30"),
+                ]
+            );
+        }
+    }
+
+    #[test]
+    fn ignores_properties_which_were_not_found() {
+        let xml = r#"<d:multistatus xmlns:d="DAV:">
+  <d:response>
+    <d:href>/pub/file.iso</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:getcontentlength>2048</d:getcontentlength>
+        <d:getlastmodified>Wed, 31 Jan 2024 12:00:00 GMT</d:getlastmodified>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop>
+        <d:resourcetype><d:collection/></d:resourcetype>
+        <d:getcontentlength>0</d:getcontentlength>
+        <d:getlastmodified>Mon, 01 Jan 2001 00:00:00 GMT</d:getlastmodified>
+      </d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+        let entries = parse_multistatus(xml).unwrap();
+        assert_eq!(
+            entries.iter().map(fields).collect::<Vec<_>>(),
+            [("/pub/file.iso", false, Some(2048), "2024-01-31 12:00")]
+        );
+    }
+
+    #[test]
+    fn formats_dates_like_html_listings() {
+        assert_eq!(
+            format_date("Wed, 31 Jan 2024 12:00:00 GMT"),
+            "2024-01-31 12:00"
+        );
+        assert_eq!(format_date("yesterday"), "yesterday");
+    }
+
+    #[test]
+    fn recognizes_the_collection_itself() {
+        let base_url = Url::parse("https://host/pub/").unwrap();
+
+        assert!(is_same_dir(&base_url, "/pub/"));
+        assert!(is_same_dir(&base_url, "/pub"));
+        assert!(is_same_dir(&base_url, "https://host/pub/"));
+        assert!(!is_same_dir(&base_url, "/pub/docs/"));
+        assert!(!is_same_dir(&base_url, "/"));
+    }
+
+    #[tokio::test]
+    async fn lists_members_without_the_collection() {
+        let server = TestServer::start(|_| {
+            Reply::new(207, multistatus("D:")).header("content-type", "application/xml")
+        })
+        .await;
+        let session = server.session("/pub/");
+
+        let (base_url, entries) = list(&server.url("/pub/"), &session).await.unwrap();
+        assert_eq!(base_url, server.url("/pub/"));
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.href.as_str())
+                .collect::<Vec<_>>(),
+            ["/pub/docs/", "/pub/a%20b.txt"]
+        );
+
+        let requests = server.requests();
+        assert_eq!(requests[0].method, "PROPFIND");
+        assert_eq!(requests[0].target, "/pub/");
+        assert_eq!(requests[0].header("depth"), Some("1"));
+        assert_eq!(requests[0].body, PROPFIND_BODY.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn rejects_servers_without_webdav() {
+        for status in &[200, 405, 501] {
+            let status = *status;
+            let server = TestServer::start(move |_| Reply::new(status, "<html></html>")).await;
+            let session = server.session("/pub/");
+
+            assert!(list(&server.url("/pub/"), &session).await.is_err());
+        }
+    }
+}
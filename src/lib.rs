@@ -0,0 +1,14 @@
+/*!
+Crawls & downloads [open directories](https://www.vice.com/en/article/d35x57/what-are-open-directories).
+
+Build an `OdGet` for a root URL with `OdGet::builder()`, then `crawl` the directory tree
+and `download` the files of the crawl. The `download` module holds the building blocks
+(sessions, scopes, state stores, exports, ...) for more control.
+*/
+
+pub mod download;
+
+mod od_get;
+
+pub use download::error::{OdGetError, Result};
+pub use od_get::{OdGet, OdGetBuilder};
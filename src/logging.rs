@@ -0,0 +1,327 @@
+use anyhow::{bail, Result};
+use indicatif::MultiProgress;
+use log::{
+    debug, error, info,
+    kv::{self, Key, Value, VisitSource},
+    warn, Level, LevelFilter, Log, Metadata, Record,
+};
+use od_get::download::events::{DirSkipReason, Event};
+use serde_json::{json, Map};
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+/// The progress bars (if any) which log lines must not tear apart
+static BARS: Mutex<Option<MultiProgress>> = Mutex::new(None);
+
+/// How log records get written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Plain messages on the terminal (with timestamp & level in the log file)
+    Text,
+
+    /// One JSON object per line (with the structured fields of the event)
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            other => bail!(
+                "Invalid log format \"{}\" (must be `text` or `json`)",
+                other
+            ),
+        }
+    }
+}
+
+/// Writes the log records to the terminal (and optionally to a file)
+struct Logger {
+    /// The level of the terminal output
+    level: LevelFilter,
+
+    /// The level of the log file (never below `info`)
+    file_level: LevelFilter,
+
+    format: LogFormat,
+    file: Option<Mutex<File>>,
+
+    /// Print all terminal output to stderr (as stdout carries the downloaded data)
+    stderr_only: bool,
+}
+
+/**
+Installs the logger.
+
+The level starts at `info`; every `-v` raises it and every `-q` lowers it by one.
+The log file always receives at least the `info` records, so `-q` only quiets the terminal.
+Debug & trace records are only shown for this application (not for its dependencies).
+With `stderr_only`, the terminal output stays off stdout (e.g. when the files are written to it).
+*/
+pub fn init(
+    verbosity: u64,
+    quiet: u64,
+    format: LogFormat,
+    log_file: Option<&Path>,
+    stderr_only: bool,
+) -> Result<()> {
+    // Open the log file (appending to earlier runs)
+    let file = match log_file {
+        Some(path) => {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            Some(Mutex::new(
+                fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)?,
+            ))
+        }
+        None => None,
+    };
+
+    let level = level(verbosity, quiet);
+    let file_level = match file {
+        Some(_) => level.max(LevelFilter::Info),
+        None => LevelFilter::Off,
+    };
+
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        file_level,
+        format,
+        file,
+        stderr_only,
+    }))?;
+    log::set_max_level(level.max(file_level));
+
+    Ok(())
+}
+
+/// Keeps log lines above the given progress bars (until they are detached)
+pub fn attach_bars(bars: &MultiProgress) {
+    *BARS.lock().expect("Logger state poisoned") = Some(bars.clone());
+}
+
+/// Stops routing log lines around the progress bars
+pub fn detach_bars() {
+    *BARS.lock().expect("Logger state poisoned") = None;
+}
+
+/// Logs the events of a run (with their structured fields)
+pub fn log_event(event: &Event) {
+    match event {
+        Event::CrawlStarted { url } => {
+            info!(event = "crawl_start", url = url.as_str(); "Fetching root listing")
+        }
+        Event::CrawlingDir { url, name } => {
+            info!(event = "crawl_directory", url = url.as_str(); "Now crawling: {}", name)
+        }
+        Event::DirDiscovered { dir } => {
+            debug!(event = "found_directory", url = dir.url.as_str(); "Got directory: {}", dir.name)
+        }
+        Event::FileDiscovered { file } => {
+            debug!(event = "found_file", url = file.url.as_str(); "Got file: {}", file.name)
+        }
+        Event::DirSkipped { url, name, reason } => {
+            let url = url.as_str();
+            let reason_name = reason.as_str();
+
+            match reason {
+                DirSkipReason::UrlLength => info!(
+                    event = "skip", reason = reason_name, url = url;
+                    "({}) Skip directory with too long URL: {}", reason, name
+                ),
+                DirSkipReason::DirectoryLimit => info!(
+                    event = "skip", reason = reason_name, url = url;
+                    "({}) Directory limit reached at {}", reason, name
+                ),
+                DirSkipReason::Duplicate => info!(
+                    event = "skip", reason = reason_name, url = url;
+                    "({}) Skip already crawled directory {}", reason, url
+                ),
+                DirSkipReason::Loop => info!(
+                    event = "skip", reason = reason_name, url = url;
+                    "({}) Skip directory {} (same listing as a parent directory)", reason, name
+                ),
+                DirSkipReason::PathFilter | DirSkipReason::PathMatcher => info!(
+                    event = "skip", reason = reason_name, url = url;
+                    "({}) Skip directory {}", reason, name
+                ),
+                DirSkipReason::NotCrawled => warn!(
+                    event = "skip", reason = reason_name, url = url;
+                    "({}) Directory not initialized: {}", reason, name
+                ),
+            }
+        }
+        Event::CrawlFinished {
+            directories,
+            duration_ms,
+        } => info!(
+            event = "crawl_end", directories = directories, duration_ms = duration_ms;
+            "Crawled {} directories", directories
+        ),
+        Event::DownloadStarted { url, name, .. } => {
+            info!(event = "file_start", url = url.as_str(); "Downloading file {}", name)
+        }
+        Event::DownloadFinished { url, name, bytes } => info!(
+            event = "file_done", url = url.as_str(), bytes = bytes;
+            "Downloaded file {} ({} bytes)", name, bytes
+        ),
+        Event::DownloadFailed { url, name, error } => error!(
+            event = "file_failed", url = url.as_str(), error = error.as_str();
+            "Failed to download file {}: {}", name, error
+        ),
+        Event::Skipped {
+            url, name, reason, ..
+        } => info!(
+            event = "skip", reason = reason.as_str(), url = url.as_str();
+            "({}) Skip file {}", reason, name
+        ),
+        Event::DownloadPlanned { .. } | Event::DownloadProgress { .. } | Event::Finished => {}
+    }
+}
+
+/// Calculates the log level from the number of `-v` and `-q` flags
+fn level(verbosity: u64, quiet: u64) -> LevelFilter {
+    const LEVELS: [LevelFilter; 6] = [
+        LevelFilter::Off,
+        LevelFilter::Error,
+        LevelFilter::Warn,
+        LevelFilter::Info,
+        LevelFilter::Debug,
+        LevelFilter::Trace,
+    ];
+
+    let index = (3 + verbosity as i64 - quiet as i64).clamp(0, LEVELS.len() as i64 - 1);
+    LEVELS[index as usize]
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level.max(self.file_level)
+            && (metadata.level() <= Level::Warn
+                || metadata.target().starts_with(env!("CARGO_CRATE_NAME")))
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let (console, file) = match self.format {
+            LogFormat::Text => (console_line(record), text_line(record)),
+            LogFormat::Json => {
+                let line = json_line(record);
+                (line.clone(), line)
+            }
+        };
+
+        // Print warnings & errors to stderr (and keep the lines above the progress bars)
+        // A closed terminal (e.g. a broken pipe) shouldn't stop the run
+        let print = || {
+            let _ = if record.level() <= Level::Warn || self.stderr_only {
+                writeln!(io::stderr(), "{}", console)
+            } else {
+                writeln!(io::stdout(), "{}", console)
+            };
+        };
+
+        if record.level() <= self.level {
+            match &*BARS.lock().expect("Logger state poisoned") {
+                Some(bars) => bars.suspend(print),
+                None => print(),
+            }
+        }
+
+        if let (Some(log_file), true) = (&self.file, record.level() <= self.file_level) {
+            let mut log_file = log_file.lock().expect("Logger state poisoned");
+
+            // A broken log file shouldn't stop the run
+            let _ = writeln!(log_file, "{}", file);
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(log_file) = &self.file {
+            let _ = log_file.lock().expect("Logger state poisoned").flush();
+        }
+    }
+}
+
+/// Formats a record for the terminal (just the message)
+fn console_line(record: &Record) -> String {
+    match record.level() {
+        Level::Error => format!("error: {}", record.args()),
+        Level::Warn => format!("warning: {}", record.args()),
+        _ => record.args().to_string(),
+    }
+}
+
+/// Formats a record for the log file (with timestamp, level & fields)
+fn text_line(record: &Record) -> String {
+    let mut line = format!(
+        "{} {:<5} {}",
+        chrono::Utc::now().to_rfc3339(),
+        record.level(),
+        record.args()
+    );
+
+    let mut fields = Fields::default();
+    let _ = record.key_values().visit(&mut fields);
+
+    for (key, value) in fields.0 {
+        line.push_str(&format!(" {}={}", key, value));
+    }
+
+    line
+}
+
+/// Formats a record as a JSON object (with its fields at the top level)
+fn json_line(record: &Record) -> String {
+    let mut object = Map::new();
+    object.insert("timestamp".into(), json!(chrono::Utc::now().to_rfc3339()));
+    object.insert("level".into(), json!(record.level().as_str()));
+    object.insert("target".into(), json!(record.target()));
+    object.insert("message".into(), json!(record.args().to_string()));
+
+    let mut fields = Fields::default();
+    let _ = record.key_values().visit(&mut fields);
+
+    for (key, value) in fields.0 {
+        object.insert(key, value);
+    }
+
+    serde_json::Value::Object(object).to_string()
+}
+
+/// Collects the structured fields of a record
+#[derive(Default)]
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(number) = value.to_u64() {
+            json!(number)
+        } else if let Some(number) = value.to_i64() {
+            json!(number)
+        } else if let Some(boolean) = value.to_bool() {
+            json!(boolean)
+        } else {
+            json!(value.to_string())
+        };
+
+        self.0.push((key.as_str().to_owned(), value));
+        Ok(())
+    }
+}
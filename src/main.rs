@@ -1,193 +1,409 @@
 // For internal use within the CLI application only
 pub(crate) mod cli;
+pub(crate) mod config;
 pub(crate) mod constants;
-
-// Export as a library
-pub mod download;
-
-use anyhow::{bail, Result};
-use download::{
-    crawl,
-    fetch::{self, DownloadRecursiveStatus},
-    types::{CrawlingState, Node, StateStore},
+pub(crate) mod logging;
+pub(crate) mod progress;
+
+use anyhow::{bail, Context, Result};
+use clap::ArgMatches;
+use cli::CliOptions;
+use config::Settings;
+use log::{error, info, warn};
+use logging::LogFormat;
+use od_get::download::{
+    export::{self, ExportFormat},
+    report::Reporter,
+    session::RateLimiter,
+    stats::CrawlStats,
+    store::StatusMap,
+    types::{CrawlingState, StateStore},
+};
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::Path,
+    sync::Arc,
 };
-use std::fs;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // The working directory
-    let pwd = std::env::current_dir()
-        .unwrap()
+    let pwd = std::env::current_dir().context("Cannot read the working directory")?;
+    let pwd = pwd
         .to_str()
-        .unwrap()
+        .with_context(|| format!("The working directory {} is no UTF-8 path", pwd.display()))?
         .to_owned();
 
     // Parse the command line parameters into arg-matches
     let matches = cli::configure_parser(&pwd).get_matches();
 
-    // Print the name and version of the application along its license notice
-    println!("{} {}", constants::NAME, constants::VERSION);
-    println!("{}\n", constants::LICENSE);
+    // Merge the command line parameters with the config file (and profile)
+    let settings = cli::get_settings(&matches)?;
 
-    // Try to extract the desired configuration from the arg-matches
-    let cli_options = cli::get_options(matches)?;
+    // Print the effective configuration if requested
+    if let ("config", Some(config_matches)) = matches.subcommand() {
+        if let ("show", Some(_)) = config_matches.subcommand() {
+            print!("{}", toml::to_string(&settings.redacted())?);
+        }
 
-    // Make a new client for issuing HTTP(S) requests
-    let client = reqwest::Client::new();
+        return Ok(());
+    }
 
-    // Crawl the root directory
-    // TODO extract to `setup` or `crawl` function
-    let (mut state_store, state_path, mut done_list) = if let Some(state_path) =
-        cli_options.state_store_path.clone()
-    {
-        // A state store is desired
-
-        // Try to load the state store from the file system
-        let mut state_store: StateStore =
-            serde_json::from_str(&fs::read_to_string(&state_path).unwrap_or("make-new".to_owned()))
-                .unwrap_or(StateStore::new());
-
-        // Clone the done_list
-        let done_list: Vec<String> = state_store.downloaded_urls.clone();
-
-        // Return the pre-made crawl list or start crawling
-        match state_store.crawling_state {
-            CrawlingState::Complete(_) => (state_store, Some(state_path), done_list),
-            CrawlingState::Partial(_) | CrawlingState::None => {
-                // Perform the crawl
-                // TODO utilize partial crawls in the future
-
-                let mut root = crawl::get_root_dir(&cli_options.url, &client).await?;
-
-                // Expand the tree
-                if let Node::CrawledDir(_, ref mut children) = root {
-                    crawl::expand_node(children, &client).await?;
-                } else {
-                    panic!("Cannot expand root node")
-                }
+    // Export the crawl of a state store if requested
+    if let ("export", Some(export_matches)) = matches.subcommand() {
+        return export(export_matches);
+    }
 
-                // Update the modified time
-                state_store.update_modified_time();
+    // Migrate state stores to the current format if requested
+    if let ("migrate", Some(migrate_matches)) = matches.subcommand() {
+        return migrate(migrate_matches);
+    }
 
-                // Save the completed crawl
-                state_store.crawling_state = CrawlingState::Complete(root.clone());
+    // Summarize the crawl of a state store if requested
+    if let ("stats", Some(stats_matches)) = matches.subcommand() {
+        return stats(stats_matches, &settings);
+    }
 
-                // Serialize & persist the new state store
-                fs::write(&state_path, serde_json::to_string_pretty(&state_store)?)
-                    .expect("Cannot write to state store");
+    // Set up the log output (terminal & log file)
+    logging::init(
+        settings.verbosity.unwrap_or_default(),
+        settings.quiet.unwrap_or_default(),
+        settings
+            .log_format
+            .as_deref()
+            .unwrap_or("text")
+            .parse::<LogFormat>()?,
+        settings.log_file.as_deref().map(Path::new),
+        // Keep stdout clean when the files are written to it
+        settings.destination.as_deref() == Some("-"),
+    )?;
 
-                // Return the crawl results
-                (state_store, Some(state_path), done_list)
-            }
+    // Print the name and version of the application along its license notice
+    info!("{} {}", constants::NAME, constants::VERSION);
+    info!("{}\n", constants::LICENSE);
+
+    // Try to extract the desired configuration (one set of options per root URL)
+    let roots = cli::get_options(&settings)?;
+
+    // Make a new client for issuing HTTP(S) requests (shared by all roots)
+    // Redirects are followed by the session (to check them against the scope)
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    // Limit the rate of requests (across all roots)
+    let rate_limiter = match settings.rate_limit {
+        Some(0) | None => None,
+        Some(requests) => Some(Arc::new(RateLimiter::per_second(requests))),
+    };
+
+    // Collect the statistics of the run (across all roots)
+    let reporter = Arc::new(Reporter::new());
+
+    // Process a single root URL without the batch summary
+    if let [cli_options] = roots.as_slice() {
+        let result = process_root(cli_options, &client, rate_limiter, &reporter).await;
+        reporter.root_done(
+            &cli_options.url,
+            result.as_ref().err().map(|err| err as &dyn Display),
+        );
+        write_report(&reporter, settings.report.as_deref())?;
+        result?;
+        return Ok(());
+    }
+
+    // Process all roots, even if some of them fail
+    let mut results = vec![];
+    for (index, cli_options) in roots.iter().enumerate() {
+        info!(
+            "Processing root {}/{}: {}",
+            index + 1,
+            roots.len(),
+            cli_options.url
+        );
+
+        let result = process_root(cli_options, &client, rate_limiter.clone(), &reporter).await;
+        reporter.root_done(
+            &cli_options.url,
+            result.as_ref().err().map(|err| err as &dyn Display),
+        );
+
+        if let Err(error) = &result {
+            error!(
+                event = "root_failed", url:% = cli_options.url, error:% = error;
+                "Failed to process {}: {}", cli_options.url, error
+            );
         }
-    } else {
-        // No state store is desired
 
-        // Check if --no-download was specified
-        if cli_options.no_download {
-            bail!("Cannot use --no-download without --state-store")
+        results.push((&cli_options.url, result));
+    }
+
+    // Print the combined summary
+    info!("\nSummary:");
+    let mut failed = 0;
+    for (url, result) in &results {
+        match result {
+            Ok(downloaded) => info!("  [ok]     {} ({} file(s) downloaded)", url, downloaded),
+            Err(error) => {
+                failed += 1;
+                info!("  [failed] {} ({})", url, error);
+            }
         }
+    }
 
-        // Make a phantom state store (not persisted)
-        let mut state_store = StateStore::new();
+    let downloaded: usize = results
+        .iter()
+        .filter_map(|(_, result)| result.as_ref().ok())
+        .sum();
+    info!(
+        "{} of {} root(s) succeeded, {} file(s) downloaded in total",
+        results.len() - failed,
+        results.len(),
+        downloaded
+    );
+
+    write_report(&reporter, settings.report.as_deref())?;
+
+    if failed > 0 {
+        bail!("{} of {} root(s) failed", failed, results.len());
+    }
 
-        let mut root = crawl::get_root_dir(&cli_options.url, &client).await?;
+    Ok(())
+}
 
-        // Expand the tree
-        if let Node::CrawledDir(_, ref mut children) = root {
-            crawl::expand_node(children, &client).await?;
-        } else {
-            panic!("Cannot expand root node")
+/// Exports the crawl of a state store (to a file or stdout)
+fn export(matches: &ArgMatches) -> Result<()> {
+    let state_path = matches
+        .value_of("STATE_STORE")
+        .context("Missing state store")?;
+    let state_store = StateStore::load(Path::new(state_path))?;
+
+    let format = matches
+        .value_of("format")
+        .unwrap_or("urls")
+        .parse::<ExportFormat>()?;
+    let destination = matches.value_of("destination").unwrap_or(".");
+    let root = state_store.get_root_ref()?;
+
+    match matches.value_of("output") {
+        Some(path) => {
+            let mut file = io::BufWriter::new(fs::File::create(path)?);
+            export::export(root, format, destination, &mut file)?;
+            file.flush()?;
+        }
+        None => {
+            let stdout = io::stdout();
+            export::export(root, format, destination, &mut stdout.lock())?;
         }
+    }
 
-        // Save the completed crawl
-        state_store.crawling_state = CrawlingState::Complete(root.clone());
+    Ok(())
+}
 
-        (state_store, None, vec![])
-    };
+/// Migrates state stores to the current format version
+fn migrate(matches: &ArgMatches) -> Result<()> {
+    for state_path in matches.values_of("STATE_STORE").into_iter().flatten() {
+        match StateStore::migrate(Path::new(state_path))? {
+            None => bail!("The state store {} doesn't exist", state_path),
+            Some(StateStore::VERSION) => println!(
+                "{} is up to date (format version {})",
+                state_path,
+                StateStore::VERSION
+            ),
+            Some(version) => println!(
+                "Migrated {} from format version {} to {}",
+                state_path,
+                version,
+                StateStore::VERSION
+            ),
+        }
+    }
 
-    // Only download files if --no-download was not specified
-    // TODO extract to `download_files` function
+    Ok(())
+}
+
+/// Prints the statistics of the crawl of a state store
+fn stats(matches: &ArgMatches, settings: &Settings) -> Result<()> {
+    let state_path = matches
+        .value_of("STATE_STORE")
+        .context("Missing state store")?;
+    let state_store = StateStore::load(Path::new(state_path))?;
+
+    let selection = cli::get_selection(matches, settings)?;
+    let top = cli::parse_number(matches.value_of("top").unwrap_or("10"))? as usize;
+    let stats = CrawlStats::collect(state_store.get_root_ref()?, &selection, top);
+
+    if matches.is_present("json") {
+        writeln!(io::stdout(), "{}", serde_json::to_string_pretty(&stats)?)?;
+    } else {
+        write!(io::stdout(), "{}", stats)?;
+    }
+
+    Ok(())
+}
+
+/// Writes the report of the run to disk (if one was requested)
+fn write_report(reporter: &Reporter, report_path: Option<&str>) -> Result<()> {
+    if let Some(report_path) = report_path {
+        fs::write(
+            report_path,
+            serde_json::to_string_pretty(&reporter.finish())?,
+        )?;
+        info!("Wrote report to {}", report_path);
+    }
+
+    Ok(())
+}
+
+/**
+Crawls (and downloads) a single root URL.
+
+Returns the number of files downloaded during this run.
+Files which fail to download don't stop the run, but make it fail in the end.
+*/
+async fn process_root(
+    cli_options: &CliOptions,
+    client: &reqwest::Client,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    reporter: &Arc<Reporter>,
+) -> Result<usize> {
+    // Remember how many files failed before this root
+    let initially_failed = reporter.files_failed();
+
+    // Share the client, rate limiter & reporter with the other roots
+    let mut builder = cli_options
+        .builder()
+        .client(client.clone())
+        .reporter(reporter.clone())
+        .on_event(logging::log_event);
+
+    if let Some(rate_limiter) = rate_limiter {
+        builder = builder.rate_limiter(rate_limiter);
+    }
+
+    // Display the progress of the downloads
+    if cli_options.progress && !cli_options.no_download {
+        builder = builder.on_event(progress::make_progress());
+    }
+
+    // Write to an archive, stdout or an object store instead of a directory
     if !cli_options.no_download {
-        // TODO implement the counters
-        let mut counters = download::fetch::LimitCounts::new();
-        let mut counters_1 = counters.clone();
-
-        let res = {
-            fetch::download_recursive(
-                state_store.get_root_ref()?,
-                &cli_options,
-                &client,
-                &mut counters_1,
-                &mut done_list,
-            )
-            .await?
-        };
+        if let Some(storage) = cli_options.storage(client)? {
+            builder = builder.storage(storage);
+        }
+    }
+
+    let od_get = builder.build()?;
+
+    // Crawl the root directory
+    // TODO extract to `setup` or `crawl` function
+    let (mut state_store, state_path) =
+        if let Some(state_path) = cli_options.state_store_path.clone() {
+            // A state store is desired
+
+            // Make sure the directory of the state store exists
+            if let Some(parent) = Path::new(&state_path).parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            // Load the state store from the file system (or start a new one if there is none)
+            let mut state_store = StateStore::load(Path::new(&state_path))?;
+
+            // Return the pre-made crawl list or start crawling
+            match state_store.crawling_state {
+                CrawlingState::Complete(_) => (state_store, Some(state_path)),
+                CrawlingState::Partial(_) | CrawlingState::None => {
+                    // Perform the crawl
+                    // TODO utilize partial crawls in the future
+                    let root = od_get.crawl().await?;
+
+                    // Update the modified time
+                    state_store.update_modified_time();
+
+                    // Save the completed crawl
+                    state_store.crawling_state = CrawlingState::Complete(root);
+
+                    // Persist the new state store
+                    state_store.save(Path::new(&state_path))?;
 
-        if let DownloadRecursiveStatus::Do(ref to_do) = res {
-            for task in to_do {
-                let (node, options, client) = task;
-                // TODO implement more than one level of recursion
-                // res = fetch::download_recursive(node, options, client, &mut counters).await?;
-                match fetch::download_recursive(
-                    node,
-                    options,
-                    client,
-                    &mut counters,
-                    &mut done_list,
-                )
-                .await
-                {
-                    Ok(_) => {}
-                    Err(error) => {
-                        if let Some(state_path) = state_path {
-                            // Update the modified time
-                            state_store.update_modified_time();
-
-                            // Update the done_list
-                            state_store.downloaded_urls = done_list;
-
-                            // Serialize & persist the new state store
-                            fs::write(state_path, serde_json::to_string_pretty(&state_store)?)
-                                .expect("Cannot write to state store");
-                        }
-
-                        // Return the error and halt execution
-                        bail!(error)
-                    }
+                    // Return the crawl results
+                    (state_store, Some(state_path))
                 }
             }
+        } else {
+            // No state store is desired
+
+            // Check if --no-download was specified
+            if cli_options.no_download {
+                bail!("Cannot use --no-download without --state-store")
+            }
+
+            // Make a phantom state store (not persisted)
+            let mut state_store = StateStore::new();
+
+            let root = od_get.crawl().await?;
+
+            // Save the completed crawl
+            state_store.crawling_state = CrawlingState::Complete(root);
+
+            (state_store, None)
+        };
+
+    // Move the file statuses into the status map (which appends changes to the state store)
+    let file_status = std::mem::take(&mut state_store.file_status);
+    let mut statuses = match &state_path {
+        Some(state_path) => StatusMap::with_journal(
+            file_status,
+            state_store.stale_entries,
+            Path::new(state_path),
+        )?,
+        None => StatusMap::new(file_status),
+    };
+
+    // Re-queue the files which failed in earlier runs (if desired)
+    if cli_options.retry_failed {
+        let retried = statuses.retry_failed();
+        if retried > 0 {
+            info!("Retrying {} file(s) which failed before", retried);
         }
     }
 
-    // Persist the new state to disk if necessary
+    // Only download files if --no-download was not specified
+    let root = state_store.get_root_ref()?;
+    let downloaded = if cli_options.no_download {
+        info!("Skipped download");
+        reporter.files_found(root.files().len() as u64);
+        0
+    } else {
+        // The statuses are persisted as they change, so errors can end the run
+        od_get.download(root, &mut statuses).await?
+    };
+
+    // Record the end of the run in the state store (if any)
     if let Some(state_path) = state_path {
-        write_state(&mut state_store, &state_path, done_list)?;
-        println!("Download done.");
+        statuses.touch(&mut state_store)?;
+        info!("Updated state store {}", state_path);
+        info!("Download done.");
     } else {
-        println!("All done.");
+        info!("All done.");
     }
 
-    Ok(())
-}
+    let failed = reporter.files_failed() - initially_failed;
 
-/// Persists the state to disk
-fn write_state(
-    state_store: &mut StateStore,
-    state_path: &str,
-    done_list: Vec<String>,
-) -> Result<()> {
-    // Update the modified time
-    state_store.update_modified_time();
-
-    // Update the done_list
-    state_store.downloaded_urls = done_list;
-
-    // Serialize & persist the new state store
-    fs::write(state_path, serde_json::to_string_pretty(&state_store)?)
-        .expect("Cannot write to state store");
+    // Point out the failures of earlier runs, which were left alone
+    let failed_before = (statuses.failed().count() as u64).saturating_sub(failed);
+    if failed_before > 0 && !cli_options.no_download {
+        warn!(
+            "{} file(s) failed in earlier runs, use --retry-failed to download them again",
+            failed_before
+        );
+    }
 
-    println!("Wrote state store to {}", state_path);
+    if failed > 0 {
+        bail!("{} file(s) failed to download", failed);
+    }
 
-    Ok(())
+    Ok(downloaded)
 }
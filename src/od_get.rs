@@ -0,0 +1,378 @@
+use crate::download::{
+    auth::{Auth, Credentials},
+    crawl::{self, CrawlLimits, ListingFormat},
+    error::{OdGetError, Result},
+    events::{Event, Events},
+    fetch::{self, DownloadOptions, DownloadRecursiveStatus, LimitCounts},
+    report::Reporter,
+    scope::{Queue, RedirectPolicy, Scope},
+    session::{RateLimiter, Session},
+    stats::Selection,
+    storage::{LocalSink, StorageSink},
+    store::StatusMap,
+    types::{DiscoveredEntry, Node},
+};
+use futures::Stream;
+use regex::Regex;
+use reqwest::Url;
+use std::{path::PathBuf, sync::Arc};
+
+/**
+Crawls (and downloads) a single open directory.
+
+Made by `OdGet::builder()`. The crawl can be kept (e.g. in a `StateStore`)
+and downloaded later (or several times, skipping the files already done).
+*/
+#[derive(Debug, Clone)]
+pub struct OdGet {
+    session: Session,
+    options: DownloadOptions,
+    crawl_limits: CrawlLimits,
+}
+
+impl OdGet {
+    pub fn builder() -> OdGetBuilder {
+        OdGetBuilder::default()
+    }
+
+    /// The URL of the root directory
+    pub fn url(&self) -> &Url {
+        &self.options.url
+    }
+
+    /// The session issuing the requests (with the scope, events & reporter of the run)
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn options(&self) -> &DownloadOptions {
+        &self.options
+    }
+
+    /// The events of the run (handlers can be added until the run starts)
+    pub fn events(&self) -> &Events {
+        &self.session.events
+    }
+
+    /// Crawls the root directory and all of its sub-directories (within the scope & limits)
+    pub async fn crawl(&self) -> Result<Node> {
+        self.session.scope.reset(Queue::Crawl);
+
+        let (mut root, hash) = crawl::get_root_dir(&self.options.url, &self.session).await?;
+
+        match &mut root {
+            Node::CrawledDir(_, children) => {
+                crawl::expand_node(children, hash, &self.session, &self.crawl_limits).await?
+            }
+            _ => return Err(OdGetError::State("Cannot expand root node".to_owned())),
+        }
+
+        Ok(root)
+    }
+
+    /**
+    Crawls like `crawl`, but yields the files & directories as they are discovered
+    (with their paths relative to the root directory) instead of building a tree.
+    */
+    pub fn crawl_stream(&self) -> impl Stream<Item = Result<DiscoveredEntry>> + Send {
+        crawl::stream(
+            self.options.url.clone(),
+            self.session.clone(),
+            self.crawl_limits,
+        )
+    }
+
+    /**
+    Downloads the selected files of a crawl.
+
+    Files which are done according to the status map are skipped, and so are the
+    files which failed before (see `StatusMap::retry_failed`). Files failing to
+    download don't stop the download, they are marked as failed instead.
+    The storage is completed afterwards (e.g. an archive can't take more files).
+
+    Returns the number of files downloaded.
+    */
+    pub async fn download(&self, root: &Node, statuses: &mut StatusMap) -> Result<usize> {
+        let initially_done = statuses.done();
+
+        // Every run downloads the files of its crawl once
+        self.session.scope.reset(Queue::Download);
+
+        // Announce the total amount of work
+        let files = root.files();
+        let total_bytes = files.iter().filter_map(|file| file.size_in_bytes()).sum();
+        self.session.reporter.files_found(files.len() as u64);
+        self.session.events.emit(Event::DownloadPlanned {
+            files: files.len() as u64,
+            bytes: total_bytes,
+        });
+
+        // The limits apply to the whole root (not to every directory)
+        let mut counters = LimitCounts::new();
+
+        // Download the directories depth first (the last one queued is next)
+        let mut queue = vec![(root, fetch::root_folder(&self.options.url))];
+        while let Some((node, folder)) = queue.pop() {
+            let res = fetch::download_recursive(
+                node,
+                &folder,
+                &self.options,
+                &self.session,
+                &mut counters,
+                statuses,
+            )
+            .await?;
+
+            if let DownloadRecursiveStatus::Do(to_do) = res {
+                queue.extend(to_do.into_iter().rev());
+            }
+        }
+
+        // Complete the storage (e.g. the end of an archive)
+        self.options.storage.finish().await?;
+
+        self.session.events.emit(Event::Finished);
+
+        Ok(statuses.done() - initially_done)
+    }
+}
+
+/**
+Configures an `OdGet` (only the root URL is required).
+
+By default, the files are written to the working directory, the crawl is restricted
+to the root directory (and its host), and off-scope redirects are refused.
+*/
+#[derive(Debug)]
+pub struct OdGetBuilder {
+    url: Option<String>,
+    destination: PathBuf,
+    storage: Option<Arc<dyn StorageSink>>,
+    selection: Selection,
+    limit_count: Option<u64>,
+    skip_count: Option<u64>,
+    recursion_limit: Option<u64>,
+    crawl_limits: CrawlLimits,
+    listing_format: ListingFormat,
+    client: Option<reqwest::Client>,
+    credentials: Option<Credentials>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    no_parent: bool,
+    allowed_hosts: Vec<String>,
+    redirect_policy: RedirectPolicy,
+    events: Events,
+    reporter: Option<Arc<Reporter>>,
+}
+
+impl Default for OdGetBuilder {
+    fn default() -> Self {
+        Self {
+            url: None,
+            destination: PathBuf::from("."),
+            storage: None,
+            selection: Selection::default(),
+            limit_count: None,
+            skip_count: None,
+            recursion_limit: None,
+            crawl_limits: CrawlLimits::default(),
+            listing_format: ListingFormat::default(),
+            client: None,
+            credentials: None,
+            rate_limiter: None,
+            no_parent: true,
+            allowed_hosts: vec![],
+            redirect_policy: RedirectPolicy::Refuse,
+            events: Events::new(),
+            reporter: None,
+        }
+    }
+}
+
+impl OdGetBuilder {
+    /// The URL of the root directory (credentials in the URL are used & removed)
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    /// The directory to which to write the downloaded files (unless a storage is set)
+    pub fn destination(mut self, destination: impl Into<PathBuf>) -> Self {
+        self.destination = destination.into();
+        self
+    }
+
+    /// Stores the files somewhere else than in a local directory (e.g. in an archive)
+    pub fn storage(mut self, storage: Arc<dyn StorageSink>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Skips the files with matching names
+    pub fn file_filter(mut self, regex: Regex) -> Self {
+        self.selection.file_filter = Some(regex);
+        self
+    }
+
+    /// Only downloads the files with matching names
+    pub fn file_matcher(mut self, regex: Regex) -> Self {
+        self.selection.file_matcher = Some(regex);
+        self
+    }
+
+    /// Skips the directories with matching names
+    pub fn path_filter(mut self, regex: Regex) -> Self {
+        self.selection.path_filter = Some(regex);
+        self
+    }
+
+    /// Only downloads from the directories with matching names
+    pub fn path_matcher(mut self, regex: Regex) -> Self {
+        self.selection.path_matcher = Some(regex);
+        self
+    }
+
+    /// Sets all filters & matchers at once
+    pub fn selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// The maximum number of files to download
+    pub fn limit(mut self, files: u64) -> Self {
+        self.limit_count = Some(files);
+        self
+    }
+
+    /// The number of files to skip (before downloading any)
+    pub fn skip(mut self, files: u64) -> Self {
+        self.skip_count = Some(files);
+        self
+    }
+
+    /// The maximum number of directories to download from
+    pub fn recursion_limit(mut self, directories: u64) -> Self {
+        self.recursion_limit = Some(directories);
+        self
+    }
+
+    /// The limits protecting the crawl against pathological servers
+    pub fn crawl_limits(mut self, limits: CrawlLimits) -> Self {
+        self.crawl_limits = limits;
+        self
+    }
+
+    /// How to request the listings of HTTP(S) directories (HTML pages by default)
+    pub fn listing_format(mut self, format: ListingFormat) -> Self {
+        self.listing_format = format;
+        self
+    }
+
+    /**
+    The HTTP client issuing the requests (e.g. to share it between several roots).
+
+    It should be built with `reqwest::redirect::Policy::none()`,
+    so redirects can be checked against the scope.
+    */
+    pub fn client(mut self, client: reqwest::Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// The credentials (only ever sent to the host of the root URL)
+    pub fn credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+
+    /// Spaces out the requests (the limiter can be shared between several roots)
+    pub fn rate_limiter(mut self, rate_limiter: Arc<RateLimiter>) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Whether to stay below the root directory (`true` by default)
+    pub fn no_parent(mut self, no_parent: bool) -> Self {
+        self.no_parent = no_parent;
+        self
+    }
+
+    /// Also crawls & downloads from these hosts
+    pub fn allowed_hosts(mut self, hosts: Vec<String>) -> Self {
+        self.allowed_hosts = hosts;
+        self
+    }
+
+    /// What to do with redirects leaving the scope
+    pub fn redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self
+    }
+
+    /// Calls the handler for every event of the run (see `OdGet::events` for a channel)
+    pub fn on_event(self, handler: impl Fn(&Event) + Send + Sync + 'static) -> Self {
+        self.events.on(handler);
+        self
+    }
+
+    /// Collects the statistics of the run (the reporter can be shared between several roots)
+    pub fn reporter(mut self, reporter: Arc<Reporter>) -> Self {
+        self.reporter = Some(reporter);
+        self
+    }
+
+    pub fn build(self) -> Result<OdGet> {
+        let url = self
+            .url
+            .ok_or_else(|| OdGetError::Config("No root URL specified".to_owned()))?;
+        let mut url = Url::parse(&url)?;
+
+        // Never keep credentials in the URL (it gets printed & persisted)
+        let from_url = Credentials::take_from_url(&mut url);
+        let auth = match self.credentials.or(from_url) {
+            Some(credentials) => Some(Auth::new(&url, credentials)?),
+            None => None,
+        };
+
+        // Redirects are followed by the session (to check them against the scope)
+        let client = match self.client {
+            Some(client) => client,
+            None => reqwest::Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .build()?,
+        };
+
+        // Restrict the crawl to the root directory (and the allowed hosts)
+        let scope = Scope::new(
+            &url,
+            self.no_parent,
+            self.allowed_hosts,
+            self.redirect_policy,
+        );
+
+        let mut session = Session::new(client, scope, auth, self.rate_limiter);
+        session.events = self.events;
+        session.listing_format = self.listing_format;
+        if let Some(reporter) = self.reporter {
+            session.reporter = reporter;
+        }
+
+        // Write to the destination directory by default
+        let destination = self.destination;
+        let storage = self
+            .storage
+            .unwrap_or_else(|| Arc::new(LocalSink::new(destination)));
+
+        Ok(OdGet {
+            session,
+            options: DownloadOptions {
+                url,
+                storage,
+                selection: self.selection,
+                limit_count: self.limit_count,
+                skip_count: self.skip_count,
+                recursion_limit: self.recursion_limit,
+            },
+            crawl_limits: self.crawl_limits,
+        })
+    }
+}
@@ -0,0 +1,340 @@
+use crate::logging;
+use indicatif::{HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+use od_get::download::events::Event;
+use std::{
+    collections::HashMap,
+    io::{self, IsTerminal},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// How often the plain-text progress gets printed
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Displays the progress of a run (driven by its events)
+trait ProgressDisplay: Send {
+    fn handle(&mut self, event: &Event);
+}
+
+/// Makes the progress display fitting the terminal (or a plain-text one if stdout is no TTY)
+pub fn make_progress() -> impl Fn(&Event) + Send + Sync {
+    let display: Box<dyn ProgressDisplay> = if io::stdout().is_terminal() {
+        Box::new(BarProgress::new())
+    } else {
+        Box::new(PlainProgress::new())
+    };
+    let display = Mutex::new(display);
+
+    move |event| {
+        display
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .handle(event)
+    }
+}
+
+/// Draws progress bars (one for the whole run and one per active download)
+struct BarProgress {
+    bars: MultiProgress,
+    overall: ProgressBar,
+    files_done: u64,
+    files_total: u64,
+
+    /// The active downloads (by URL)
+    downloads: HashMap<String, BarDownload>,
+}
+
+/// The progress bar of a single download
+struct BarDownload {
+    bar: ProgressBar,
+    expected: Option<u64>,
+    written: u64,
+}
+
+impl BarProgress {
+    fn new() -> Self {
+        let bars = MultiProgress::new();
+
+        let overall = bars.add(ProgressBar::new(0));
+        overall.set_style(
+            ProgressStyle::with_template(
+                "{msg:>24} [{wide_bar}] {binary_bytes}/{binary_total_bytes} ({eta})",
+            )
+            .expect("Invalid progress template")
+            .progress_chars("=> "),
+        );
+        overall.set_message("Crawling");
+
+        // Keep the log lines above the bars
+        logging::attach_bars(&bars);
+
+        Self {
+            bars,
+            overall,
+            files_done: 0,
+            files_total: 0,
+            downloads: HashMap::new(),
+        }
+    }
+
+    /// Counts a file as done (downloaded, failed or skipped)
+    fn count_file(&mut self) {
+        self.files_done += 1;
+        self.overall
+            .set_message(format!("{}/{} files", self.files_done, self.files_total));
+    }
+
+    fn download_started(&mut self, url: &str, name: &str, bytes: Option<u64>) {
+        let bar = self
+            .bars
+            .insert_from_back(0, ProgressBar::new(bytes.unwrap_or_default()));
+        bar.set_style(
+            ProgressStyle::with_template(
+                "{msg:>24} [{wide_bar}] {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} ({eta})",
+            )
+            .expect("Invalid progress template")
+            .progress_chars("=> "),
+        );
+        bar.set_message(truncate(name, 24));
+
+        self.downloads.insert(
+            url.to_owned(),
+            BarDownload {
+                bar,
+                expected: bytes,
+                written: 0,
+            },
+        );
+    }
+
+    fn advance(&mut self, url: &str, bytes: u64) {
+        if let Some(download) = self.downloads.get_mut(url) {
+            download.written += bytes;
+            download.bar.inc(bytes);
+
+            // Don't let the overall progress exceed the estimate of this file
+            let estimate = download.expected.unwrap_or(download.written);
+            if download.written <= estimate {
+                self.overall.inc(bytes);
+            }
+        }
+    }
+
+    fn download_finished(&mut self, url: &str) {
+        if let Some(download) = self.downloads.remove(url) {
+            // Make up for the difference between the estimated and the actual size
+            if let Some(expected) = download.expected {
+                self.overall.inc(expected.saturating_sub(download.written));
+            }
+
+            download.bar.finish_and_clear();
+        }
+
+        self.count_file();
+    }
+
+    fn download_failed(&mut self, url: &str) {
+        // Remove the bars of failed downloads too
+        if let Some(download) = self.downloads.remove(url) {
+            download.bar.abandon();
+        }
+
+        self.count_file();
+    }
+
+    fn finish(&mut self) {
+        // Replace the bars with a summary line
+        self.overall.finish_and_clear();
+        logging::detach_bars();
+
+        info!(
+            "{}/{} files done ({})",
+            self.files_done,
+            self.files_total,
+            HumanBytes(self.overall.position())
+        );
+    }
+}
+
+impl ProgressDisplay for BarProgress {
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::DownloadPlanned { files, bytes } => {
+                self.files_done = 0;
+                self.files_total = *files;
+                self.overall.set_length(*bytes);
+                self.overall.set_message(format!("0/{} files", files));
+            }
+            Event::Skipped { bytes, .. } => {
+                self.overall.inc(bytes.unwrap_or_default());
+                self.count_file();
+            }
+            Event::DownloadStarted { url, name, bytes } => self.download_started(url, name, *bytes),
+            Event::DownloadProgress { url, bytes } => self.advance(url, *bytes),
+            Event::DownloadFinished { url, .. } => self.download_finished(url),
+            Event::DownloadFailed { url, .. } => self.download_failed(url),
+            Event::Finished => self.finish(),
+            _ => {}
+        }
+    }
+}
+
+/// The active download of the plain-text progress
+struct PlainDownload {
+    url: String,
+    name: String,
+    expected: Option<u64>,
+    written: u64,
+    started: Instant,
+}
+
+/// Prints the progress as a line of text every few seconds (for logs & pipes)
+struct PlainProgress {
+    files_done: u64,
+    files_total: u64,
+    bytes_done: u64,
+    bytes_total: u64,
+    started: Instant,
+    last_report: Instant,
+    current: Option<PlainDownload>,
+}
+
+impl PlainProgress {
+    fn new() -> Self {
+        let now = Instant::now();
+
+        Self {
+            files_done: 0,
+            files_total: 0,
+            bytes_done: 0,
+            bytes_total: 0,
+            started: now,
+            last_report: now,
+            current: None,
+        }
+    }
+
+    /// Prints the progress (if the last report is old enough, or when forced)
+    fn report(&mut self, force: bool) {
+        if !force && self.last_report.elapsed() < PLAIN_INTERVAL {
+            return;
+        }
+
+        self.last_report = Instant::now();
+
+        let mut line = format!(
+            "[progress] {}/{} files, {}/{}",
+            self.files_done,
+            self.files_total,
+            HumanBytes(self.bytes_done),
+            HumanBytes(self.bytes_total)
+        );
+
+        if let Some(eta) = eta(self.bytes_done, self.bytes_total, self.started) {
+            line.push_str(&format!(" (ETA {})", HumanDuration(eta)));
+        }
+
+        if let Some(current) = &self.current {
+            let rate = current.written as f64 / current.started.elapsed().as_secs_f64().max(0.001);
+            line.push_str(&format!(
+                ", {}: {}/{} at {}/s",
+                current.name,
+                HumanBytes(current.written),
+                current
+                    .expected
+                    .map(|size| HumanBytes(size).to_string())
+                    .unwrap_or_else(|| "?".to_owned()),
+                HumanBytes(rate as u64)
+            ));
+        }
+
+        info!(event = "progress", files_done = self.files_done, bytes_done = self.bytes_done; "{}", line);
+    }
+
+    fn advance(&mut self, url: &str, bytes: u64) {
+        if let Some(current) = self.current.as_mut().filter(|current| current.url == url) {
+            current.written += bytes;
+
+            // Don't let the overall progress exceed the estimate of this file
+            if current.written <= current.expected.unwrap_or(current.written) {
+                self.bytes_done += bytes;
+            }
+        }
+
+        self.report(false);
+    }
+
+    fn download_finished(&mut self, url: &str) {
+        if let Some(current) = self.current.take() {
+            // Make up for the difference between the estimated and the actual size
+            if let (true, Some(expected)) = (current.url == url, current.expected) {
+                self.bytes_done += expected.saturating_sub(current.written);
+            }
+        }
+
+        self.files_done += 1;
+        self.report(false);
+    }
+}
+
+impl ProgressDisplay for PlainProgress {
+    fn handle(&mut self, event: &Event) {
+        match event {
+            Event::DownloadPlanned { files, bytes } => {
+                self.files_total = *files;
+                self.bytes_total = *bytes;
+                self.started = Instant::now();
+                self.report(true);
+            }
+            Event::Skipped { bytes, .. } => {
+                self.files_done += 1;
+                self.bytes_done += bytes.unwrap_or_default();
+                self.report(false);
+            }
+            Event::DownloadStarted { url, name, bytes } => {
+                self.current = Some(PlainDownload {
+                    url: url.clone(),
+                    name: name.clone(),
+                    expected: *bytes,
+                    written: 0,
+                    started: Instant::now(),
+                });
+            }
+            Event::DownloadProgress { url, bytes } => self.advance(url, *bytes),
+            Event::DownloadFinished { url, .. } => self.download_finished(url),
+            Event::DownloadFailed { .. } => {
+                self.current = None;
+                self.files_done += 1;
+                self.report(false);
+            }
+            Event::Finished => {
+                self.current = None;
+                self.report(true);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Estimates the remaining time from the average rate so far
+fn eta(done: u64, total: u64, started: Instant) -> Option<Duration> {
+    if done == 0 || done >= total {
+        return None;
+    }
+
+    let elapsed = started.elapsed().as_secs_f64();
+    Some(Duration::from_secs_f64(
+        elapsed / done as f64 * (total - done) as f64,
+    ))
+}
+
+/// Shortens a name to fit the width of the progress bar label
+fn truncate(name: &str, width: usize) -> String {
+    if name.chars().count() <= width {
+        name.to_owned()
+    } else {
+        let tail: String = name.chars().rev().take(width - 1).collect();
+        format!("…{}", tail.chars().rev().collect::<String>())
+    }
+}